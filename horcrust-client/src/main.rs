@@ -1,22 +1,72 @@
-use anyhow::Context;
+use anyhow::{bail, Context};
 use clap::{Parser, Subcommand};
 use env_logger::Env;
 use horcrust::horcrust_msg_response::Response;
 use horcrust::{
-    msg_put_share_request, msg_retrieve_secret_request, ConnectionHandler, HorcrustMsgError,
-    HorcrustMsgResponse, HorcrustSecret, HorcrustShare, HorcrustStoreKey, Result, SecretSharing,
-    TcpConnectionHandler,
+    default_modulus, msg_delete_share_request, msg_list_keys_request, msg_put_share_request,
+    msg_reshare_request, msg_retrieve_secret_request, validate_key, AdditiveSecretSharing, BigUint,
+    BytesSecretSharing, ErrorCode, HorcrustCommitmentsBytes, HorcrustMsgError, HorcrustMsgRequest,
+    HorcrustSecretBytes, HorcrustShareBytes, HorcrustStoreKey, KeyAcl, KeyInfo,
+    PedersenSecretSharing, Result, SecretSharing, ShamirSecretSharing, SharingScheme,
 };
-use log::{debug, info};
+use horcrust::{peer_id_from_hex, public_identity_to_hex, AllowedPeers, HandshakeConfig, Identity};
+use horcrust::{tls_certificate_id, tls_client_config, ConnectionPool, TlsConfig, Transport};
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How many times the shares are retrieved when servers return shares of different epochs.
+const RETRIEVE_ATTEMPTS: usize = 5;
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// The server that returned it, the index, the share and the commitments.
+type RetrievedShare = (String, usize, HorcrustShareBytes, HorcrustCommitmentsBytes);
 
 /// Create shares out of your secret and stores them to distributed stores. Allows you
 /// to safely recover your secret from the shares on a later moment.
 #[derive(Parser, Clone, Debug)]
 #[command(author, version, about, long_about = None)]
 struct CliArgs {
-    #[arg(short, long, required = true)]
+    #[arg(short, long)]
     /// a list of servers to store your secret. Please provide at least 2 servers.
     servers: Vec<String>,
+    /// the file with the identity key of this client. A new one is generated if it doesn't exist.
+    #[arg(
+        short,
+        long,
+        env = "HORCRUST_IDENTITY",
+        default_value = "horcrust-client.key"
+    )]
+    identity: PathBuf,
+    /// the file with the public identities of the servers, one hex encoded key per line.
+    /// Connections to servers not in this file are refused.
+    #[arg(short, long, env = "HORCRUST_ALLOWED_PEERS")]
+    allowed_peers: Option<PathBuf>,
+    /// use TLS instead of the identity handshake: the PEM file with the CA certificates trusted
+    /// to sign the servers' certificates.
+    #[arg(long, env = "HORCRUST_TLS_CA", requires_all = ["tls_cert", "tls_key"])]
+    tls_ca: Option<PathBuf>,
+    /// the PEM file with the certificate chain of this client, signed by a CA the servers trust.
+    /// The servers refuse TLS clients without one.
+    #[arg(long, env = "HORCRUST_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+    /// the PEM file with the private key of the client certificate.
+    #[arg(long, env = "HORCRUST_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    #[arg(short, long)]
+    /// use Shamir's secret sharing: any `threshold` servers are enough to recover the secret.
+    /// By default, all servers are needed.
+    threshold: Option<usize>,
+    #[arg(short, long)]
+    /// the modulus used to split the secret, it must be prime when using a threshold.
+    /// Defaults to 2^255 - 19.
+    modulus: Option<BigUint>,
+    #[arg(long, conflicts_with = "modulus")]
+    /// use Pedersen's verifiable secret sharing, so shares tampered by a server are detected on
+    /// retrieval. The threshold defaults to the number of servers.
+    verifiable: bool,
     #[command(subcommand)]
     subcommands: Command,
 }
@@ -25,96 +75,508 @@ struct CliArgs {
 enum Command {
     StoreSecret {
         key: HorcrustStoreKey,
-        secret: HorcrustSecret,
+        /// the secret to store. If neither this nor --file are provided, it is read from stdin.
+        secret: Option<String>,
+        #[arg(short, long, conflicts_with = "secret")]
+        /// read the secret from this file.
+        file: Option<PathBuf>,
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        /// have the servers refresh the shares once they are this many seconds old, instead of
+        /// using their own refresh interval.
+        refresh_interval: Option<u64>,
+        #[arg(long = "reader")]
+        /// the identity of another client allowed to retrieve the secret, see show-identity. Only
+        /// this client can by default.
+        readers: Vec<String>,
+        #[arg(long = "writer")]
+        /// the identity of another client allowed to replace or delete the secret.
+        writers: Vec<String>,
     },
     RetrieveSecret {
         key: HorcrustStoreKey,
+        #[arg(short, long)]
+        /// write the secret to this file instead of stdout.
+        output: Option<PathBuf>,
+    },
+    /// Moves a secret from the servers passed with --servers to a new set of servers, without
+    /// rebuilding it. The new threshold is the one passed with --threshold, by default all the
+    /// new servers are needed.
+    Reshare {
+        key: HorcrustStoreKey,
+        #[arg(short, long = "new-server", required = true)]
+        /// the servers that will store the secret. Please provide at least 2 servers.
+        new_servers: Vec<String>,
+    },
+    /// Lists the secrets stored on the servers, flagging the ones missing on some of them.
+    ListSecrets {
+        /// only list the secrets below this key, e.g. `prod/db` lists `prod/db/password`.
+        prefix: Option<String>,
     },
+    /// Deletes the secret from all the servers, and reports which ones confirmed it.
+    DeleteSecret { key: HorcrustStoreKey },
+    /// Prints the public identity of this client, to add it to the servers' allowed peers. With
+    /// --tls-cert, the fingerprint of the certificate, its identity with TLS.
+    ShowIdentity,
 }
 
-fn main() {
+fn main() -> Result<()> {
     // setup env_logger
     env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
 
     let mut cli = CliArgs::parse();
+    if let Command::ShowIdentity = cli.subcommands {
+        let identity = match &cli.tls_cert {
+            Some(cert) => tls_certificate_id(cert)?,
+            None => public_identity_to_hex(&Identity::load_or_generate(&cli.identity)?.public()),
+        };
+        println!("{identity}");
+        return Ok(());
+    }
     if cli.servers.len() < 2 {
         panic!("Please provide at least 2 servers");
     }
+    let transport = match (&cli.tls_ca, &cli.allowed_peers) {
+        (Some(ca), _) => {
+            // safe unwraps: required by --tls-ca.
+            let (cert, key) = (
+                cli.tls_cert.as_ref().unwrap(),
+                cli.tls_key.as_ref().unwrap(),
+            );
+            Transport::Tls(TlsConfig {
+                client: Some(tls_client_config(ca, cert, key)?),
+                server: None,
+            })
+        }
+        (None, Some(allowed_peers)) => {
+            let identity = Identity::load_or_generate(&cli.identity)?;
+            Transport::Handshake(HandshakeConfig::new(
+                identity,
+                AllowedPeers::load(allowed_peers)?,
+            ))
+        }
+        (None, None) => {
+            bail!("Please provide the allowed peers file with --allowed-peers, or the TLS CA with --tls-ca.")
+        }
+    };
+    // every server is contacted through a single connection, whatever the number of requests.
+    let mut connections = ConnectionPool::new(transport);
     // used to ease concurrency issues with the refresher thread.
     cli.servers.sort();
-    dbg!(&cli);
-    let additive_sharing = horcrust::AdditiveSecretSharing::default();
     let shares_len = cli.servers.len();
 
     match cli.subcommands {
-        Command::RetrieveSecret { key } => {
+        Command::RetrieveSecret { key, output } => {
             info!(
                 "Retrieving secret with key '{key}' from servers: {:?}",
                 cli.servers
             );
-            let shares = cli
-                .servers
-                .into_iter()
-                .map(|server| reterieve_secret(key, server).expect("Retrieve failed"))
-                .collect();
-            let secret = additive_sharing.combine(shares);
-            println!("Recovered secret: {}", secret);
+            // a refresh committed while the shares are retrieved gives shares of different
+            // epochs, that can't be combined: retrieving them again should get the same epoch.
+            let mut attempt = 1;
+            let (scheme, shares) = loop {
+                let (scheme, shares) = retrieve_shares(&key, &cli.servers, &mut connections)?;
+                let epochs: BTreeSet<u64> = shares.iter().map(|(epoch, _)| *epoch).collect();
+                if epochs.len() <= 1 || attempt == RETRIEVE_ATTEMPTS {
+                    break (scheme, same_epoch_shares(shares));
+                }
+                warn!("Servers returned shares of different refresh epochs {epochs:?}, retrying.");
+                attempt += 1;
+                std::thread::sleep(RETRY_DELAY);
+            };
+            let secret_sharing = BytesSecretSharing::from_scheme(&scheme)?;
+            let shares = if scheme.verifiable {
+                verify_shares(&secret_sharing, shares)
+            } else {
+                shares
+                    .into_iter()
+                    .map(|(_, index, share, _)| (index, share))
+                    .collect()
+            };
+            let threshold = secret_sharing.inner().threshold(shares_len);
+            if shares.len() < threshold {
+                bail!(
+                    "Retrieved {} valid shares, but {} are needed to recover the secret.",
+                    shares.len(),
+                    threshold
+                );
+            }
+            let secret = secret_sharing.combine(shares)?;
+            match output {
+                Some(path) => std::fs::write(&path, secret)
+                    .context(format!("failed to write the secret to {path:?}"))?,
+                None => std::io::stdout().write_all(&secret)?,
+            }
         }
-        Command::StoreSecret { key, secret } => {
+        Command::StoreSecret {
+            key,
+            secret,
+            file,
+            refresh_interval,
+            readers,
+            writers,
+        } => {
+            validate_key(&key)?;
+            for identity in readers.iter().chain(writers.iter()) {
+                peer_id_from_hex(identity).context("Invalid --reader or --writer")?;
+            }
+            let secret = read_secret(secret, file)?;
             info!(
-                "Storing secret {secret} with key '{key}' to servers: {:?}",
+                "Storing secret of {} bytes with key '{key}' to servers: {:?}",
+                secret.len(),
                 cli.servers
             );
-            let shares = additive_sharing.split(shares_len, secret);
+            let modulus = cli.modulus.unwrap_or_else(default_modulus);
+            if modulus <= BigUint::from(shares_len) {
+                bail!("The modulus should be bigger than the number of servers.");
+            }
+            let inner: Box<dyn SecretSharing> = match (cli.threshold, cli.verifiable) {
+                (Some(threshold), _) if threshold < 2 || threshold > shares_len => {
+                    bail!("The threshold should be between 2 and the number of servers ({shares_len}).");
+                }
+                (threshold, true) => {
+                    Box::new(PedersenSecretSharing::new(threshold.unwrap_or(shares_len)))
+                }
+                (Some(threshold), false) => {
+                    Box::new(ShamirSecretSharing::try_new(threshold, modulus)?)
+                }
+                (None, false) => Box::new(AdditiveSecretSharing::new(modulus)),
+            };
+            let scheme = inner.scheme();
+            let secret_sharing = BytesSecretSharing::new(inner)?;
+            // the servers make the client storing the secret its owner.
+            let acl = KeyAcl {
+                owner: String::new(),
+                readers,
+                writers,
+            };
+            // fails before any share is sent if the secret can't be stored.
+            let (shares, commitments) =
+                secret_sharing.split_with_commitments(shares_len, &secret)?;
             shares
                 .into_iter()
                 .zip(cli.servers)
-                .for_each(|(share, server)| {
-                    put_share(key, share, server).expect("Store failed");
+                .enumerate()
+                .for_each(|(index, (share, server))| {
+                    let request = msg_put_share_request(
+                        key.clone(),
+                        index as u32,
+                        share,
+                        scheme.clone(),
+                        commitments.clone(),
+                        refresh_interval.unwrap_or(0),
+                        acl.clone(),
+                    );
+                    put_share(request, &server, &mut connections).expect("Store failed");
                 });
         }
+        Command::Reshare { key, new_servers } => {
+            if new_servers.len() < 2 {
+                bail!("Please provide at least 2 new servers.");
+            }
+            let threshold = cli.threshold.unwrap_or(0) as u32;
+            // any of the current servers can coordinate the reshare.
+            let mut reshared = false;
+            for server in cli.servers.iter() {
+                let request = msg_reshare_request(key.clone(), new_servers.clone(), threshold);
+                match send_request(server, request, &mut connections) {
+                    Ok(()) => {
+                        reshared = true;
+                        break;
+                    }
+                    Err(err) => warn!("Failed to reshare through server '{server}': {err:?}"),
+                }
+            }
+            if !reshared {
+                bail!("Failed to reshare key '{key}'.");
+            }
+            println!("Secret reshared to servers: {:?}", new_servers);
+        }
+        Command::ListSecrets { prefix } => {
+            let prefix = prefix.unwrap_or_default();
+            // what every server that answered knows about each key.
+            let mut keys: BTreeMap<HorcrustStoreKey, Vec<(String, KeyInfo)>> = BTreeMap::new();
+            let mut listed = vec![];
+            for server in cli.servers.iter() {
+                match list_keys(server, &prefix, &mut connections) {
+                    Ok(infos) => {
+                        for info in infos {
+                            keys.entry(info.key.clone())
+                                .or_default()
+                                .push((server.clone(), info));
+                        }
+                        listed.push(server.clone());
+                    }
+                    Err(err) => warn!("Failed to list the keys of server '{server}': {err:?}"),
+                }
+            }
+            if listed.is_empty() {
+                bail!("Failed to list the keys of any server.");
+            }
+            for (key, infos) in keys {
+                println!("{}", describe_key(&key, &infos, &listed));
+            }
+        }
+        Command::DeleteSecret { key } => {
+            let mut failed = vec![];
+            for server in cli.servers.iter() {
+                let request = msg_delete_share_request(key.clone());
+                match send_request(server, request, &mut connections) {
+                    Ok(()) => println!("Share deleted on server: {server}"),
+                    Err(err) => {
+                        warn!("Failed to delete share on server '{server}': {err:?}");
+                        failed.push(server.clone());
+                    }
+                }
+            }
+            if !failed.is_empty() {
+                bail!("Key '{key}' was not deleted on servers: {failed:?}");
+            }
+        }
+        // handled before connecting to any server.
+        Command::ShowIdentity => unreachable!(),
     }
-    //test_tls_rust(client_config);
+    Ok(())
 }
 
-// retrieves a secret from a single server.
-fn reterieve_secret(key: HorcrustStoreKey, server: String) -> Result<HorcrustSecret> {
-    let socket = std::net::TcpStream::connect(&server)?;
-    let mut handler = TcpConnectionHandler::new(socket)?;
-    let request = msg_retrieve_secret_request(key);
-    handler.send(request)?;
-    let received: HorcrustMsgResponse = handler.receive()?;
+fn read_secret(secret: Option<String>, file: Option<PathBuf>) -> Result<HorcrustSecretBytes> {
+    match (secret, file) {
+        (Some(secret), _) => Ok(secret.into_bytes()),
+        (None, Some(path)) => {
+            std::fs::read(&path).context(format!("failed to read the secret from {path:?}"))
+        }
+        (None, None) => {
+            let mut secret = vec![];
+            std::io::stdin().read_to_end(&mut secret)?;
+            Ok(secret)
+        }
+    }
+}
+
+/// Retrieves the shares of `key` from all the servers, each with its refresh epoch. Shares are
+/// collected together with the index stored by the server, so threshold schemes can recover the
+/// secret even if some of the servers are not available.
+fn retrieve_shares(
+    key: &str,
+    servers: &[String],
+    connections: &mut ConnectionPool,
+) -> Result<(SharingScheme, Vec<(u64, RetrievedShare)>)> {
+    let mut shares: Vec<(u64, RetrievedShare)> = vec![];
+    let mut scheme: Option<SharingScheme> = None;
+    for server in servers.iter() {
+        match reterieve_secret(key, server, connections) {
+            Ok((share, share_scheme, commitments, index, epoch)) => {
+                if scheme.get_or_insert(share_scheme.clone()) != &share_scheme {
+                    bail!("Server '{server}' returned a share of a different sharing scheme: {share_scheme:?}");
+                }
+                if shares.iter().any(|(_, (_, i, _, _))| *i == index) {
+                    warn!("Server '{server}' returned a share with a duplicated index {index}, ignoring it.");
+                    continue;
+                }
+                shares.push((epoch, (server.clone(), index, share, commitments)));
+            }
+            Err(err) => warn!("Failed to retrieve share from server '{server}': {err:?}"),
+        }
+    }
+    let Some(scheme) = scheme else {
+        bail!("Failed to retrieve any share for key '{key}'.");
+    };
+    Ok((scheme, shares))
+}
+
+/// Keeps the shares of the epoch returned by most servers, the latest one on ties, and warns
+/// about the servers that returned a different one.
+fn same_epoch_shares(shares: Vec<(u64, RetrievedShare)>) -> Vec<RetrievedShare> {
+    let count = |epoch: u64| shares.iter().filter(|(e, _)| *e == epoch).count();
+    // safe unwrap: there is at least one share.
+    let epoch = shares
+        .iter()
+        .map(|(epoch, _)| *epoch)
+        .max_by_key(|epoch| (count(*epoch), *epoch))
+        .unwrap();
+    shares
+        .into_iter()
+        .filter(|(share_epoch, (server, ..))| {
+            if *share_epoch != epoch {
+                warn!("Server '{server}' returned a share of refresh epoch {share_epoch} instead of {epoch}, ignoring it.");
+            }
+            *share_epoch == epoch
+        })
+        .map(|(_, share)| share)
+        .collect()
+}
+
+/// Keeps the shares that are valid against the commitments returned by most servers, and warns
+/// about the servers that returned different commitments or an invalid share.
+fn verify_shares(
+    secret_sharing: &BytesSecretSharing,
+    shares: Vec<RetrievedShare>,
+) -> Vec<(usize, HorcrustShareBytes)> {
+    // safe unwrap: there is at least one share.
+    let commitments = shares
+        .iter()
+        .map(|(_, _, _, c)| c)
+        .max_by_key(|c| shares.iter().filter(|(_, _, _, other)| &other == c).count())
+        .unwrap()
+        .clone();
+    shares
+        .into_iter()
+        .filter(|(server, index, share, share_commitments)| {
+            if share_commitments != &commitments {
+                warn!("Server '{server}' returned different commitments, ignoring its share.");
+                return false;
+            }
+            match secret_sharing.verify(*index, share, &commitments) {
+                Ok(true) => true,
+                Ok(false) => {
+                    warn!("Server '{server}' returned an invalid share, ignoring it.");
+                    false
+                }
+                Err(err) => {
+                    warn!("Failed to verify the share of server '{server}': {err:?}");
+                    false
+                }
+            }
+        })
+        .map(|(_, index, share, _)| (index, share))
+        .collect()
+}
+
+// retrieves a share, the scheme it was created with, its commitments, its index and its refresh
+// epoch, from a single server.
+fn reterieve_secret(
+    key: &str,
+    server: &str,
+    connections: &mut ConnectionPool,
+) -> Result<(
+    HorcrustShareBytes,
+    SharingScheme,
+    HorcrustCommitmentsBytes,
+    usize,
+    u64,
+)> {
+    let request = msg_retrieve_secret_request(key.to_string());
+    let received = connections.request(server, request)?;
     match received.response.unwrap() {
+        Response::Error(err) if err.code() == ErrorCode::Unauthorized => {
+            bail!("Server '{server}' refused access: {}", err.error_string)
+        }
         Response::Error(HorcrustMsgError {
             error,
             error_string,
+            ..
         }) => {
-            panic!(
+            bail!(
                 "Error response from server '{}' : {} {}",
-                server, error, error_string
+                server,
+                error,
+                error_string
             );
         }
         Response::ShareResponse(share) => {
-            println!("Share received: {}", share.share);
-            Ok(share.share as HorcrustSecret)
+            debug!(
+                "Share of {} bytes of refresh epoch {} received.",
+                share.share.len(),
+                share.epoch
+            );
+            Ok((
+                share.share,
+                share.scheme.unwrap_or_default(),
+                share.commitments,
+                share.index as usize,
+                share.epoch,
+            ))
         }
+        resp => bail!("Unexpected response from server '{server}': {resp:?}"),
     }
 }
 
-fn put_share(key: HorcrustStoreKey, share: HorcrustShare, server: String) -> Result<()> {
-    let req = msg_put_share_request(key, share);
-    let socket = std::net::TcpStream::connect(&server)?;
-    let mut handler = TcpConnectionHandler::new(socket)?;
-    handler.send(req)?;
+// lists all the keys of a server below `prefix`, a page at a time.
+fn list_keys(server: &str, prefix: &str, connections: &mut ConnectionPool) -> Result<Vec<KeyInfo>> {
+    let mut keys = vec![];
+    let mut start = String::new();
+    loop {
+        let request = msg_list_keys_request(prefix.to_string(), start, 0);
+        let received = connections.request(server, request)?;
+        match received.response.unwrap() {
+            Response::ListKeys(page) => {
+                keys.extend(page.keys);
+                if !page.more {
+                    return Ok(keys);
+                }
+                start = page.next;
+            }
+            Response::Error(err) if err.code() == ErrorCode::Unauthorized => {
+                bail!("Server '{server}' refused access: {}", err.error_string)
+            }
+            Response::Error(HorcrustMsgError { error_string, .. }) => {
+                bail!("Error response from server '{server}': {error_string}")
+            }
+            resp => bail!("Unexpected response from server '{server}': {resp:?}"),
+        }
+    }
+}
+
+/// One line about a key, flagging the servers that don't have it or that are at a different
+/// refresh epoch.
+fn describe_key(key: &str, infos: &[(String, KeyInfo)], servers: &[String]) -> String {
+    // safe unwrap: keys are listed by at least one server.
+    let (_, info) = infos.first().unwrap();
+    let scheme = info.scheme.clone().unwrap_or_default();
+    let mut line = match (scheme.threshold, scheme.verifiable) {
+        (0, _) => format!("{key}: additive"),
+        (threshold, false) => format!("{key}: Shamir, threshold {threshold}"),
+        (threshold, true) => format!("{key}: Pedersen, threshold {threshold}"),
+    };
+    line += &format!(", stored {}", age(info.stored_at));
+    match infos.iter().map(|(_, info)| info.refreshed_at).max() {
+        Some(refreshed_at) if refreshed_at > 0 => {
+            line += &format!(", refreshed {}", age(refreshed_at))
+        }
+        _ => line += ", never refreshed",
+    }
+    if info.refresh_interval_secs > 0 {
+        line += &format!(" (every {}s)", info.refresh_interval_secs);
+    }
+    line += &format!(", on {} of {} servers", infos.len(), servers.len());
+    let missing: Vec<&String> = servers
+        .iter()
+        .filter(|server| !infos.iter().any(|(s, _)| s == *server))
+        .collect();
+    if !missing.is_empty() {
+        line += &format!(", MISSING on {missing:?}");
+    }
+    let epochs: BTreeSet<u64> = infos.iter().map(|(_, info)| info.epoch).collect();
+    if epochs.len() > 1 {
+        line += &format!(", DIFFERENT refresh epochs {epochs:?}");
+    }
+    line
+}
+
+fn age(unix_time: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    format!("{}s ago", now.saturating_sub(unix_time))
+}
+
+fn put_share(
+    req: HorcrustMsgRequest,
+    server: &str,
+    connections: &mut ConnectionPool,
+) -> Result<()> {
     debug!("fetching server response: ");
-    let received: HorcrustMsgResponse = handler
-        .receive()
-        .context(format!("failed handler receive with server: {server}"))?;
+    let received = connections
+        .request(server, req)
+        .context(format!("failed request to server: {server}"))?;
 
     match received.response.unwrap() {
+        Response::Error(err) if err.code() == ErrorCode::Unauthorized => {
+            panic!("Server '{server}' refused access: {}", err.error_string);
+        }
         Response::Error(HorcrustMsgError {
             error,
             error_string,
+            ..
         }) => {
             if error {
                 panic!(
@@ -131,3 +593,22 @@ fn put_share(key: HorcrustStoreKey, share: HorcrustShare, server: String) -> Res
     }
     Ok(())
 }
+
+// sends a request to a server, and fails unless it replies with a success response.
+fn send_request(
+    server: &str,
+    request: HorcrustMsgRequest,
+    connections: &mut ConnectionPool,
+) -> Result<()> {
+    let received = connections.request(server, request)?;
+    match received.response.unwrap() {
+        Response::Error(HorcrustMsgError { error: false, .. }) => Ok(()),
+        Response::Error(err) if err.code() == ErrorCode::Unauthorized => {
+            bail!("Server '{server}' refused access: {}", err.error_string)
+        }
+        Response::Error(HorcrustMsgError { error_string, .. }) => {
+            bail!("Error response from server '{server}': {error_string}")
+        }
+        resp => bail!("Unexpected response from server '{server}': {resp:?}"),
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::Context;
+use horcrust::{peer_id_from_hex, KeyAcl, Result};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// What a request does with a key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    /// retrieve the share.
+    Read,
+    /// replace or delete it.
+    Write,
+    /// reshare it: only the owner can move the key to other servers.
+    Owner,
+}
+
+/// True if `peer`, a peer id, can access a key with the ACL. Keys without an
+/// owner can be accessed by any peer. The owner can do everything.
+pub fn can_access(acl: &KeyAcl, peer: &str, access: Access) -> bool {
+    if acl.owner.is_empty() {
+        return true;
+    }
+    let allowed: &[String] = match access {
+        Access::Read => &acl.readers,
+        Access::Write => &acl.writers,
+        Access::Owner => &[],
+    };
+    acl.owner == peer || allowed.iter().any(|identity| identity == peer)
+}
+
+/// The ACL of a key stored by `owner`, with the readers and writers it asked for. The peer ids are
+/// checked, and encoded like the ones of the connections, so they can be compared.
+pub fn new_acl(owner: &str, requested: KeyAcl) -> Result<KeyAcl> {
+    let normalize = |identities: Vec<String>| -> Result<Vec<String>> {
+        identities
+            .iter()
+            .map(|identity| {
+                peer_id_from_hex(identity)
+                    .context(format!("Invalid identity '{identity}' in the ACL"))
+            })
+            .collect()
+    };
+    let readers = normalize(requested.readers)?;
+    let writers = normalize(requested.writers)?;
+    Ok(KeyAcl {
+        owner: owner.to_string(),
+        readers,
+        writers,
+    })
+}
+
+/// The identities of the servers: only they can send the requests the servers send each other,
+/// to refresh or reshare the shares. The clients are allowed peers too, or have a certificate
+/// signed by the same CA, so the transport alone doesn't tell them apart.
+#[derive(Clone, Debug, Default)]
+pub struct ServerIdentities {
+    /// the peer ids of the servers.
+    identities: HashSet<String>,
+}
+impl ServerIdentities {
+    pub fn new<S: AsRef<str>>(identities: impl IntoIterator<Item = S>) -> Result<Self> {
+        let identities = identities
+            .into_iter()
+            .map(|identity| peer_id_from_hex(identity.as_ref()))
+            .collect::<Result<_>>()?;
+        Ok(Self { identities })
+    }
+    /// Same format as the allowed peers, one peer id per line: the public identities of the
+    /// servers, or the fingerprints of their certificates with TLS. Empty lines and lines
+    /// starting with # are skipped.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).context(format!(
+            "failed to read the server identities from {path:?}"
+        ))?;
+        Self::new(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#')),
+        )
+    }
+    /// True if `peer`, a peer id, is one of the servers.
+    pub fn contains(&self, peer: &str) -> bool {
+        self.identities.contains(peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use horcrust::{public_identity_to_hex, Identity};
+
+    #[test]
+    fn test_acl() -> Result<()> {
+        let [owner, reader, writer, other] =
+            [(); 4].map(|_| public_identity_to_hex(&Identity::generate().public()));
+        let acl = new_acl(
+            &owner,
+            KeyAcl {
+                owner: other.clone(),
+                readers: vec![reader.to_uppercase()],
+                writers: vec![writer.clone()],
+            },
+        )?;
+        // the owner is the client storing the key, identities are compared in lowercase.
+        assert_eq!(acl.owner, owner);
+        assert_eq!(acl.readers, vec![reader.clone()]);
+        for access in [Access::Read, Access::Write] {
+            assert!(can_access(&acl, &owner, access));
+            assert!(!can_access(&acl, &other, access));
+        }
+        assert!(can_access(&acl, &reader, Access::Read));
+        assert!(!can_access(&acl, &reader, Access::Write));
+        assert!(can_access(&acl, &writer, Access::Write));
+        assert!(!can_access(&acl, &writer, Access::Read));
+
+        // keys without an owner are open.
+        assert!(can_access(&KeyAcl::default(), &other, Access::Write));
+        let invalid = KeyAcl {
+            writers: vec!["00".into()],
+            ..Default::default()
+        };
+        assert!(new_acl(&owner, invalid).is_err());
+        Ok(())
+    }
+}
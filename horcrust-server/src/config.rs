@@ -0,0 +1,109 @@
+use crate::{RefreshPolicy, Tenant};
+use anyhow::{ensure, Context};
+use horcrust::Result;
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+
+/// The server configuration file, in TOML. Flags passed on the command line take precedence.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub refresh: RefreshConfig,
+    /// the `[[tenants]]` sections, see Tenants.
+    pub tenants: Vec<Tenant>,
+}
+impl ServerConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .context(format!("failed to read the configuration from {path:?}"))?;
+        toml::from_str(&content).context(format!("invalid configuration in {path:?}"))
+    }
+}
+
+/// The `[refresh]` section, see RefreshPolicy. Missing values get the default ones.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RefreshConfig {
+    pub interval_secs: Option<u64>,
+    pub jitter_ms: Option<u64>,
+    pub batch_size: Option<usize>,
+    pub enabled: Option<bool>,
+}
+impl RefreshConfig {
+    /// The values set in `overrides` replace the ones in self.
+    pub fn merge(self, overrides: RefreshConfig) -> Self {
+        Self {
+            interval_secs: overrides.interval_secs.or(self.interval_secs),
+            jitter_ms: overrides.jitter_ms.or(self.jitter_ms),
+            batch_size: overrides.batch_size.or(self.batch_size),
+            enabled: overrides.enabled.or(self.enabled),
+        }
+    }
+    pub fn policy(&self) -> Result<RefreshPolicy> {
+        let default = RefreshPolicy::default();
+        ensure!(
+            self.interval_secs != Some(0),
+            "The refresh interval must be at least 1 second."
+        );
+        ensure!(
+            self.batch_size != Some(0),
+            "The refresh batch size must be at least 1."
+        );
+        Ok(RefreshPolicy {
+            interval: self
+                .interval_secs
+                .map_or(default.interval, Duration::from_secs),
+            jitter: self.jitter_ms.map_or(default.jitter, Duration::from_millis),
+            batch_size: self.batch_size.or(default.batch_size),
+            enabled: self.enabled.unwrap_or(default.enabled),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refresh_config() -> Result<()> {
+        let config: ServerConfig = toml::from_str(
+            "[refresh]
+            interval_secs = 3600
+            batch_size = 10",
+        )?;
+        let overrides = RefreshConfig {
+            batch_size: Some(20),
+            enabled: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.refresh.merge(overrides).policy()?,
+            RefreshPolicy {
+                interval: Duration::from_secs(3600),
+                jitter: RefreshPolicy::default().jitter,
+                batch_size: Some(20),
+                enabled: false,
+            }
+        );
+        assert_eq!(
+            ServerConfig::default().refresh.policy()?,
+            RefreshPolicy::default()
+        );
+        assert!(toml::from_str::<ServerConfig>("[refresh]\ninterval = 1").is_err());
+        let config: ServerConfig = toml::from_str(
+            "[[tenants]]
+            name = \"payments\"
+            clients = []
+            max_keys = 10",
+        )?;
+        assert_eq!(config.tenants[0].max_keys, Some(10));
+        assert_eq!(config.tenants[0].max_share_size, None);
+        let zero_interval = RefreshConfig {
+            interval_secs: Some(0),
+            ..Default::default()
+        };
+        assert!(zero_interval.policy().is_err());
+        Ok(())
+    }
+}
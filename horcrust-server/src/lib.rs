@@ -1,2 +1,20 @@
+mod acl;
+mod config;
+mod master_key;
+mod refresher;
+mod reshare;
+mod server;
+mod share_store;
 mod shares_db;
-pub use shares_db::SharesDatabase;
+mod tenants;
+pub use acl::ServerIdentities;
+pub use config::{RefreshConfig, ServerConfig};
+pub use master_key::{MasterKey, MasterKeySource};
+pub use refresher::{refresher, spawn_refresher, RefreshPolicy};
+pub use reshare::{reshare, reshare_contribution, reshare_split, reshare_stage};
+pub use server::run;
+pub use share_store::{
+    CommittedRound, MemoryStore, RedbStore, ShareStore, StagedRound, StagedShare,
+};
+pub use shares_db::{lock_db, SharesDatabase, StoredShare};
+pub use tenants::{Tenant, Tenants};
@@ -1,149 +1,267 @@
-use std::net::TcpListener;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use env_logger::Env;
 use log::{debug, info};
-use rand::random;
+use tokio::net::TcpListener;
 
-use horcrust::{
-    horcrust_msg_request, horcrust_msg_response, msg_error_response, msg_refresh_share_request,
-    msg_share_response, msg_success_response, AdditiveSecretSharing, ConnectionHandler,
-    HorcrustMsgError, HorcrustMsgRequest, HorcrustMsgResponse, Result, SecretSharing,
-    TcpConnectionHandler,
+use horcrust::{public_identity_to_hex, AllowedPeers, HandshakeConfig, Identity};
+use horcrust::{tls_certificate_id, tls_client_config, tls_server_config, TlsConfig, Transport};
+use horcrust_server::{
+    run, spawn_refresher, MasterKeySource, RedbStore, RefreshConfig, ServerConfig,
+    ServerIdentities, SharesDatabase, Tenants,
 };
-use horcrust_server::SharesDatabase;
 
 /// Create shares out of your secret and stores them to distributed services. Allows you
 /// to safely recover your secret from the shares on a later moment.
 #[derive(Parser, Clone, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct CliArgs {
-    #[arg(short, long, required = true)]
+    #[arg(short, long, required_unless_present = "show_identity")]
     /// a list of servers to store your secret. Please provide at least 2 servers.
     servers: Vec<String>,
     /// a port to bind to
     #[arg(short, long, default_value = "8080")]
     port: u16,
+    /// the file with the identity key of this server. A new one is generated if it doesn't exist.
+    #[arg(
+        short,
+        long,
+        env = "HORCRUST_IDENTITY",
+        default_value = "horcrust-server.key"
+    )]
+    identity: PathBuf,
+    /// the file with the public identities of the servers and clients allowed to connect,
+    /// one hex encoded key per line. Include this server's identity as well.
+    #[arg(
+        short,
+        long,
+        env = "HORCRUST_ALLOWED_PEERS",
+        required_unless_present_any = ["show_identity", "tls_cert"]
+    )]
+    allowed_peers: Option<PathBuf>,
+    /// the file with the identities of the servers, this one included, in the same format as the
+    /// allowed peers. With TLS, the fingerprints of their certificates. Only they can take part in
+    /// refreshes and reshares.
+    #[arg(
+        long,
+        env = "HORCRUST_SERVER_IDENTITIES",
+        required_unless_present = "show_identity"
+    )]
+    server_identities: Option<PathBuf>,
+    /// print the public identity of this server, to add it to the allowed peers, and exit. With
+    /// --tls-cert, the fingerprint of the certificate, its identity with TLS.
+    #[arg(long)]
+    show_identity: bool,
+    /// use TLS instead of the identity handshake: the PEM file with this server's certificate
+    /// chain. It must be valid for the address the other servers and the clients use, and the
+    /// server also presents it when connecting to the other servers.
+    #[arg(long, env = "HORCRUST_TLS_CERT", requires_all = ["tls_key", "tls_ca"])]
+    tls_cert: Option<PathBuf>,
+    /// the PEM file with the private key of the TLS certificate.
+    #[arg(long, env = "HORCRUST_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// the PEM file with the CA certificates trusted to sign the certificates of the other servers
+    /// and of the clients.
+    #[arg(long, env = "HORCRUST_TLS_CA", requires = "tls_cert")]
+    tls_ca: Option<PathBuf>,
+    /// the database file where the shares are kept. It is created if it doesn't exist.
+    #[arg(
+        short,
+        long,
+        env = "HORCRUST_DATABASE",
+        default_value = "horcrust-server.db"
+    )]
+    database: PathBuf,
+    #[command(flatten)]
+    master_key: MasterKeyArgs,
+    /// the TOML configuration file. The flags passed on the command line take precedence.
+    #[arg(short, long, env = "HORCRUST_CONFIG")]
+    config: Option<PathBuf>,
+    /// refresh the shares once they are this many seconds old. Shares stored with their own
+    /// refresh interval use that one instead. Defaults to 5.
+    #[arg(long)]
+    refresh_interval: Option<u64>,
+    /// wait up to this many random milliseconds before every refresh round. Defaults to 1000.
+    #[arg(long)]
+    refresh_jitter: Option<u64>,
+    /// refresh at most this many keys per round, the oldest first. All of them by default.
+    #[arg(long)]
+    refresh_batch_size: Option<usize>,
+    /// don't start refreshes from this server. It still takes part in the refreshes started by
+    /// the other servers.
+    #[arg(long)]
+    no_refresh: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() {
-    // setup env_logger
-    env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
+/// The shares are encrypted on disk if a master key is passed.
+#[derive(Args, Clone)]
+#[group(multiple = false)]
+struct MasterKeyArgs {
+    /// the file with the hex encoded master key. A new one is generated if it doesn't exist.
+    #[arg(long, env = "HORCRUST_MASTER_KEY_FILE")]
+    master_key_file: Option<PathBuf>,
+    /// the hex encoded master key. Prefer the environment variable, arguments are visible to
+    /// other processes.
+    #[arg(long, env = "HORCRUST_MASTER_KEY", hide_env_values = true)]
+    master_key: Option<String>,
+    /// a passphrase to derive the master key from.
+    #[arg(long, env = "HORCRUST_MASTER_PASSPHRASE", hide_env_values = true)]
+    master_passphrase: Option<String>,
+}
+impl MasterKeyArgs {
+    fn source(&self) -> Option<MasterKeySource> {
+        match (
+            &self.master_key_file,
+            &self.master_key,
+            &self.master_passphrase,
+        ) {
+            (Some(path), _, _) => Some(MasterKeySource::File(path.clone())),
+            (_, Some(key), _) => Some(MasterKeySource::Hex(key.clone())),
+            (_, _, Some(passphrase)) => Some(MasterKeySource::Passphrase(passphrase.clone())),
+            _ => None,
+        }
+    }
+}
 
-    let cli = CliArgs::parse();
-    if cli.servers.len() < 2 {
-        //TODO panic
-        println!("Please provide at least 2 servers. Include this server's address as well.");
+/// Same as MasterKeyArgs, for the key replacing it.
+#[derive(Args, Clone)]
+#[group(multiple = false)]
+struct NewMasterKeyArgs {
+    /// the file with the new hex encoded master key. A new one is generated if it doesn't exist.
+    #[arg(long, env = "HORCRUST_NEW_MASTER_KEY_FILE")]
+    new_master_key_file: Option<PathBuf>,
+    /// the new hex encoded master key.
+    #[arg(long, env = "HORCRUST_NEW_MASTER_KEY", hide_env_values = true)]
+    new_master_key: Option<String>,
+    /// a passphrase to derive the new master key from.
+    #[arg(long, env = "HORCRUST_NEW_MASTER_PASSPHRASE", hide_env_values = true)]
+    new_master_passphrase: Option<String>,
+}
+// the cli is logged on start: the keys and the passphrases are only shown as set.
+impl std::fmt::Debug for MasterKeyArgs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MasterKeyArgs")
+            .field("master_key_file", &self.master_key_file)
+            .field("master_key", &redacted(&self.master_key))
+            .field("master_passphrase", &redacted(&self.master_passphrase))
+            .finish()
     }
-    debug!("cli: {:?}", cli);
-    run(cli.port, cli.servers).unwrap();
 }
-fn run(port: u16, servers: Vec<String>) -> Result<()> {
-    // listen on port port
-    let listener = TcpListener::bind(("0000000", port))?;
-    info!("Listening on port {}", port);
-    let db = Arc::new(Mutex::new(SharesDatabase::new()));
-    let secret_sharing = AdditiveSecretSharing::default();
-    spawn_refresher(servers, db.clone());
-    for stream in listener.incoming() {
-        let mut connection = TcpConnectionHandler::new(stream?)?;
-        // avoid crashing if client sends garbage.
-        let received_res: Result<HorcrustMsgRequest> = connection.receive();
-        if let Ok(received) = received_res {
-            debug!("Received valid request.");
-            match received.request.unwrap() {
-                horcrust_msg_request::Request::PutShare(put_share) => {
-                    info!("Received put share request: {:?}", put_share);
-                    // this overwrites whatever was there before
-                    let mut db_lock = db.lock().unwrap();
-                    db_lock.insert(put_share.key, put_share.share);
-                    let response = msg_success_response();
-                    connection.send(response)?;
-                }
-                horcrust_msg_request::Request::GetShare(get_share) => {
-                    info!("Received get share request: {:?}", get_share);
-                    let db_lock = db.lock().unwrap();
-                    let share_opt = db_lock.get(get_share.key);
-                    if let Some(share) = share_opt {
-                        let response = msg_share_response(share);
-                        connection.send(response)?;
-                    } else {
-                        let response = msg_error_response(
-                            "Key not found. Use store-key to store a key first.",
-                        );
-                        connection.send(response)?;
-                    }
-                }
-                horcrust_msg_request::Request::Refresh(refresh) => {
-                    info!("Received refresh request: {:?}", refresh);
-                    let r = refresh.random;
-                    let mut db_lock = db.lock().unwrap();
-                    for key in refresh.key {
-                        db_lock.modify(key, |v| secret_sharing.refresh_share(r, v))?;
-                    }
-                    let response = msg_success_response();
-                    connection.send(response)?;
-                }
-            }
+impl std::fmt::Debug for NewMasterKeyArgs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NewMasterKeyArgs")
+            .field("new_master_key_file", &self.new_master_key_file)
+            .field("new_master_key", &redacted(&self.new_master_key))
+            .field(
+                "new_master_passphrase",
+                &redacted(&self.new_master_passphrase),
+            )
+            .finish()
+    }
+}
+fn redacted(secret: &Option<String>) -> Option<&'static str> {
+    secret.as_ref().map(|_| "<redacted>")
+}
+
+impl NewMasterKeyArgs {
+    fn source(&self) -> Option<MasterKeySource> {
+        MasterKeyArgs {
+            master_key_file: self.new_master_key_file.clone(),
+            master_key: self.new_master_key.clone(),
+            master_passphrase: self.new_master_passphrase.clone(),
         }
+        .source()
     }
-    unreachable!();
 }
 
-pub fn spawn_refresher(servers: Vec<String>, db: Arc<Mutex<SharesDatabase>>) {
-    std::thread::spawn(move || refresher(servers, db));
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// re-encrypts the shares in the database with a new master key, and exits. The server must be
+    /// stopped. Without a new master key, the shares are decrypted.
+    RotateMasterKey {
+        #[command(flatten)]
+        new_master_key: NewMasterKeyArgs,
+    },
 }
-/// debug logs commented out to avoid verbosity on the output.
-pub fn refresher(mut servers: Vec<String>, db: Arc<Mutex<SharesDatabase>>) -> Result<()> {
-    // in this way, different refresher processes will try to connect to the first node first.
-    // the first node that is able to connect will succeed in starting the refresh process.
-    servers.sort();
-    info!("Spawned refresher thread.");
-    loop {
-        // wait at least 2 seconds + between 1 and 10 seconds
-        let time_to_wait = 2 + (random::<f32>() * 50.0) as u64;
-        //debug!("Refresher: Waiting for {} seconds", time_to_wait);
-        std::thread::sleep(std::time::Duration::from_millis(time_to_wait));
-        //debug!("Refresher: Starting refreshing");
-        let refreshers = AdditiveSecretSharing::default().generate_refreshers(servers.len());
-        let db_lock = db.lock().unwrap();
-        let stale_keys = db_lock.stale_keys();
-        drop(db_lock);
-        // all good
-        if stale_keys.is_empty() {
-            //debug!("No stale keys to refresh.");
-            continue;
-        }
-        let mut connection = vec![];
-        for server in servers.iter() {
-            let socket = std::net::TcpStream::connect(server)?;
-            let handler = TcpConnectionHandler::new(socket)?;
-            connection.push(handler);
-        }
 
-        // after we acquired a "lock" on all servers, start refreshing.
-        for ((mut handler, r), server) in connection.into_iter().zip(refreshers).zip(servers.iter())
-        {
-            let request = msg_refresh_share_request(stale_keys.clone(), r);
-            handler.send(request)?;
-            let response: HorcrustMsgResponse = handler.receive()?;
-            match response.response.unwrap() {
-                horcrust_msg_response::Response::Error(HorcrustMsgError {
-                    error,
-                    error_string,
-                }) => {
-                    if error {
-                        info!(
-                            "Failed to refresh shares on server {}, error: {}",
-                            server, error_string
-                        );
-                    }
-                }
-                _ => {
-                    info!("Unknown response from server {}", server);
-                }
+#[tokio::main]
+async fn main() {
+    // setup env_logger
+    env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
+
+    let cli = CliArgs::parse();
+    if cli.show_identity {
+        let identity = match &cli.tls_cert {
+            Some(cert) => tls_certificate_id(cert).unwrap(),
+            None => {
+                public_identity_to_hex(&Identity::load_or_generate(&cli.identity).unwrap().public())
             }
-        }
+        };
+        println!("{identity}");
+        return;
+    }
+    if let Some(Command::RotateMasterKey { new_master_key }) = &cli.command {
+        let mut store = RedbStore::open(&cli.database, cli.master_key.source().as_ref()).unwrap();
+        store
+            .rotate_master_key(new_master_key.source().as_ref())
+            .unwrap();
+        println!("Master key of {:?} rotated.", cli.database);
+        return;
     }
+    if cli.servers.len() < 2 {
+        //TODO panic
+        println!("Please provide at least 2 servers. Include this server's address as well.");
+    }
+    debug!("cli: {:?}", cli);
+    let transport = match (&cli.tls_cert, &cli.tls_key, &cli.tls_ca) {
+        (Some(cert), Some(key), Some(ca)) => {
+            info!("Using TLS with certificate {cert:?}");
+            Transport::Tls(TlsConfig {
+                client: Some(tls_client_config(ca, cert, key).unwrap()),
+                server: Some(tls_server_config(cert, key, ca).unwrap()),
+            })
+        }
+        _ => {
+            let identity = Identity::load_or_generate(&cli.identity).unwrap();
+            // safe unwrap: required unless --show-identity or --tls-cert are passed.
+            let allowed_peers = AllowedPeers::load(&cli.allowed_peers.clone().unwrap()).unwrap();
+            info!("Identity: {}", public_identity_to_hex(&identity.public()));
+            Transport::Handshake(HandshakeConfig::new(identity, allowed_peers))
+        }
+    };
+    // listen on port port
+    let listener = TcpListener::bind(("0000000", cli.port)).await.unwrap();
+    let store = RedbStore::open(&cli.database, cli.master_key.source().as_ref()).unwrap();
+    let db = Arc::new(Mutex::new(SharesDatabase::open(store).unwrap()));
+    info!("Using database {:?}", cli.database);
+    let config = match &cli.config {
+        Some(path) => ServerConfig::load(path).unwrap(),
+        None => ServerConfig::default(),
+    };
+    let overrides = RefreshConfig {
+        interval_secs: cli.refresh_interval,
+        jitter_ms: cli.refresh_jitter,
+        batch_size: cli.refresh_batch_size,
+        enabled: cli.no_refresh.then_some(false),
+    };
+    let policy = config.refresh.merge(overrides).policy().unwrap();
+    info!("Refresh policy: {policy:?}");
+    let tenants = Tenants::new(config.tenants).unwrap();
+    // safe unwrap: required unless --show-identity is passed.
+    let server_identities = ServerIdentities::load(&cli.server_identities.unwrap()).unwrap();
+    spawn_refresher(cli.servers.clone(), db.clone(), transport.clone(), policy);
+    run(
+        listener,
+        db,
+        cli.servers,
+        transport,
+        tenants,
+        server_identities,
+    )
+    .await
+    .unwrap();
 }
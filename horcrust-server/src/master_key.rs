@@ -0,0 +1,127 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::{anyhow, ensure, Context};
+use argon2::Argon2;
+use horcrust::Result;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const NONCE_LEN: usize = 12;
+
+/// Where the master key comes from.
+#[derive(Clone, Debug)]
+pub enum MasterKeySource {
+    /// a file with the hex encoded key, generated if it doesn't exist.
+    File(PathBuf),
+    /// the hex encoded key.
+    Hex(String),
+    /// a passphrase, the key is derived from it with Argon2.
+    Passphrase(String),
+}
+
+/// Key encrypting the shares on disk.
+pub struct MasterKey {
+    cipher: Aes256Gcm,
+}
+impl MasterKey {
+    /// `salt` is only used to derive keys from passphrases: it is stored in the database, so the
+    /// same passphrase gives different keys on different servers.
+    pub fn load(source: &MasterKeySource, salt: &[u8]) -> Result<Self> {
+        let key = match source {
+            MasterKeySource::File(path) => Self::load_or_generate(path)?,
+            MasterKeySource::Hex(hex_key) => key_from_hex(hex_key)?,
+            MasterKeySource::Passphrase(passphrase) => {
+                let mut key = [0; 32];
+                Argon2::default()
+                    .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                    .map_err(|err| anyhow!("Failed to derive the master key: {err}"))?;
+                key
+            }
+        };
+        Ok(Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        })
+    }
+    fn load_or_generate(path: &Path) -> Result<[u8; 32]> {
+        if path.exists() {
+            let content = std::fs::read_to_string(path)
+                .context(format!("failed to read the master key from {path:?}"))?;
+            return key_from_hex(content.trim());
+        }
+        let key: [u8; 32] = Aes256Gcm::generate_key(OsRng).into();
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(path)
+            .context(format!("failed to store the master key in {path:?}"))?;
+        writeln!(file, "{}", hex::encode(key))?;
+        Ok(key)
+    }
+    /// Returns the nonce followed by the ciphertext. `aad` binds the ciphertext to where it is
+    /// stored, so it can't be moved somewhere else.
+    pub fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| anyhow!("Failed to encrypt."))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+    pub fn decrypt(&self, encrypted: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        ensure!(encrypted.len() >= NONCE_LEN, "Encrypted value too short.");
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| anyhow!("Failed to decrypt, wrong master key?"))
+    }
+}
+
+fn key_from_hex(hex_key: &str) -> Result<[u8; 32]> {
+    hex::decode(hex_key)?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid master key: it must be 32 hex encoded bytes."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_master_key() -> Result<()> {
+        let key = MasterKey::load(&MasterKeySource::Hex("01".repeat(32)), b"")?;
+        let encrypted = key.encrypt(b"share", b"key 1")?;
+        assert_eq!(key.decrypt(&encrypted, b"key 1")?, b"share");
+        // the share of another key can't be swapped in.
+        assert!(key.decrypt(&encrypted, b"key 2").is_err());
+        let other = MasterKey::load(&MasterKeySource::Hex("02".repeat(32)), b"")?;
+        assert!(other.decrypt(&encrypted, b"key 1").is_err());
+        assert!(MasterKey::load(&MasterKeySource::Hex("01".into()), b"").is_err());
+
+        let passphrase = MasterKeySource::Passphrase("correct horse".into());
+        let encrypted = MasterKey::load(&passphrase, b"salt of 1")?.encrypt(b"share", b"")?;
+        assert_eq!(
+            MasterKey::load(&passphrase, b"salt of 1")?.decrypt(&encrypted, b"")?,
+            b"share"
+        );
+        assert!(MasterKey::load(&passphrase, b"salt of 2")?
+            .decrypt(&encrypted, b"")
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,353 @@
+use crate::shares_db::{lock_db, unix_time};
+use crate::SharesDatabase;
+use anyhow::bail;
+use horcrust::{
+    horcrust_msg_response, msg_refresh_commit_request, msg_refresh_lock_request,
+    msg_refresh_share_request, msg_refresh_status_request, msg_refresh_unlock_request,
+    BytesSecretSharing, ConnectionPool, HorcrustCommitmentsBytes, HorcrustMsgError,
+    HorcrustMsgRequest, HorcrustShareBytes, HorcrustStoreKey, RefreshShareRequest,
+    RefreshStagedResponse, Result, Transport,
+};
+use log::{info, warn};
+use rand::random;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long the refresh lease lasts: if the refresher holding it stops, another one takes over
+/// once it expires.
+const REFRESH_LEASE: Duration = Duration::from_secs(10);
+/// How often the refresher looks for stale keys, before the jitter.
+const REFRESH_CHECK_PERIOD: Duration = Duration::from_secs(1);
+/// How long after a round expires the servers resolve it themselves, if the coordinator didn't
+/// finish it. It covers the commits still in flight, and the clock drift between the servers.
+const RESOLVE_MARGIN: Duration = Duration::from_secs(10);
+
+/// When the shares are refreshed, and how many at a time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RefreshPolicy {
+    /// shares are refreshed once they are this old, unless they were stored with their own
+    /// refresh interval.
+    pub interval: Duration,
+    /// up to this random delay is added before every round, so the servers don't all try to
+    /// start a round at the same time.
+    pub jitter: Duration,
+    /// the maximum number of keys refreshed in a round, the oldest first. All of them if None.
+    pub batch_size: Option<usize>,
+    /// if false, this server doesn't start refreshes, but it still takes part in the ones started
+    /// by the other servers.
+    pub enabled: bool,
+}
+impl Default for RefreshPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            jitter: Duration::from_secs(1),
+            batch_size: None,
+            enabled: true,
+        }
+    }
+}
+
+pub fn spawn_refresher(
+    servers: Vec<String>,
+    db: Arc<Mutex<SharesDatabase>>,
+    transport: Transport,
+    policy: RefreshPolicy,
+) {
+    std::thread::spawn(move || refresher(servers, db, transport, policy));
+}
+/// debug logs commented out to avoid verbosity on the output.
+pub fn refresher(
+    mut servers: Vec<String>,
+    db: Arc<Mutex<SharesDatabase>>,
+    transport: Transport,
+    policy: RefreshPolicy,
+) -> Result<()> {
+    if !policy.enabled {
+        info!("Refresh disabled, only resolving the rounds of the other servers.");
+    }
+    // every refresher asks for the refresh lease in the same order, so they stop at the first
+    // server: the one getting it is the only one refreshing the shares, until the round is over.
+    servers.sort();
+    let holder = random::<u64>();
+    // the connections to the other servers are kept open between refreshes.
+    let mut connections = ConnectionPool::new(transport);
+    // commits and rollbacks that failed: they are retried until they succeed, because the other
+    // servers already switched to the refreshed shares, or the keys are locked by the staged ones.
+    // Once the round expires, the servers resolve it without the coordinator.
+    let mut unfinished: Vec<Unfinished> = vec![];
+    info!("Spawned refresher thread.");
+    loop {
+        let time_to_wait = REFRESH_CHECK_PERIOD + policy.jitter.mul_f64(random::<f64>());
+        //debug!("Refresher: Waiting for {:?}", time_to_wait);
+        std::thread::sleep(time_to_wait);
+        resolve_rounds(&mut connections, &db, RESOLVE_MARGIN);
+        if !policy.enabled {
+            continue;
+        }
+        unfinished.retain(|(server, request, expires_at)| {
+            unix_time() < *expires_at
+                && send_to_server(&mut connections, server, request.clone()).is_err()
+        });
+        // a new refresh has to wait for the previous ones.
+        if !unfinished.is_empty() {
+            continue;
+        }
+        //debug!("Refresher: Starting refreshing");
+        let db_lock = lock_db(&db);
+        let mut stale_keys = db_lock.stale_keys(policy.interval);
+        if let Some(batch_size) = policy.batch_size {
+            stale_keys.truncate(batch_size);
+        }
+        // every key is refreshed by the servers holding it, in a round of their own. Keys stored
+        // without them are held by the configured servers.
+        let mut rounds: BTreeMap<Vec<String>, Vec<HorcrustStoreKey>> = BTreeMap::new();
+        for key in stale_keys {
+            let holders = db_lock
+                .servers(&key)
+                .filter(|holders| !holders.is_empty())
+                .unwrap_or_else(|| servers.clone());
+            rounds.entry(holders).or_default().push(key);
+        }
+        drop(db_lock);
+        // all good
+        if rounds.is_empty() {
+            //debug!("No stale keys to refresh.");
+            continue;
+        }
+        for (servers, keys) in rounds {
+            refresh(
+                &mut connections,
+                &db,
+                &servers,
+                keys,
+                holder,
+                &mut unfinished,
+            );
+        }
+    }
+}
+
+/// A commit or rollback to retry: the server, the request, and when the round expires.
+type Unfinished = (String, HorcrustMsgRequest, u64);
+
+/// Refreshes the keys held by `servers` in a single round. The commits that fail are added to
+/// `unfinished`.
+fn refresh(
+    connections: &mut ConnectionPool,
+    db: &Mutex<SharesDatabase>,
+    servers: &[String],
+    mut keys: Vec<HorcrustStoreKey>,
+    holder: u64,
+    unfinished: &mut Vec<Unfinished>,
+) {
+    // every key gets its own refreshers, generated with the scheme it was stored with.
+    let mut refreshers: Vec<Vec<HorcrustShareBytes>> = vec![vec![]; servers.len()];
+    // the updates to the commitments are public, every server gets the same ones.
+    let mut updates: Vec<HorcrustCommitmentsBytes> = vec![];
+    let db_lock = lock_db(db);
+    // keys whose refreshers can't be generated are skipped, the others are still refreshed.
+    keys.retain(|key| {
+        // e.g. deleted since it was found stale.
+        let Some(stored) = db_lock.get_stored(key) else {
+            return false;
+        };
+        let generated =
+            BytesSecretSharing::from_scheme(&stored.scheme).and_then(|secret_sharing| {
+                secret_sharing
+                    .generate_refreshers_with_commitments(servers.len(), stored.share.len())
+            });
+        match generated {
+            Ok((key_refreshers, key_updates)) => {
+                for (server_refreshers, r) in refreshers.iter_mut().zip(key_refreshers) {
+                    server_refreshers.push(r);
+                }
+                updates.push(key_updates);
+                true
+            }
+            Err(err) => {
+                warn!("Skipping the refresh of key '{key}': {err:#}");
+                false
+            }
+        }
+    });
+    drop(db_lock);
+    if keys.is_empty() {
+        return;
+    }
+    if !acquire_lease(connections, servers, holder, REFRESH_LEASE) {
+        //debug!("Refresh lease held by another server.");
+        return;
+    }
+    // phase 1: every server stages its refreshed shares.
+    let round_id = random::<u64>();
+    let expires_at = unix_time() + REFRESH_LEASE.as_secs();
+    let mut staged = true;
+    // keys some server failed to refresh, no server commits them.
+    let mut skipped: Vec<HorcrustStoreKey> = vec![];
+    // the refreshers at position i are for the share at index i, like the client assigns them.
+    for (index, (r, server)) in refreshers.into_iter().zip(servers.iter()).enumerate() {
+        let request = msg_refresh_share_request(RefreshShareRequest {
+            key: keys.clone(),
+            random: r,
+            commitments: updates.clone(),
+            index: index as u32,
+            round_id,
+            holder,
+            expires_at,
+            round_servers: servers.to_vec(),
+        });
+        // failed connections are dropped by the pool, and opened again on the next request.
+        match stage_on_server(connections, server, request) {
+            Ok(failed) => {
+                for (key, err) in failed {
+                    warn!("Server {server} failed to refresh key '{key}', skipping it: {err}");
+                    if !skipped.contains(&key) {
+                        skipped.push(key);
+                    }
+                }
+            }
+            Err(err) => {
+                info!("Failed to stage refresh {round_id} on server {server}: {err:#}");
+                staged = false;
+                break;
+            }
+        }
+    }
+    // the servers don't accept the commit anymore, they resolve the round themselves.
+    if staged && unix_time() >= expires_at {
+        info!("Refresh {round_id} expired while it was staged.");
+        staged = false;
+    }
+    // phase 2: all the servers switch to the refreshed shares, or none does.
+    if !staged {
+        info!("Rolling back refresh {round_id}.");
+    }
+    for server in servers.iter() {
+        let request = msg_refresh_commit_request(round_id, staged, skipped.clone());
+        if let Err(err) = send_to_server(connections, server, request.clone()) {
+            info!("Failed to finish refresh {round_id} on server {server}, will retry: {err:#}");
+            unfinished.push((server.clone(), request, expires_at));
+        }
+    }
+    // the other coordinators, e.g. of a reshare, can take it now.
+    release_lease(connections, servers, holder);
+}
+
+/// Stages a refresh on the server, returning the keys it failed to refresh and why.
+fn stage_on_server(
+    connections: &mut ConnectionPool,
+    server: &str,
+    request: HorcrustMsgRequest,
+) -> Result<Vec<(HorcrustStoreKey, String)>> {
+    match request_server(connections, server, request)? {
+        horcrust_msg_response::Response::RefreshStaged(RefreshStagedResponse {
+            failed_key,
+            error,
+        }) => Ok(failed_key.into_iter().zip(error).collect()),
+        _ => bail!("Unknown response from server {server}"),
+    }
+}
+
+/// Resolves the rounds still staged `margin` after they expired, e.g. because the coordinator
+/// stopped between the stage and the commit. A round is committed if any server of the round
+/// committed it, and rolled back once all of them answered that they didn't: it stays staged
+/// while some of them don't answer.
+pub(crate) fn resolve_rounds(
+    connections: &mut ConnectionPool,
+    db: &Mutex<SharesDatabase>,
+    margin: Duration,
+) {
+    let expired = lock_db(db).expired_rounds(margin);
+    for (round_id, servers) in expired {
+        let mut committed = None;
+        let mut unanswered = vec![];
+        for server in &servers {
+            let request = msg_refresh_status_request(round_id);
+            match request_server(connections, server, request) {
+                Ok(horcrust_msg_response::Response::RefreshStatus(status)) => {
+                    if status.committed {
+                        committed = Some(status.skipped_key);
+                        break;
+                    }
+                }
+                Ok(_) => unanswered.push(server),
+                Err(err) => {
+                    info!("Failed to get the status of refresh {round_id} from {server}: {err:#}");
+                    unanswered.push(server);
+                }
+            }
+        }
+        let resolved = match &committed {
+            Some(skipped) => {
+                info!("Committing refresh {round_id}, another server committed it.");
+                lock_db(db).resolve_refresh(round_id, Some(skipped))
+            }
+            None if unanswered.is_empty() => {
+                info!("Rolling back refresh {round_id}, no server committed it.");
+                lock_db(db).resolve_refresh(round_id, None)
+            }
+            None => {
+                warn!(
+                    "Can't resolve refresh {round_id} yet, servers {unanswered:?} didn't answer."
+                );
+                continue;
+            }
+        };
+        if let Err(err) = resolved {
+            warn!("Failed to resolve refresh {round_id}: {err:#}");
+        }
+    }
+}
+
+/// Takes the refresh lease of all the servers for `lease`, or none of them.
+pub(crate) fn acquire_lease(
+    connections: &mut ConnectionPool,
+    servers: &[String],
+    holder: u64,
+    lease: Duration,
+) -> bool {
+    let lease_ms = lease.as_millis() as u64;
+    for (i, server) in servers.iter().enumerate() {
+        let request = msg_refresh_lock_request(holder, lease_ms);
+        if send_to_server(connections, server, request).is_err() {
+            release_lease(connections, &servers[..i], holder);
+            return false;
+        }
+    }
+    true
+}
+pub(crate) fn release_lease(connections: &mut ConnectionPool, servers: &[String], holder: u64) {
+    for server in servers {
+        let _ = send_to_server(connections, server, msg_refresh_unlock_request(holder));
+    }
+}
+
+/// Sends a request to another server, failing unless it answers with a success.
+pub(crate) fn send_to_server(
+    connections: &mut ConnectionPool,
+    server: &str,
+    request: HorcrustMsgRequest,
+) -> Result<()> {
+    match request_server(connections, server, request)? {
+        horcrust_msg_response::Response::Error(_) => Ok(()),
+        _ => bail!("Unknown response from server {server}"),
+    }
+}
+/// Sends a request to another server, failing if it answers with an error.
+pub(crate) fn request_server(
+    connections: &mut ConnectionPool,
+    server: &str,
+    request: HorcrustMsgRequest,
+) -> Result<horcrust_msg_response::Response> {
+    match connections.request(server, request)?.response {
+        Some(horcrust_msg_response::Response::Error(HorcrustMsgError {
+            error: true,
+            error_string,
+            ..
+        })) => bail!(error_string),
+        Some(response) => Ok(response),
+        None => bail!("Unknown response from server {server}"),
+    }
+}
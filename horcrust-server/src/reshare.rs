@@ -0,0 +1,394 @@
+use crate::refresher::{acquire_lease, release_lease, send_to_server};
+use crate::shares_db::{lock_db, refresh_interval_from_secs, refresh_interval_secs, unix_time};
+use crate::{ServerIdentities, SharesDatabase, StoredShare, Tenants};
+use anyhow::{anyhow, bail, ensure, Context};
+use horcrust::{
+    msg_refresh_commit_request, msg_reshare_contribution_request, msg_reshare_split_request,
+    msg_reshare_stage_request, BytesSecretSharing, ConnectionPool, HorcrustMsgRequest,
+    ReshareContributionRequest, ReshareRequest, ReshareSplitRequest, ReshareStageRequest, Result,
+    SharingScheme, Transport,
+};
+use log::{info, warn};
+use rand::random;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long the coordinator holds the refresh lease of the servers taking part in a reshare. It
+/// releases it when the reshare is over.
+const RESHARE_LEASE: Duration = Duration::from_secs(30);
+/// How long the coordinator waits for the refresh lease of the servers, while they are refreshing
+/// their keys.
+const LEASE_WAIT: Duration = Duration::from_secs(10);
+/// How many times the coordinator tries to send the commit, or the rollback, to every server.
+const FINISH_ATTEMPTS: usize = 3;
+
+/// Coordinates the reshare of a key from the servers holding it to the new ones, `servers` if it
+/// was stored without them. The old servers split their own shares and send the sub-shares to
+/// the new servers directly, so neither the coordinator nor any other server sees enough of them
+/// to rebuild the secret.
+/// The coordinator holds the refresh lease of the old and new servers for the whole reshare, so
+/// the share can't be refreshed meanwhile, and the new shares are staged and committed like a
+/// refresh: all the servers switch to them, or none does. The new servers must authenticate as
+/// one of `server_identities`.
+pub fn reshare(
+    db: &Mutex<SharesDatabase>,
+    mut servers: Vec<String>,
+    request: ReshareRequest,
+    transport: &Transport,
+    server_identities: &ServerIdentities,
+) -> Result<()> {
+    let key = request.key;
+    let stored = lock_db(db)
+        .get_stored(&key)
+        .ok_or_else(|| anyhow!("Key '{key}' not found."))?;
+    // share indexes are positions in the sorted list, like the client and refresher use.
+    if !stored.servers.is_empty() {
+        servers = stored.servers.clone();
+    }
+    servers.sort();
+    let mut new_servers = request.servers;
+    new_servers.sort();
+    new_servers.dedup();
+    ensure!(
+        new_servers.len() >= 2,
+        "Please provide at least 2 new servers."
+    );
+    let old = BytesSecretSharing::from_scheme(&stored.scheme)?;
+    let new_scheme = SharingScheme {
+        threshold: request.threshold,
+        ..stored.scheme.clone()
+    };
+    let new = BytesSecretSharing::from_scheme(&new_scheme)?;
+    ensure!(
+        (2..=new_servers.len()).contains(&new.inner().threshold(new_servers.len())),
+        "The threshold should be between 2 and the number of new servers."
+    );
+
+    let mut involved: Vec<String> = servers.iter().chain(&new_servers).cloned().collect();
+    involved.sort();
+    involved.dedup();
+    let holder = random::<u64>();
+    let mut connections = ConnectionPool::new(transport.clone());
+    check_servers(&mut connections, &new_servers, server_identities)?;
+    ensure!(
+        wait_for_lease(&mut connections, &involved, holder),
+        "The refresh lease of the servers is held by another server, or they are not reachable."
+    );
+    let round_id = random::<u64>();
+    let expires_at = unix_time() + RESHARE_LEASE.as_secs();
+    let reshare = Reshare {
+        key: &key,
+        epoch: stored.epoch,
+        threshold: old.inner().threshold(servers.len()),
+        servers: &servers,
+        new_servers: &new_servers,
+        new_scheme,
+        holder,
+        round_id,
+        expires_at,
+        involved: &involved,
+    };
+    // phase 1: the sub-shares are sent, and every server stages its new share or the removal.
+    let mut staged = reshare.stage(&mut connections);
+    // the servers don't accept the commit anymore, they resolve the round themselves.
+    if staged.is_ok() && unix_time() >= expires_at {
+        staged = Err(anyhow!("The reshare expired while it was staged."));
+    }
+    if let Err(err) = &staged {
+        info!("Rolling back reshare {round_id} of key '{key}': {err:#}");
+    }
+    // phase 2: all the servers switch to the new shares, or none does.
+    let finished = finish(&mut connections, &involved, round_id, staged.is_ok());
+    release_lease(&mut connections, &involved, holder);
+    staged?;
+    finished?;
+    info!("Key '{key}' reshared to servers {new_servers:?}.");
+    Ok(())
+}
+
+/// A reshare round, see `reshare`.
+struct Reshare<'a> {
+    key: &'a str,
+    /// the epoch of the share when the reshare started: the old servers must still be there.
+    epoch: u64,
+    /// how many old servers take part.
+    threshold: usize,
+    servers: &'a [String],
+    new_servers: &'a [String],
+    new_scheme: SharingScheme,
+    holder: u64,
+    round_id: u64,
+    /// see RefreshShareRequest.expires_at.
+    expires_at: u64,
+    /// the old and new servers.
+    involved: &'a [String],
+}
+impl Reshare<'_> {
+    fn stage(&self, connections: &mut ConnectionPool) -> Result<()> {
+        // a qualified subset of the old servers, the first ones, takes part.
+        let indices: Vec<u32> = (0..self.threshold as u32).collect();
+        for (index, server) in self.servers.iter().enumerate().take(self.threshold) {
+            let split = ReshareSplitRequest {
+                key: self.key.to_string(),
+                servers: self.new_servers.to_vec(),
+                scheme: Some(self.new_scheme.clone()),
+                indices: indices.clone(),
+                index: index as u32,
+                epoch: self.epoch,
+                holder: self.holder,
+                round_id: self.round_id,
+            };
+            send_to_server(connections, server, msg_reshare_split_request(split))
+                .context(format!("Server {server} failed to reshare"))?;
+        }
+        // every new server received all of its sub-shares.
+        for server in self.new_servers {
+            send_to_server(connections, server, self.stage_request(false))
+                .context(format!("Server {server} failed to stage the new share"))?;
+        }
+        for server in self
+            .servers
+            .iter()
+            .filter(|s| !self.new_servers.contains(s))
+        {
+            send_to_server(connections, server, self.stage_request(true))
+                .context(format!("Server {server} failed to stage the removal"))?;
+        }
+        Ok(())
+    }
+    fn stage_request(&self, remove: bool) -> HorcrustMsgRequest {
+        msg_reshare_stage_request(ReshareStageRequest {
+            key: self.key.to_string(),
+            remove,
+            round_id: self.round_id,
+            holder: self.holder,
+            servers: self.new_servers.to_vec(),
+            expires_at: self.expires_at,
+            round_servers: self.involved.to_vec(),
+        })
+    }
+}
+
+/// Takes the refresh lease of `servers`, retrying until `LEASE_WAIT` is over: the refreshers
+/// release it after every round.
+fn wait_for_lease(connections: &mut ConnectionPool, servers: &[String], holder: u64) -> bool {
+    let deadline = Instant::now() + LEASE_WAIT;
+    while !acquire_lease(connections, servers, holder, RESHARE_LEASE) {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        // a random pause, so the reshare doesn't keep trying in lockstep with a refresher.
+        std::thread::sleep(Duration::from_millis(100).mul_f64(random::<f64>()));
+    }
+    true
+}
+
+/// Commits, or rolls back, the round on every server. Rollbacks of rounds a server didn't stage
+/// do nothing. The servers that don't get it resolve the round themselves once it expires.
+fn finish(
+    connections: &mut ConnectionPool,
+    servers: &[String],
+    round_id: u64,
+    commit: bool,
+) -> Result<()> {
+    let mut unfinished: Vec<&String> = servers.iter().collect();
+    for _ in 0..FINISH_ATTEMPTS {
+        unfinished.retain(|server| {
+            let request = msg_refresh_commit_request(round_id, commit, vec![]);
+            match send_to_server(connections, server, request) {
+                Ok(()) => false,
+                Err(err) => {
+                    warn!("Failed to finish reshare {round_id} on server {server}: {err:#}");
+                    true
+                }
+            }
+        });
+        if unfinished.is_empty() {
+            return Ok(());
+        }
+    }
+    bail!("Servers {unfinished:?} didn't finish reshare {round_id}.")
+}
+
+/// Fails unless every one of `servers` authenticates as one of `server_identities`: the sub-shares
+/// and the new shares must only reach servers.
+fn check_servers(
+    connections: &mut ConnectionPool,
+    servers: &[String],
+    server_identities: &ServerIdentities,
+) -> Result<()> {
+    for server in servers {
+        let peer_id = connections
+            .peer_id(server)
+            .context(format!("Failed to connect to server {server}"))?;
+        ensure!(
+            server_identities.contains(&peer_id),
+            "{server} is not one of the server identities."
+        );
+    }
+    Ok(())
+}
+
+/// Splits the share of this server among the new servers, see ReshareSplitRequest. The new
+/// servers must authenticate as one of `server_identities`.
+pub fn reshare_split(
+    db: &Mutex<SharesDatabase>,
+    request: ReshareSplitRequest,
+    transport: &Transport,
+    server_identities: &ServerIdentities,
+) -> Result<()> {
+    let key = request.key;
+    // the share is copied, so the lock is not held while talking to the other servers.
+    let stored = {
+        let db = lock_db(db);
+        ensure!(
+            db.has_refresh_lease(request.holder),
+            "The refresh lease is held by another server."
+        );
+        ensure!(
+            !db.is_staged(&key),
+            "Key '{key}' has a refresh in progress."
+        );
+        db.get_stored(&key)
+            .ok_or_else(|| anyhow!("Key '{key}' not found."))?
+    };
+    ensure!(
+        stored.epoch == request.epoch,
+        "The reshare expected epoch {}, but key '{key}' is at epoch {}.",
+        request.epoch,
+        stored.epoch
+    );
+    ensure!(
+        stored.index == request.index,
+        "The reshare expected share index {}, but key '{key}' has index {}.",
+        request.index,
+        stored.index
+    );
+    let old = BytesSecretSharing::from_scheme(&stored.scheme)?;
+    let new_scheme = request.scheme.unwrap_or_default();
+    let new = BytesSecretSharing::from_scheme(&new_scheme)?;
+    let indices: Vec<usize> = request.indices.iter().map(|i| *i as usize).collect();
+    let (sub_shares, commitments) = old.reshare(
+        stored.index as usize,
+        &indices,
+        &stored.share,
+        &new,
+        request.servers.len(),
+    )?;
+    let mut connections = ConnectionPool::new(transport.clone());
+    // all of them are checked before any sub-share leaves this server.
+    check_servers(&mut connections, &request.servers, server_identities)?;
+    for (index, (server, share)) in request.servers.iter().zip(sub_shares).enumerate() {
+        let contribution = ReshareContributionRequest {
+            key: key.clone(),
+            index: index as u32,
+            from_index: stored.index,
+            contributions: indices.len() as u32,
+            share,
+            commitments: commitments.clone(),
+            scheme: Some(new_scheme.clone()),
+            refresh_interval_secs: refresh_interval_secs(stored.refresh_interval),
+            acl: Some(stored.acl.clone()),
+            epoch: stored.epoch,
+            round_id: request.round_id,
+            holder: request.holder,
+        };
+        let request = msg_reshare_contribution_request(contribution);
+        send_to_server(&mut connections, server, request)
+            .context(format!("Failed to send the sub-share to server {server}"))?;
+    }
+    Ok(())
+}
+
+/// Keeps a sub-share sent by an old server until the ReshareStageRequest of the round.
+pub fn reshare_contribution(
+    db: &Mutex<SharesDatabase>,
+    contribution: ReshareContributionRequest,
+) -> Result<()> {
+    let key = &contribution.key;
+    let mut db = lock_db(db);
+    ensure!(
+        db.has_refresh_lease(contribution.holder),
+        "The refresh lease is held by another server."
+    );
+    ensure!(!db.is_staged(key), "Key '{key}' has a refresh in progress.");
+    // sub-shares of a round that is over would be staged by the next reshare of the key.
+    ensure!(
+        db.committed_round(contribution.round_id).is_none(),
+        "Reshare {} is already over.",
+        contribution.round_id
+    );
+    db.add_reshare_contribution(contribution);
+    Ok(())
+}
+
+/// Stages the sum of the received sub-shares as the new share, or the removal of the share if
+/// this server is not part of the new set. See ReshareStageRequest. New keys must fit in the
+/// quotas of their tenant.
+pub fn reshare_stage(
+    db: &Mutex<SharesDatabase>,
+    request: ReshareStageRequest,
+    tenants: &Tenants,
+) -> Result<()> {
+    let key = request.key;
+    let mut db = lock_db(db);
+    ensure!(
+        db.has_refresh_lease(request.holder),
+        "The refresh lease is held by another server."
+    );
+    if request.remove {
+        return db.stage_refresh(
+            request.round_id,
+            vec![(key, None)],
+            request.expires_at,
+            request.round_servers,
+        );
+    }
+    let contributions = db.take_reshare_contributions(&key);
+    let Some(first) = contributions.first() else {
+        bail!("No sub-shares received for key '{key}'.");
+    };
+    ensure!(
+        first.round_id == request.round_id,
+        "The sub-shares received for key '{key}' are from another reshare."
+    );
+    ensure!(
+        contributions.len() == first.contributions as usize,
+        "Received {} sub-shares out of {} for key '{key}'.",
+        contributions.len(),
+        first.contributions
+    );
+    let index = first.index;
+    let epoch = first.epoch;
+    let refresh_interval = refresh_interval_from_secs(first.refresh_interval_secs);
+    let scheme = first.scheme.clone().unwrap_or_default();
+    let acl = first.acl.clone().unwrap_or_default();
+    let secret_sharing = BytesSecretSharing::from_scheme(&scheme)?;
+    let sub_shares = contributions
+        .into_iter()
+        .map(|c| (c.share, c.commitments))
+        .collect();
+    let (share, commitments) = secret_sharing.combine_reshares(sub_shares)?;
+    let stored = StoredShare {
+        share,
+        scheme,
+        commitments,
+        index,
+        // the old shares can't be combined with the new ones, so they get different epochs.
+        epoch: epoch + 1,
+        refresh_interval,
+        stored_at: unix_time(),
+        refreshed_at: 0,
+        acl,
+        servers: request.servers,
+    };
+    if let Some(tenant) = tenants.tenant_of_key(&key) {
+        tenant.check_quotas(&db, &key, stored.share.len())?;
+    }
+    db.stage_refresh(
+        request.round_id,
+        vec![(key, Some(stored))],
+        request.expires_at,
+        request.round_servers,
+    )
+}
@@ -0,0 +1,1332 @@
+use crate::acl::{can_access, new_acl, Access};
+use crate::shares_db::{lock_db, refresh_interval_from_secs, refresh_interval_secs, unix_time};
+use crate::{ServerIdentities, SharesDatabase, StoredShare, Tenants};
+use anyhow::{anyhow, ensure};
+use horcrust::{
+    horcrust_msg_request, msg_error_response, msg_list_keys_response, msg_refresh_staged_response,
+    msg_refresh_status_response, msg_share_response, msg_success_response,
+    msg_unauthorized_response, validate_key, BytesSecretSharing, ConnectionClosed,
+    HorcrustMsgRequest, HorcrustMsgResponse, HorcrustStoreKey, KeyInfo, MalformedMessage,
+    RefreshShareRequest, Result, Transport,
+};
+use log::{debug, error, info, warn};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+
+/// Connections served at the same time, the next ones wait to be accepted.
+const MAX_CONNECTIONS: usize = 1024;
+/// Keys listed by a ListKeysRequest without a limit, and the most that can be asked for.
+const DEFAULT_LIST_LIMIT: usize = 100;
+const MAX_LIST_LIMIT: usize = 1000;
+
+/// Serves the connections accepted by `listener`. Failures are isolated to their connection: they
+/// are logged, and answered with an error response when the connection is still usable. Clients
+/// of a tenant only see the keys of the tenant, and only the ones their ACLs let them access: with
+/// tenants, the other peers can't access any key. Only the peers in `server_identities` can send
+/// the requests the servers send each other.
+pub async fn run(
+    listener: TcpListener,
+    db: Arc<Mutex<SharesDatabase>>,
+    servers: Vec<String>,
+    transport: Transport,
+    tenants: Tenants,
+    server_identities: ServerIdentities,
+) -> Result<()> {
+    info!("Listening on {}", listener.local_addr()?);
+    let tenants = Arc::new(tenants);
+    let server_identities = Arc::new(server_identities);
+    let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    loop {
+        // safe unwrap: the semaphore is never closed.
+        let permit = connections.clone().acquire_owned().await.unwrap();
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                // e.g. too many open files: the next connections may succeed.
+                warn!("Failed to accept a connection: {err:?}");
+                continue;
+            }
+        };
+        let db = db.clone();
+        let servers = servers.clone();
+        let transport = transport.clone();
+        let (tenants, server_identities) = (tenants.clone(), server_identities.clone());
+        // every connection gets its own task, so a slow client doesn't block the others.
+        tokio::spawn(async move {
+            handle_connection(
+                stream,
+                peer,
+                db,
+                servers,
+                transport,
+                tenants,
+                server_identities,
+            )
+            .await;
+            drop(permit);
+        });
+    }
+}
+
+/// Logs why a connection was closed, in the same key=value format for every failure.
+fn log_failure(peer: SocketAddr, stage: &str, err: &anyhow::Error) {
+    info!("connection failed: peer={peer} stage={stage} error=\"{err:#}\"");
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    peer: SocketAddr,
+    db: Arc<Mutex<SharesDatabase>>,
+    servers: Vec<String>,
+    transport: Transport,
+    tenants: Arc<Tenants>,
+    server_identities: Arc<ServerIdentities>,
+) {
+    // peers not in the allowed list, or with an invalid certificate, are refused here.
+    let mut connection = match transport.accept_async(stream).await {
+        Ok(connection) => connection,
+        Err(err) => return log_failure(peer, "handshake", &err),
+    };
+    // the peer is authenticated by either transport.
+    let peer_identity = connection.peer_id();
+    if let Some(tenant) = tenants.tenant_of(&peer_identity) {
+        debug!("Connection from {peer} of tenant '{}'.", tenant.name);
+    }
+    // the peer can send many requests, until it closes the connection.
+    loop {
+        let response = match connection.receive().await {
+            Ok(HorcrustMsgRequest {
+                request: Some(request),
+            }) => {
+                debug!("Received valid request.");
+                let (db, servers, transport) = (db.clone(), servers.clone(), transport.clone());
+                let (tenants, peer_identity) = (tenants.clone(), peer_identity.clone());
+                let server_identities = server_identities.clone();
+                // requests lock the database and may talk to the other servers during a reshare,
+                // so they run on the blocking pool. Servers can send requests to themselves while
+                // serving another one, so they can't wait for each other.
+                let handled = tokio::task::spawn_blocking(move || {
+                    handle_request(
+                        request,
+                        &peer_identity,
+                        &tenants,
+                        &db,
+                        servers,
+                        &transport,
+                        &server_identities,
+                    )
+                })
+                .await;
+                match handled {
+                    Ok(response) => response,
+                    Err(err) => {
+                        error!("Request from {peer} failed: {err}");
+                        msg_error_response("Internal error.")
+                    }
+                }
+            }
+            Ok(HorcrustMsgRequest { request: None }) => {
+                log_failure(peer, "request", &anyhow!("empty request"));
+                msg_error_response("Empty request.")
+            }
+            Err(err) if err.is::<ConnectionClosed>() => {
+                debug!("Connection from {peer} closed.");
+                return;
+            }
+            Err(err) if err.is::<MalformedMessage>() => {
+                log_failure(peer, "request", &err);
+                msg_error_response("Malformed request.")
+            }
+            // the session can't be trusted anymore, e.g. the message was replayed.
+            Err(err) => return log_failure(peer, "receive", &err),
+        };
+        if let Err(err) = connection.send(response).await {
+            return log_failure(peer, "send", &err);
+        }
+    }
+}
+
+/// Handles a request of `peer`, the peer id of the sender.
+fn handle_request(
+    request: horcrust_msg_request::Request,
+    peer: &str,
+    tenants: &Tenants,
+    db: &Mutex<SharesDatabase>,
+    servers: Vec<String>,
+    transport: &Transport,
+    server_identities: &ServerIdentities,
+) -> HorcrustMsgResponse {
+    // a client taking part in a refresh or a reshare could corrupt, or delete, the shares.
+    if is_server_request(&request) && !server_identities.contains(peer) {
+        return msg_unauthorized_response("Only servers can send this request.");
+    }
+    // the requests of a tenant's clients only reach the tenant's keys.
+    let tenant = tenants.tenant_of(peer);
+    let request = match tenant {
+        Some(tenant) => match tenant.scope(request) {
+            Ok(request) => request,
+            Err(err) => return msg_error_response(&format!("{err}")),
+        },
+        // the other peers would reach the keys of every tenant.
+        None if !tenants.is_empty() && !is_server_request(&request) => {
+            return msg_unauthorized_response("Client is not a client of any tenant.");
+        }
+        None => request,
+    };
+    match request {
+        horcrust_msg_request::Request::PutShare(put_share) => {
+            // the share itself is never logged.
+            info!(
+                "Received put share request for key '{}': share index {}, {} bytes, {} bytes of commitments",
+                put_share.key,
+                put_share.index,
+                put_share.share.len(),
+                put_share.commitments.len()
+            );
+            if let Err(err) = validate_key(&put_share.key) {
+                return msg_error_response(&format!("Invalid key: {err}"));
+            }
+            let scheme = put_share.scheme.unwrap_or_default();
+            // the scheme is needed to refresh the share later on.
+            match BytesSecretSharing::from_scheme(&scheme) {
+                Ok(secret_sharing) => {
+                    if let Err(err) =
+                        secret_sharing.validate_share(&put_share.share, &put_share.commitments)
+                    {
+                        return msg_error_response(&format!("Invalid share: {err}"));
+                    }
+                    // the refresher splits the refreshers among all the servers.
+                    if scheme.threshold as usize > servers.len() {
+                        return msg_error_response(&format!(
+                            "Threshold {} is higher than the {} servers, the share couldn't be refreshed.",
+                            scheme.threshold,
+                            servers.len()
+                        ));
+                    }
+                    // this overwrites whatever was there before
+                    let mut db_lock = lock_db(db);
+                    let existing = db_lock.get_stored(&put_share.key).map(|stored| stored.acl);
+                    if let Some(acl) = &existing {
+                        if !can_access(acl, peer, Access::Write) {
+                            return msg_unauthorized_response(&format!(
+                                "Client is not allowed to replace key '{}'.",
+                                put_share.key
+                            ));
+                        }
+                    }
+                    // only the owner can change the ACL of a key, the writers keep it.
+                    let acl = match existing {
+                        Some(acl) if !acl.owner.is_empty() && peer != acl.owner => acl,
+                        _ => match new_acl(peer, put_share.acl.unwrap_or_default()) {
+                            Ok(acl) => acl,
+                            Err(err) => return msg_error_response(&format!("{err:#}")),
+                        },
+                    };
+                    if let Some(tenant) = tenant {
+                        let share_len = put_share.share.len();
+                        if let Err(err) = tenant.check_quotas(&db_lock, &put_share.key, share_len) {
+                            return msg_error_response(&format!("{err}"));
+                        }
+                    }
+                    // the share index is the position of this server in the sorted list.
+                    let mut holders = servers;
+                    holders.sort();
+                    let stored = StoredShare {
+                        share: put_share.share,
+                        scheme,
+                        commitments: put_share.commitments,
+                        index: put_share.index,
+                        epoch: 0,
+                        refresh_interval: refresh_interval_from_secs(
+                            put_share.refresh_interval_secs,
+                        ),
+                        stored_at: unix_time(),
+                        refreshed_at: 0,
+                        acl,
+                        servers: holders,
+                    };
+                    match db_lock.insert(put_share.key, stored) {
+                        Ok(()) => msg_success_response(),
+                        Err(err) => msg_error_response(&format!("Failed to store: {err:#}")),
+                    }
+                }
+                Err(err) => msg_error_response(&format!("Invalid sharing scheme: {err}")),
+            }
+        }
+        horcrust_msg_request::Request::GetShare(get_share) => {
+            info!("Received get share request for key '{}'", get_share.key);
+            let db_lock = lock_db(db);
+            match db_lock.get_stored(&get_share.key) {
+                Some(stored) if !can_access(&stored.acl, peer, Access::Read) => {
+                    msg_unauthorized_response(&format!(
+                        "Client is not allowed to read key '{}'.",
+                        get_share.key
+                    ))
+                }
+                Some(stored) => msg_share_response(
+                    stored.share,
+                    stored.scheme,
+                    stored.commitments,
+                    stored.index,
+                    stored.epoch,
+                ),
+                None => msg_error_response("Key not found. Use store-key to store a key first."),
+            }
+        }
+        horcrust_msg_request::Request::ListKeys(list) => {
+            info!("Received list keys request: {:?}", list);
+            let limit = match list.limit as usize {
+                0 => DEFAULT_LIST_LIMIT,
+                limit => limit.min(MAX_LIST_LIMIT),
+            };
+            let (page, next) = lock_db(db).list(&list.prefix, &list.start, limit);
+            // the clients of a tenant get the keys of its keyspace, without the tenant's name.
+            let client_key = |key: &str| match tenant {
+                Some(tenant) => tenant.client_key(key).map(str::to_string),
+                None => Some(key.to_string()),
+            };
+            // everything but the shares, of the keys the peer can access.
+            let keys = page
+                .into_iter()
+                .filter(|(_, stored)| {
+                    [Access::Read, Access::Write]
+                        .into_iter()
+                        .any(|access| can_access(&stored.acl, peer, access))
+                })
+                .filter_map(|(key, stored)| {
+                    Some(KeyInfo {
+                        key: client_key(&key)?,
+                        scheme: Some(stored.scheme),
+                        index: stored.index,
+                        epoch: stored.epoch,
+                        stored_at: stored.stored_at,
+                        refreshed_at: stored.refreshed_at,
+                        refresh_interval_secs: refresh_interval_secs(stored.refresh_interval),
+                        acl: Some(stored.acl),
+                    })
+                })
+                .collect();
+            msg_list_keys_response(keys, next.and_then(|next| client_key(&next)))
+        }
+        horcrust_msg_request::Request::DeleteShare(delete) => {
+            info!("Received delete share request for key '{}'", delete.key);
+            let mut db_lock = lock_db(db);
+            if let Some(stored) = db_lock.get_stored(&delete.key) {
+                if !can_access(&stored.acl, peer, Access::Write) {
+                    return msg_unauthorized_response(&format!(
+                        "Client is not allowed to delete key '{}'.",
+                        delete.key
+                    ));
+                }
+            }
+            // deleting a key that is not there succeeds, so a failed deletion can be retried.
+            match db_lock.delete(&delete.key) {
+                Ok(_) => msg_success_response(),
+                Err(err) => msg_error_response(&format!("Failed to delete: {err:#}")),
+            }
+        }
+        horcrust_msg_request::Request::Refresh(refresh) => {
+            // nor are the refreshers, that would reveal the new shares together with the old ones.
+            info!(
+                "Received refresh request {} for {} keys, share index {}",
+                refresh.round_id,
+                refresh.key.len(),
+                refresh.index
+            );
+            match stage_refresh(&mut lock_db(db), refresh) {
+                Ok(failed) => {
+                    let (failed_key, error) = failed.into_iter().unzip();
+                    msg_refresh_staged_response(failed_key, error)
+                }
+                Err(err) => msg_error_response(&format!("Failed to refresh: {err}")),
+            }
+        }
+        horcrust_msg_request::Request::RefreshLock(lock) => {
+            info!("Received refresh lock request: {:?}", lock);
+            let mut db = lock_db(db);
+            if lock.release {
+                db.release_refresh_lease(lock.holder);
+                return msg_success_response();
+            }
+            match db.acquire_refresh_lease(lock.holder, Duration::from_millis(lock.lease_ms)) {
+                Ok(()) => msg_success_response(),
+                Err(err) => msg_error_response(&format!("Failed to lock: {err}")),
+            }
+        }
+        horcrust_msg_request::Request::RefreshCommit(commit) => {
+            info!("Received refresh commit request: {:?}", commit);
+            let mut db = lock_db(db);
+            let result = if commit.commit {
+                db.commit_refresh(commit.round_id, &commit.skipped_key)
+            } else {
+                db.abort_refresh(commit.round_id)
+            };
+            match result {
+                Ok(()) => msg_success_response(),
+                Err(err) => msg_error_response(&format!("Failed to commit the refresh: {err:#}")),
+            }
+        }
+        horcrust_msg_request::Request::RefreshStatus(status) => {
+            info!("Received refresh status request: {:?}", status);
+            match lock_db(db).committed_round(status.round_id) {
+                Some(committed) => msg_refresh_status_response(true, committed.skipped.clone()),
+                None => msg_refresh_status_response(false, vec![]),
+            }
+        }
+        horcrust_msg_request::Request::Reshare(reshare) => {
+            info!("Received reshare request: {:?}", reshare);
+            // the lock is released before resharing, the servers talk to each other meanwhile.
+            let acl = lock_db(db)
+                .get_stored(&reshare.key)
+                .map(|stored| stored.acl);
+            if let Some(acl) = acl {
+                if !can_access(&acl, peer, Access::Owner) {
+                    return msg_unauthorized_response(&format!(
+                        "Client is not allowed to reshare key '{}'.",
+                        reshare.key
+                    ));
+                }
+            }
+            match crate::reshare(db, servers, reshare, transport, server_identities) {
+                Ok(()) => msg_success_response(),
+                Err(err) => msg_error_response(&format!("Failed to reshare: {err:#}")),
+            }
+        }
+        horcrust_msg_request::Request::ReshareSplit(split) => {
+            info!("Received reshare split request for key: {}", split.key);
+            match crate::reshare_split(db, split, transport, server_identities) {
+                Ok(()) => msg_success_response(),
+                Err(err) => msg_error_response(&format!("Failed to split the share: {err:#}")),
+            }
+        }
+        horcrust_msg_request::Request::ReshareContribution(contribution) => {
+            info!(
+                "Received reshare sub-share for key {} from share index {}",
+                contribution.key, contribution.from_index
+            );
+            match crate::reshare_contribution(db, contribution) {
+                Ok(()) => msg_success_response(),
+                Err(err) => msg_error_response(&format!("Failed to keep the sub-share: {err:#}")),
+            }
+        }
+        horcrust_msg_request::Request::ReshareStage(stage) => {
+            info!("Received reshare stage request: {:?}", stage);
+            match crate::reshare_stage(db, stage, tenants) {
+                Ok(()) => msg_success_response(),
+                Err(err) => msg_error_response(&format!("Failed to stage the reshare: {err:#}")),
+            }
+        }
+    }
+}
+
+/// True for the requests the servers send each other, during refreshes and reshares.
+fn is_server_request(request: &horcrust_msg_request::Request) -> bool {
+    use horcrust_msg_request::Request;
+    match request {
+        Request::Refresh(_)
+        | Request::RefreshLock(_)
+        | Request::RefreshCommit(_)
+        | Request::RefreshStatus(_)
+        | Request::ReshareSplit(_)
+        | Request::ReshareContribution(_)
+        | Request::ReshareStage(_) => true,
+        Request::PutShare(_)
+        | Request::GetShare(_)
+        | Request::ListKeys(_)
+        | Request::DeleteShare(_)
+        | Request::Reshare(_) => false,
+    }
+}
+
+/// Stages the refreshed shares of the keys of the request, to switch to them on commit. Keys that
+/// can't be refreshed are returned with the reason, the others are still staged: the coordinator
+/// leaves the failed ones out of the commit on every server.
+fn stage_refresh(
+    db: &mut SharesDatabase,
+    refresh: RefreshShareRequest,
+) -> Result<Vec<(HorcrustStoreKey, String)>> {
+    ensure!(
+        db.has_refresh_lease(refresh.holder),
+        "The refresh lease is held by another server."
+    );
+    let mut refreshed = vec![];
+    let mut failed = vec![];
+    for (i, (key, r)) in refresh.key.into_iter().zip(refresh.random).enumerate() {
+        let update = refresh.commitments.get(i).map(Vec::as_slice);
+        match refresh_key(db, &key, &r, update, refresh.index) {
+            Ok(share) => refreshed.push((key, Some(share))),
+            Err(err) => failed.push((key, format!("{err:#}"))),
+        }
+    }
+    db.stage_refresh(
+        refresh.round_id,
+        refreshed,
+        refresh.expires_at,
+        refresh.round_servers,
+    )?;
+    Ok(failed)
+}
+fn refresh_key(
+    db: &SharesDatabase,
+    key: &str,
+    r: &[u8],
+    update: Option<&[u8]>,
+    index: u32,
+) -> Result<StoredShare> {
+    // e.g. removed by a reshare.
+    let stored = db
+        .get_stored(key)
+        .ok_or_else(|| anyhow!("Key '{key}' not found."))?;
+    ensure!(
+        !db.is_staged(key),
+        "Key '{key}' has another refresh in progress."
+    );
+    // refreshers generated for another index would corrupt the shares.
+    ensure!(
+        stored.index == index,
+        "Refreshers were generated for share index {index}, but key '{key}' has a different one."
+    );
+    let secret_sharing = BytesSecretSharing::from_scheme(&stored.scheme)?;
+    let share = secret_sharing.refresh_share(r, &stored.share)?;
+    // the commitments must follow the share, or it won't verify anymore.
+    let commitments = if stored.scheme.verifiable {
+        let update = update.ok_or_else(|| anyhow!("Missing commitments update for key '{key}'"))?;
+        secret_sharing.refresh_commitments(&stored.commitments, update)?
+    } else {
+        stored.commitments.clone()
+    };
+    Ok(StoredShare {
+        share,
+        commitments,
+        epoch: stored.epoch + 1,
+        ..stored
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::refresher::{acquire_lease, release_lease, resolve_rounds, send_to_server};
+    use crate::{spawn_refresher, RefreshPolicy, Tenant};
+    use horcrust::horcrust_msg_response::Response;
+    use horcrust::{
+        default_modulus, msg_delete_share_request, msg_list_keys_request, msg_put_share_request,
+        msg_refresh_commit_request, msg_refresh_lock_request, msg_refresh_share_request,
+        msg_refresh_status_request, msg_reshare_contribution_request, msg_reshare_request,
+        msg_reshare_split_request, msg_reshare_stage_request, msg_retrieve_secret_request,
+        public_identity_to_hex, AdditiveSecretSharing, AllowedPeers, ConnectionHandler,
+        ConnectionPool, ErrorCode, HandshakeConfig, HorcrustMsgError, Identity, KeyAcl,
+        ReshareContributionRequest, ReshareSplitRequest, ReshareStageRequest, SecretSharing,
+        ShamirSecretSharing, SharingScheme, TcpConnectionHandler,
+    };
+    use rand::{random, Rng};
+    use std::io::Write;
+
+    /// A share of a short secret with the default additive scheme: a single chunk.
+    fn share(byte: u8) -> Vec<u8> {
+        vec![byte; 32]
+    }
+
+    fn client_request(
+        connection: &mut TcpConnectionHandler,
+        request: HorcrustMsgRequest,
+    ) -> Result<HorcrustMsgResponse> {
+        connection.send(request)?;
+        connection.receive()
+    }
+
+    /// The share of a response to a retrieve request.
+    fn share_of(response: HorcrustMsgResponse) -> Vec<u8> {
+        match response.response {
+            Some(Response::ShareResponse(share)) => share.share,
+            response => panic!("Unexpected response: {response:?}"),
+        }
+    }
+
+    fn is_error(response: &HorcrustMsgResponse) -> bool {
+        matches!(
+            response.response,
+            Some(Response::Error(HorcrustMsgError { error: true, .. }))
+        )
+    }
+
+    /// The code of an error response.
+    fn error_code(response: &HorcrustMsgResponse) -> ErrorCode {
+        match &response.response {
+            Some(Response::Error(error)) => error.code(),
+            response => panic!("Unexpected response: {response:?}"),
+        }
+    }
+
+    /// Stores one share per server, in the order of the servers.
+    fn store_shares(
+        connections: &mut ConnectionPool,
+        servers: &[String],
+        key: &str,
+        scheme: &SharingScheme,
+        shares: Vec<Vec<u8>>,
+    ) -> Result<()> {
+        for (index, (server, share)) in servers.iter().zip(shares).enumerate() {
+            let put = msg_put_share_request(
+                key.into(),
+                index as u32,
+                share,
+                scheme.clone(),
+                vec![],
+                0,
+                KeyAcl::default(),
+            );
+            assert_eq!(connections.request(server, put)?, msg_success_response());
+        }
+        Ok(())
+    }
+
+    type Servers = (
+        Vec<String>,
+        Vec<Arc<Mutex<SharesDatabase>>>,
+        Transport,
+        Vec<Transport>,
+    );
+    /// Starts `count` servers that know each other, each one with its own database. Returns their
+    /// addresses, sorted like the share indexes, their databases, the transport of a client they
+    /// accept, and their own transports, to talk to the others as a server.
+    async fn start_servers(count: usize) -> Result<Servers> {
+        let client = Identity::generate();
+        let identities: Vec<Identity> = (0..count).map(|_| Identity::generate()).collect();
+        let servers_peers = AllowedPeers::new(identities.iter().map(Identity::public));
+        let server_identities = ServerIdentities::new(
+            identities
+                .iter()
+                .map(|identity| public_identity_to_hex(&identity.public())),
+        )?;
+        let peers = AllowedPeers::new(
+            identities
+                .iter()
+                .map(Identity::public)
+                .chain([client.public()]),
+        );
+        let mut listeners = vec![];
+        for _ in 0..count {
+            listeners.push(TcpListener::bind("127.0.0.1:0").await?);
+        }
+        let addresses = listeners
+            .iter()
+            .map(|listener| Ok(listener.local_addr()?.to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        let mut servers = vec![];
+        for ((listener, identity), address) in listeners.into_iter().zip(identities).zip(&addresses)
+        {
+            let db = Arc::new(Mutex::new(SharesDatabase::new()));
+            let transport = Transport::Handshake(HandshakeConfig::new(identity, peers.clone()));
+            let tenants = Tenants::default();
+            tokio::spawn(run(
+                listener,
+                db.clone(),
+                addresses.clone(),
+                transport.clone(),
+                tenants,
+                server_identities.clone(),
+            ));
+            servers.push((address.clone(), (db, transport)));
+        }
+        servers.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let (addresses, servers): (_, Vec<_>) = servers.into_iter().unzip();
+        let (dbs, transports) = servers.into_iter().unzip();
+        let client = Transport::Handshake(HandshakeConfig::new(client, servers_peers));
+        Ok((addresses, dbs, client, transports))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_garbage_doesnt_stop_the_server() -> Result<()> {
+        let (server, client) = (Identity::generate(), Identity::generate());
+        let peers = AllowedPeers::new([server.public(), client.public()]);
+        let client_config = HandshakeConfig::new(client, peers.clone());
+        let transport = Transport::Handshake(HandshakeConfig::new(server, peers));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let db = Arc::new(Mutex::new(SharesDatabase::new()));
+        let (tenants, server_identities) = (Tenants::default(), ServerIdentities::default());
+        tokio::spawn(run(
+            listener,
+            db,
+            vec![],
+            transport,
+            tenants,
+            server_identities,
+        ));
+
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut rng = rand::thread_rng();
+            // port scanners, truncated handshakes and random bytes.
+            for _ in 0..50 {
+                let mut socket = std::net::TcpStream::connect(address)?;
+                let len = [0, 1, 63, 64, 128, rng.gen_range(0..1024)][rng.gen_range(0..6)];
+                let garbage: Vec<u8> = (0..len).map(|_| random()).collect();
+                // the server may close the connection before reading everything.
+                let _ = socket.write_all(&garbage);
+            }
+            // garbage after a valid handshake.
+            let socket = std::net::TcpStream::connect(address)?;
+            let mut raw = socket.try_clone()?;
+            let mut connection = TcpConnectionHandler::new(socket, &client_config)?;
+            raw.write_all(&[0, 0, 0, 4, 1, 2, 3, 4])?;
+            let get = msg_retrieve_secret_request("1".into());
+            assert!(client_request(&mut connection, get).is_err());
+
+            // the server still works, and answers empty requests with an error.
+            let socket = std::net::TcpStream::connect(address)?;
+            let mut connection = TcpConnectionHandler::new(socket, &client_config)?;
+            let response = client_request(&mut connection, HorcrustMsgRequest::default())?;
+            assert_eq!(response, msg_error_response("Empty request."));
+            let scheme = AdditiveSecretSharing::default().scheme();
+            // keys are validated.
+            let put = msg_put_share_request(
+                "a//b".into(),
+                0,
+                vec![42],
+                scheme.clone(),
+                vec![],
+                0,
+                KeyAcl::default(),
+            );
+            assert_eq!(
+                client_request(&mut connection, put)?,
+                msg_error_response("Invalid key: The levels of the key 'a//b' can't be empty.")
+            );
+            // and so are the shares.
+            let put = |share| {
+                msg_put_share_request(
+                    "a/b".into(),
+                    0,
+                    share,
+                    scheme.clone(),
+                    vec![],
+                    0,
+                    KeyAcl::default(),
+                )
+            };
+            assert_eq!(
+                client_request(&mut connection, put(vec![42]))?,
+                msg_error_response("Invalid share: Share length 1 is not valid.")
+            );
+            assert_eq!(
+                client_request(&mut connection, put(share(42)))?,
+                msg_success_response()
+            );
+            // and so is the threshold, that the servers must be able to refresh.
+            let put_shamir = msg_put_share_request(
+                "a/c".into(),
+                0,
+                share(42),
+                ShamirSecretSharing::new(2, default_modulus()).scheme(),
+                vec![],
+                0,
+                KeyAcl::default(),
+            );
+            assert_eq!(
+                client_request(&mut connection, put_shamir)?,
+                msg_error_response(
+                    "Threshold 2 is higher than the 0 servers, the share couldn't be refreshed."
+                )
+            );
+            let get = msg_retrieve_secret_request("a/b".into());
+            let response = client_request(&mut connection, get)?;
+            let stored = match response.response {
+                Some(horcrust::horcrust_msg_response::Response::ShareResponse(stored)) => stored,
+                response => panic!("Unexpected response: {response:?}"),
+            };
+            assert_eq!(stored.share, share(42));
+            Ok(())
+        })
+        .await?
+    }
+
+    #[test]
+    fn test_tenants_isolation() {
+        let db = Mutex::new(SharesDatabase::new());
+        let identity = Identity::generate();
+        let transport = Transport::Handshake(HandshakeConfig::new(identity, AllowedPeers::new([])));
+        let [payments, billing, other] =
+            [(); 3].map(|_| public_identity_to_hex(&Identity::generate().public()));
+        let tenant = |name: &str, client: &String| Tenant {
+            name: name.into(),
+            clients: vec![client.clone()],
+            max_keys: Some(1),
+            max_share_size: Some(32),
+        };
+        let tenants = Tenants::new(vec![
+            tenant("payments", &payments),
+            tenant("billing", &billing),
+        ])
+        .unwrap();
+        let handle = |request: HorcrustMsgRequest, peer: &str| {
+            handle_request(
+                request.request.unwrap(),
+                peer,
+                &tenants,
+                &db,
+                vec![],
+                &transport,
+                &ServerIdentities::default(),
+            )
+        };
+        let scheme = AdditiveSecretSharing::default().scheme();
+        let put = |key: &str, share| {
+            msg_put_share_request(
+                key.into(),
+                0,
+                share,
+                scheme.clone(),
+                vec![],
+                0,
+                KeyAcl::default(),
+            )
+        };
+        let get = |key: &str| msg_retrieve_secret_request(key.into());
+
+        let success = msg_success_response();
+        assert_eq!(handle(put("db", share(1)), &payments), success);
+        // the same key of another tenant is a different one.
+        assert_eq!(handle(put("db", share(2)), &billing), success);
+        assert_eq!(share_of(handle(get("db"), &payments)), share(1));
+        assert!(is_error(&handle(get("payments/db"), &billing)));
+        // peers that are not clients of a tenant can't access any key.
+        assert_eq!(
+            error_code(&handle(get("payments/db"), &other)),
+            ErrorCode::Unauthorized
+        );
+        assert_eq!(
+            error_code(&handle(put("billing/db", share(3)), &other)),
+            ErrorCode::Unauthorized
+        );
+        assert_eq!(share_of(handle(get("db"), &billing)), share(2));
+
+        // the quotas allow replacing the key, but not adding more or bigger ones.
+        assert!(is_error(&handle(put("other", share(1)), &payments)));
+        assert!(is_error(&handle(
+            put("db", [share(1), share(2)].concat()),
+            &payments
+        )));
+        assert_eq!(handle(put("db", share(3)), &payments), success);
+
+        let list = msg_list_keys_request(String::new(), String::new(), 0);
+        match handle(list, &payments).response {
+            Some(Response::ListKeys(list)) => {
+                let keys: Vec<_> = list.keys.into_iter().map(|info| info.key).collect();
+                assert_eq!(keys, vec!["db"]);
+            }
+            response => panic!("Unexpected response: {response:?}"),
+        }
+        // clients can't take part in refreshes.
+        let commit = msg_refresh_commit_request(1, true, vec![]);
+        assert!(is_error(&handle(commit, &payments)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_clients_cant_send_server_requests() -> Result<()> {
+        let (servers, dbs, client, _) = start_servers(2).await?;
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut connections = ConnectionPool::new(client);
+            let scheme = AdditiveSecretSharing::default().scheme();
+            let put = msg_put_share_request(
+                "db".into(),
+                0,
+                share(1),
+                scheme,
+                vec![],
+                0,
+                KeyAcl::default(),
+            );
+            assert_eq!(
+                connections.request(&servers[0], put)?,
+                msg_success_response()
+            );
+            // the client is an allowed peer, but it can't take the lease, stage a refresh of its
+            // own, commit it or remove the share.
+            let holder = random();
+            let requests = [
+                msg_refresh_lock_request(holder, 10_000),
+                msg_refresh_share_request(RefreshShareRequest {
+                    key: vec!["db".into()],
+                    random: vec![share(2)],
+                    round_id: 1,
+                    holder,
+                    expires_at: unix_time() + 10,
+                    ..Default::default()
+                }),
+                msg_refresh_commit_request(1, true, vec![]),
+                msg_refresh_status_request(1),
+                msg_reshare_stage_request(ReshareStageRequest {
+                    key: "db".into(),
+                    remove: true,
+                    round_id: 1,
+                    holder,
+                    expires_at: unix_time() + 10,
+                    ..Default::default()
+                }),
+            ];
+            for request in requests {
+                let response = connections.request(&servers[0], request)?;
+                assert_eq!(error_code(&response), ErrorCode::Unauthorized);
+            }
+            let db = lock_db(&dbs[0]);
+            assert!(!db.is_staged("db"));
+            assert_eq!(db.get_stored("db").unwrap().share, share(1));
+            Ok(())
+        })
+        .await?
+    }
+
+    #[test]
+    fn test_acl() {
+        let db = Mutex::new(SharesDatabase::new());
+        let identity = Identity::generate();
+        let transport = Transport::Handshake(HandshakeConfig::new(identity, AllowedPeers::new([])));
+        let [owner, reader, writer, other] =
+            [(); 4].map(|_| public_identity_to_hex(&Identity::generate().public()));
+        let handle = |request: HorcrustMsgRequest, peer: &str| {
+            handle_request(
+                request.request.unwrap(),
+                peer,
+                &Tenants::default(),
+                &db,
+                vec![],
+                &transport,
+                &ServerIdentities::default(),
+            )
+        };
+        let scheme = AdditiveSecretSharing::default().scheme();
+        let put = |share, acl| {
+            msg_put_share_request("db".into(), 0, share, scheme.clone(), vec![], 0, acl)
+        };
+        let get = || msg_retrieve_secret_request("db".into());
+        let acl = KeyAcl {
+            owner: String::new(),
+            readers: vec![reader.clone()],
+            writers: vec![writer.clone()],
+        };
+
+        let success = msg_success_response();
+        assert_eq!(handle(put(share(1), acl.clone()), &owner), success);
+        assert_eq!(share_of(handle(get(), &owner)), share(1));
+        assert_eq!(error_code(&handle(get(), &other)), ErrorCode::Unauthorized);
+        assert_eq!(
+            error_code(&handle(put(share(2), KeyAcl::default()), &reader)),
+            ErrorCode::Unauthorized
+        );
+        // writers replace the share, but can't change the ACL.
+        assert_eq!(handle(put(share(2), KeyAcl::default()), &writer), success);
+        assert_eq!(error_code(&handle(get(), &writer)), ErrorCode::Unauthorized);
+        assert_eq!(share_of(handle(get(), &reader)), share(2));
+        // other clients don't even see the key.
+        let list = || msg_list_keys_request(String::new(), String::new(), 0);
+        for (peer, expected) in [(&other, 0), (&reader, 1), (&writer, 1)] {
+            match handle(list(), peer).response {
+                Some(Response::ListKeys(list)) => assert_eq!(list.keys.len(), expected),
+                response => panic!("Unexpected response: {response:?}"),
+            }
+        }
+        // only the owner can move the key to other servers.
+        let reshare = || msg_reshare_request("db".into(), vec![], 2);
+        assert_eq!(
+            error_code(&handle(reshare(), &writer)),
+            ErrorCode::Unauthorized
+        );
+        assert_ne!(
+            error_code(&handle(reshare(), &owner)),
+            ErrorCode::Unauthorized
+        );
+        let delete = || msg_delete_share_request("db".into());
+        assert_eq!(
+            error_code(&handle(delete(), &reader)),
+            ErrorCode::Unauthorized
+        );
+        assert_eq!(handle(delete(), &owner), success);
+    }
+
+    #[test]
+    fn test_reshare_sub_shares() {
+        let db = Mutex::new(SharesDatabase::new());
+        let identity = Identity::generate();
+        let transport = Transport::Handshake(HandshakeConfig::new(identity, AllowedPeers::new([])));
+        let [client, server] =
+            [(); 2].map(|_| public_identity_to_hex(&Identity::generate().public()));
+        let tenants = Tenants::new(vec![Tenant {
+            name: "payments".into(),
+            clients: vec![client.clone()],
+            max_keys: Some(1),
+            max_share_size: None,
+        }])
+        .unwrap();
+        let server_identities = ServerIdentities::new([server.clone()]).unwrap();
+        let handle = |request: HorcrustMsgRequest, peer: &str| {
+            handle_request(
+                request.request.unwrap(),
+                peer,
+                &tenants,
+                &db,
+                vec![],
+                &transport,
+                &server_identities,
+            )
+        };
+        let scheme = AdditiveSecretSharing::default().scheme();
+        let put = msg_put_share_request(
+            "db".into(),
+            0,
+            share(1),
+            scheme.clone(),
+            vec![],
+            0,
+            KeyAcl::default(),
+        );
+        assert_eq!(handle(put, &client), msg_success_response());
+
+        let holder = random();
+        let contribution = || {
+            msg_reshare_contribution_request(ReshareContributionRequest {
+                key: "payments/other".into(),
+                contributions: 1,
+                share: share(2),
+                scheme: Some(scheme.clone()),
+                round_id: 1,
+                holder,
+                ..Default::default()
+            })
+        };
+        // sub-shares are only accepted from the coordinator holding the lease.
+        assert!(is_error(&handle(contribution(), &server)));
+        lock_db(&db)
+            .acquire_refresh_lease(holder, Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(handle(contribution(), &server), msg_success_response());
+        // the reshared key would be a second key of the tenant.
+        let stage = msg_reshare_stage_request(ReshareStageRequest {
+            key: "payments/other".into(),
+            round_id: 1,
+            holder,
+            expires_at: unix_time() + 10,
+            ..Default::default()
+        });
+        match handle(stage, &server).response {
+            Some(Response::Error(error)) => assert!(error.error_string.contains("Quota exceeded")),
+            response => panic!("Unexpected response: {response:?}"),
+        }
+        assert!(!lock_db(&db).is_staged("payments/other"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reshare() -> Result<()> {
+        let (servers, dbs, client, transports) = start_servers(3).await?;
+        // a client listening for sub-shares, as if it were a server.
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let impostor = listener.local_addr()?.to_string();
+        let impostor_db = Arc::new(Mutex::new(SharesDatabase::new()));
+        tokio::spawn(run(
+            listener,
+            impostor_db.clone(),
+            vec![],
+            client.clone(),
+            Tenants::default(),
+            ServerIdentities::default(),
+        ));
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut connections = ConnectionPool::new(client);
+            let shamir = ShamirSecretSharing::new(2, default_modulus());
+            let scheme = shamir.scheme();
+            let secret_sharing = BytesSecretSharing::new(Box::new(shamir))?;
+            let secret = b"reshared secret".to_vec();
+            let shares = secret_sharing.split(3, &secret)?;
+            store_shares(&mut connections, &servers, "db", &scheme, shares)?;
+            let check = |new_servers: &[String], epoch| -> Result<()> {
+                let mut shares = vec![];
+                for (server, db) in servers.iter().zip(&dbs) {
+                    let db = lock_db(db);
+                    assert!(!db.is_staged("db"));
+                    let Some(stored) = db.get_stored("db") else {
+                        assert!(!new_servers.contains(server));
+                        continue;
+                    };
+                    assert_eq!(stored.epoch, epoch);
+                    assert_eq!(stored.servers, new_servers);
+                    shares.push((stored.index as usize, stored.share));
+                }
+                assert_eq!(shares.len(), new_servers.len());
+                assert_eq!(secret_sharing.combine(shares)?, secret);
+                Ok(())
+            };
+
+            // the last server leaves: the new shares are committed, the old one is removed.
+            let reshare = msg_reshare_request("db".into(), servers[..2].to_vec(), 2);
+            assert_eq!(
+                connections.request(&servers[0], reshare)?,
+                msg_success_response()
+            );
+            check(&servers[..2], 1)?;
+            // a reshare to a server that is not reachable changes nothing.
+            let unreachable = vec![servers[0].clone(), "127.0.0.1:1".into()];
+            let reshare = msg_reshare_request("db".into(), unreachable, 2);
+            match connections.request(&servers[0], reshare)?.response {
+                Some(Response::Error(HorcrustMsgError { error: true, .. })) => {}
+                response => panic!("Unexpected response: {response:?}"),
+            }
+            check(&servers[..2], 1)?;
+            // sub-shares are only sent to servers, neither by the coordinator nor by the servers
+            // splitting their shares.
+            let with_impostor = vec![servers[0].clone(), impostor];
+            let not_a_server = "is not one of the server identities";
+            let reshare = msg_reshare_request("db".into(), with_impostor.clone(), 2);
+            match connections.request(&servers[0], reshare)?.response {
+                Some(Response::Error(HorcrustMsgError {
+                    error: true,
+                    error_string,
+                    ..
+                })) => assert!(error_string.contains(not_a_server)),
+                response => panic!("Unexpected response: {response:?}"),
+            }
+            let mut server_connections = ConnectionPool::new(transports[0].clone());
+            let holder = random();
+            let lease = Duration::from_secs(10);
+            assert!(acquire_lease(
+                &mut server_connections,
+                &servers[1..2],
+                holder,
+                lease
+            ));
+            let split = msg_reshare_split_request(ReshareSplitRequest {
+                key: "db".into(),
+                servers: with_impostor,
+                scheme: Some(scheme.clone()),
+                indices: vec![0, 1],
+                index: 1,
+                epoch: 1,
+                holder,
+                round_id: 1,
+            });
+            let err = send_to_server(&mut server_connections, &servers[1], split).unwrap_err();
+            assert!(err.to_string().contains(not_a_server));
+            release_lease(&mut server_connections, &servers[1..2], holder);
+            assert!(lock_db(&impostor_db)
+                .take_reshare_contributions("db")
+                .is_empty());
+            check(&servers[..2], 1)?;
+            // the lease was released, so the server can join again.
+            let reshare = msg_reshare_request("db".into(), servers.clone(), 2);
+            assert_eq!(
+                connections.request(&servers[1], reshare)?,
+                msg_success_response()
+            );
+            check(&servers, 2)
+        })
+        .await?
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reshare_while_refreshing() -> Result<()> {
+        let (servers, dbs, client, transports) = start_servers(3).await?;
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut connections = ConnectionPool::new(client);
+            let shamir = ShamirSecretSharing::new(2, default_modulus());
+            let scheme = shamir.scheme();
+            let secret_sharing = BytesSecretSharing::new(Box::new(shamir))?;
+            let secret = b"reshared secret".to_vec();
+            let shares = secret_sharing.split(3, &secret)?;
+            store_shares(&mut connections, &servers, "db", &scheme, shares)?;
+            // every server refreshes the key all the time, taking the lease for every round.
+            let policy = RefreshPolicy {
+                interval: Duration::ZERO,
+                jitter: Duration::from_millis(100),
+                ..Default::default()
+            };
+            for (db, transport) in dbs.iter().zip(&transports) {
+                spawn_refresher(
+                    servers.clone(),
+                    db.clone(),
+                    transport.clone(),
+                    policy.clone(),
+                );
+            }
+            // the reshare waits for a round to be over.
+            let reshare = msg_reshare_request("db".into(), servers[..2].to_vec(), 2);
+            assert_eq!(
+                connections.request(&servers[0], reshare)?,
+                msg_success_response()
+            );
+            // and the new shares are refreshed like the others.
+            let reshared = lock_db(&dbs[0]).get_stored("db").unwrap().epoch;
+            let deadline = std::time::Instant::now() + Duration::from_secs(30);
+            loop {
+                assert!(
+                    std::time::Instant::now() < deadline,
+                    "The reshared key was not refreshed."
+                );
+                std::thread::sleep(Duration::from_millis(200));
+                let locked: Vec<_> = dbs.iter().map(|db| lock_db(db)).collect();
+                assert!(locked[2].get_stored("db").is_none());
+                let stored: Vec<StoredShare> = locked[..2]
+                    .iter()
+                    .map(|db| db.get_stored("db").unwrap())
+                    .collect();
+                assert_eq!(stored[0].servers, servers[..2]);
+                let epoch = stored[0].epoch;
+                if stored.iter().any(|s| s.epoch != epoch) {
+                    continue;
+                }
+                let shares = stored
+                    .into_iter()
+                    .map(|s| (s.index as usize, s.share))
+                    .collect();
+                assert_eq!(secret_sharing.combine(shares)?, secret);
+                if epoch > reshared {
+                    return Ok(());
+                }
+            }
+        })
+        .await?
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_coordinator_crash() -> Result<()> {
+        let (servers, dbs, client, transports) = start_servers(3).await?;
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut clients = ConnectionPool::new(client);
+            let additive = AdditiveSecretSharing::default();
+            let scheme = additive.scheme();
+            let secret_sharing = BytesSecretSharing::new(Box::new(additive))?;
+            let secret = b"refreshed secret".to_vec();
+            let shares = secret_sharing.split(3, &secret)?;
+            store_shares(&mut clients, &servers, "db", &scheme, shares)?;
+            // the coordinator runs outside of the servers, so it can stop at any point.
+            let mut connections = ConnectionPool::new(transports[0].clone());
+            let holder = random();
+            // stages a refresh round on every server, expiring in a couple of seconds.
+            let stage = |connections: &mut ConnectionPool, round_id| -> Result<()> {
+                assert!(acquire_lease(
+                    connections,
+                    &servers,
+                    holder,
+                    Duration::from_secs(10)
+                ));
+                let len = lock_db(&dbs[0]).get("db").unwrap().len();
+                let (refreshers, _) =
+                    secret_sharing.generate_refreshers_with_commitments(3, len)?;
+                let expires_at = unix_time() + 2;
+                for (index, (server, r)) in servers.iter().zip(refreshers).enumerate() {
+                    let refresh = msg_refresh_share_request(RefreshShareRequest {
+                        key: vec!["db".into(), "missing".into()],
+                        random: vec![r.clone(), r],
+                        commitments: vec![],
+                        index: index as u32,
+                        round_id,
+                        holder,
+                        expires_at,
+                        round_servers: servers.clone(),
+                    });
+                    // keys are staged one by one, the ones that fail are reported.
+                    match connections.request(server, refresh)?.response {
+                        Some(Response::RefreshStaged(staged)) => {
+                            assert_eq!(staged.failed_key, vec!["missing"]);
+                        }
+                        response => panic!("Unexpected response: {response:?}"),
+                    }
+                }
+                Ok(())
+            };
+            let check = |epoch| -> Result<()> {
+                let mut shares = vec![];
+                for db in &dbs {
+                    let db = lock_db(db);
+                    assert!(!db.is_staged("db"));
+                    let stored = db.get_stored("db").unwrap();
+                    assert_eq!(stored.epoch, epoch);
+                    shares.push((stored.index as usize, stored.share));
+                }
+                assert_eq!(secret_sharing.combine(shares)?, secret);
+                Ok(())
+            };
+            let expire = || std::thread::sleep(Duration::from_secs(3));
+
+            // the coordinator stops after the stage: the key is locked until the round expires,
+            // then the servers roll it back.
+            stage(&mut connections, 1)?;
+            assert!(lock_db(&dbs[0]).stale_keys(Duration::ZERO).is_empty());
+            expire();
+            let commit = msg_refresh_commit_request(1, true, vec![]);
+            assert!(send_to_server(&mut connections, &servers[0], commit).is_err());
+            for db in &dbs {
+                resolve_rounds(&mut connections, db, Duration::ZERO);
+            }
+            check(0)?;
+            assert_eq!(lock_db(&dbs[0]).stale_keys(Duration::ZERO), vec!["db"]);
+
+            // it stops after committing on the first server: the others commit too.
+            stage(&mut connections, 2)?;
+            let commit = msg_refresh_commit_request(2, true, vec![]);
+            send_to_server(&mut connections, &servers[0], commit)?;
+            expire();
+            for db in &dbs[1..] {
+                resolve_rounds(&mut connections, db, Duration::ZERO);
+            }
+            check(1)
+        })
+        .await?
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_refreshers() -> Result<()> {
+        let (servers, dbs, client, transports) = start_servers(3).await?;
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut connections = ConnectionPool::new(client);
+            let shamir = ShamirSecretSharing::new(2, default_modulus());
+            let scheme = shamir.scheme();
+            let secret_sharing = BytesSecretSharing::new(Box::new(shamir))?;
+            let secret = b"refreshed secret".to_vec();
+            let shares = secret_sharing.split(3, &secret)?;
+            store_shares(&mut connections, &servers, "db", &scheme, shares)?;
+            // the first two servers try to refresh the key all the time.
+            let policy = RefreshPolicy {
+                interval: Duration::ZERO,
+                jitter: Duration::from_millis(100),
+                ..Default::default()
+            };
+            for (db, transport) in dbs.iter().zip(&transports).take(2) {
+                spawn_refresher(
+                    servers.clone(),
+                    db.clone(),
+                    transport.clone(),
+                    policy.clone(),
+                );
+            }
+            // the refreshes are checked between rounds, when every server has the same epoch.
+            let deadline = std::time::Instant::now() + Duration::from_secs(30);
+            let mut checked = 0;
+            while checked < 3 {
+                assert!(
+                    std::time::Instant::now() < deadline,
+                    "The key was not refreshed."
+                );
+                std::thread::sleep(Duration::from_millis(200));
+                let locked: Vec<_> = dbs.iter().map(|db| lock_db(db)).collect();
+                let stored: Vec<StoredShare> = locked
+                    .iter()
+                    .map(|db| db.get_stored("db").unwrap())
+                    .collect();
+                let epoch = stored[0].epoch;
+                if epoch <= checked || stored.iter().any(|s| s.epoch != epoch) {
+                    continue;
+                }
+                let shares = stored
+                    .into_iter()
+                    .map(|s| (s.index as usize, s.share))
+                    .collect();
+                assert_eq!(secret_sharing.combine(shares)?, secret);
+                checked = epoch;
+            }
+            Ok(())
+        })
+        .await?
+    }
+}
@@ -0,0 +1,532 @@
+use crate::shares_db::{refresh_interval_from_secs, refresh_interval_secs};
+use crate::{MasterKey, MasterKeySource, StoredShare};
+use anyhow::{bail, ensure, Context};
+use horcrust::{
+    CommittedRoundRecord, HorcrustStoreKey, Result, StagedRefreshRecord, StagedShareRecord,
+    StoredShareRecord,
+};
+use prost::Message;
+use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition, WriteTransaction};
+use std::path::Path;
+
+/// Where a SharesDatabase keeps its shares, so they survive restarts.
+pub trait ShareStore: Send {
+    /// Every stored share, to fill the database at startup.
+    fn load(&self) -> Result<Vec<(HorcrustStoreKey, StoredShare)>>;
+    /// Stores the shares, or removes them if they are None, all or nothing. The changes are
+    /// durable once this returns.
+    fn write(&mut self, changes: Vec<(HorcrustStoreKey, Option<StoredShare>)>) -> Result<()>;
+    /// The rounds staged and not committed yet, to resume them after a restart.
+    fn load_staged(&self) -> Result<Vec<(u64, StagedRound)>>;
+    /// Durably stores a refresh, or reshare, round until it is committed or rolled back.
+    fn stage(&mut self, round_id: u64, round: StagedRound) -> Result<()>;
+    /// Drops the staged round and writes `changes`, all or nothing. `committed` is recorded if
+    /// the round was committed, see `load_committed`.
+    fn unstage(
+        &mut self,
+        round_id: u64,
+        changes: Vec<(HorcrustStoreKey, Option<StoredShare>)>,
+        committed: Option<CommittedRound>,
+    ) -> Result<()>;
+    /// The rounds committed and not forgotten yet, so the other servers of a round can still ask
+    /// about it after a restart.
+    fn load_committed(&self) -> Result<Vec<(u64, CommittedRound)>>;
+    fn forget_committed(&mut self, round_ids: &[u64]) -> Result<()>;
+}
+
+/// A refresh, or reshare, round staged by this server.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StagedRound {
+    pub shares: Vec<StagedShare>,
+    /// seconds since the unix epoch, the coordinator can't commit the round after this.
+    pub expires_at: u64,
+    /// the servers taking part in the round, they resolve it if the coordinator didn't finish it.
+    pub servers: Vec<String>,
+}
+#[derive(Clone, Debug, PartialEq)]
+pub struct StagedShare {
+    pub key: HorcrustStoreKey,
+    /// the new share, None if the commit removes the key.
+    pub share: Option<StoredShare>,
+    /// digest of the share it replaces, see `share_digest`. None if the key was not stored.
+    pub base: Option<Vec<u8>>,
+}
+/// A round committed by this server.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CommittedRound {
+    /// seconds since the unix epoch.
+    pub committed_at: u64,
+    /// the keys left out of the commit, on every server of the round.
+    pub skipped: Vec<HorcrustStoreKey>,
+}
+
+/// Keeps nothing: the shares are lost when the server stops.
+#[derive(Default)]
+pub struct MemoryStore;
+impl ShareStore for MemoryStore {
+    fn load(&self) -> Result<Vec<(HorcrustStoreKey, StoredShare)>> {
+        Ok(vec![])
+    }
+    fn write(&mut self, _changes: Vec<(HorcrustStoreKey, Option<StoredShare>)>) -> Result<()> {
+        Ok(())
+    }
+    fn load_staged(&self) -> Result<Vec<(u64, StagedRound)>> {
+        Ok(vec![])
+    }
+    fn stage(&mut self, _round_id: u64, _round: StagedRound) -> Result<()> {
+        Ok(())
+    }
+    fn unstage(
+        &mut self,
+        _round_id: u64,
+        _changes: Vec<(HorcrustStoreKey, Option<StoredShare>)>,
+        _committed: Option<CommittedRound>,
+    ) -> Result<()> {
+        Ok(())
+    }
+    fn load_committed(&self) -> Result<Vec<(u64, CommittedRound)>> {
+        Ok(vec![])
+    }
+    fn forget_committed(&mut self, _round_ids: &[u64]) -> Result<()> {
+        Ok(())
+    }
+}
+
+const SHARES: TableDefinition<&str, &[u8]> = TableDefinition::new("shares");
+const STAGED: TableDefinition<u64, &[u8]> = TableDefinition::new("staged");
+/// CommittedRoundRecord by round id. They don't contain shares, so they are not encrypted.
+const COMMITTED: TableDefinition<u64, &[u8]> = TableDefinition::new("committed");
+const METADATA: TableDefinition<&str, &[u8]> = TableDefinition::new("metadata");
+/// salt to derive the master key from a passphrase.
+const SALT: &str = "salt";
+/// an empty value encrypted with the master key, present if the shares are encrypted.
+const KEY_CHECK: &str = "key check";
+const KEY_CHECK_AAD: &[u8] = b"horcrust master key check";
+
+/// Stores the shares in a redb database file. Every write is a transaction, committed with an
+/// fsync. If there is a master key, the shares are encrypted with it.
+///
+/// redb never overwrites data in place: removed shares stay in the free pages of the file until
+/// they are reused. With a master key, rotating it makes them unreadable.
+pub struct RedbStore {
+    db: Database,
+    master_key: Option<MasterKey>,
+}
+impl RedbStore {
+    /// Opens the database at `path`, creating it if it doesn't exist. The master key must be the
+    /// one the shares were encrypted with, if any: use `rotate_master_key` to change it.
+    pub fn open(path: &Path, master_key: Option<&MasterKeySource>) -> Result<Self> {
+        let db = Database::create(path)?;
+        let transaction = db.begin_write()?;
+        let master_key = {
+            // creates the tables, so loading an empty database works.
+            let shares = transaction.open_table(SHARES)?;
+            let staged = transaction.open_table(STAGED)?;
+            transaction.open_table(COMMITTED)?;
+            let mut metadata = transaction.open_table(METADATA)?;
+            let salt = metadata.get(SALT)?.map(|salt| salt.value().to_vec());
+            let salt = match salt {
+                Some(salt) => salt,
+                None => {
+                    let salt = random_salt();
+                    metadata.insert(SALT, salt.as_slice())?;
+                    salt
+                }
+            };
+            let master_key = master_key
+                .map(|source| MasterKey::load(source, &salt))
+                .transpose()?;
+            let key_check = metadata.get(KEY_CHECK)?.map(|check| check.value().to_vec());
+            match (&master_key, key_check) {
+                (Some(master_key), Some(key_check)) => {
+                    master_key
+                        .decrypt(&key_check, KEY_CHECK_AAD)
+                        .context("Wrong master key for the database.")?;
+                }
+                (None, Some(_)) => bail!("The database is encrypted, a master key is needed."),
+                (Some(master_key), None) => {
+                    ensure!(
+                        shares.is_empty()? && staged.is_empty()?,
+                        "The database is not encrypted, use rotate-master-key to encrypt it."
+                    );
+                    let key_check = master_key.encrypt(b"", KEY_CHECK_AAD)?;
+                    metadata.insert(KEY_CHECK, key_check.as_slice())?;
+                }
+                (None, None) => {}
+            }
+            master_key
+        };
+        transaction.commit()?;
+        Ok(Self { db, master_key })
+    }
+    /// Encrypts all the shares with the new master key, or stores them in clear if there is none,
+    /// in a single transaction.
+    pub fn rotate_master_key(&mut self, new_key: Option<&MasterKeySource>) -> Result<()> {
+        let salt = random_salt();
+        let new_key = new_key
+            .map(|source| MasterKey::load(source, &salt))
+            .transpose()?;
+        let transaction = self.db.begin_write()?;
+        {
+            let mut shares = transaction.open_table(SHARES)?;
+            let mut reencrypted = vec![];
+            for entry in shares.iter()? {
+                let (key, value) = entry?;
+                let aad = share_aad(key.value());
+                let record = decrypt(self.master_key.as_ref(), &aad, value.value())?;
+                let value = encrypt(new_key.as_ref(), &aad, record)?;
+                reencrypted.push((key.value().to_string(), value));
+            }
+            for (key, value) in reencrypted {
+                shares.insert(key.as_str(), value.as_slice())?;
+            }
+            let mut staged = transaction.open_table(STAGED)?;
+            let mut reencrypted = vec![];
+            for entry in staged.iter()? {
+                let (round_id, value) = entry?;
+                let aad = staged_aad(round_id.value());
+                let record = decrypt(self.master_key.as_ref(), &aad, value.value())?;
+                reencrypted.push((round_id.value(), encrypt(new_key.as_ref(), &aad, record)?));
+            }
+            for (round_id, value) in reencrypted {
+                staged.insert(round_id, value.as_slice())?;
+            }
+            let mut metadata = transaction.open_table(METADATA)?;
+            metadata.insert(SALT, salt.as_slice())?;
+            match &new_key {
+                Some(new_key) => {
+                    let key_check = new_key.encrypt(b"", KEY_CHECK_AAD)?;
+                    metadata.insert(KEY_CHECK, key_check.as_slice())?;
+                }
+                None => {
+                    metadata.remove(KEY_CHECK)?;
+                }
+            }
+        }
+        transaction.commit()?;
+        self.master_key = new_key;
+        Ok(())
+    }
+}
+impl ShareStore for RedbStore {
+    fn load(&self) -> Result<Vec<(HorcrustStoreKey, StoredShare)>> {
+        let transaction = self.db.begin_read()?;
+        let table = transaction.open_table(SHARES)?;
+        table
+            .iter()?
+            .map(|entry| {
+                let (key, value) = entry?;
+                let aad = share_aad(key.value());
+                let record = decrypt(self.master_key.as_ref(), &aad, value.value())?;
+                let record = StoredShareRecord::decode(record.as_slice())?;
+                Ok((key.value().to_string(), record.into()))
+            })
+            .collect()
+    }
+    fn write(&mut self, changes: Vec<(HorcrustStoreKey, Option<StoredShare>)>) -> Result<()> {
+        let transaction = self.db.begin_write()?;
+        self.write_shares(&transaction, changes)?;
+        // nothing is written if this is not reached.
+        transaction.commit()?;
+        Ok(())
+    }
+    fn load_staged(&self) -> Result<Vec<(u64, StagedRound)>> {
+        let transaction = self.db.begin_read()?;
+        let table = transaction.open_table(STAGED)?;
+        table
+            .iter()?
+            .map(|entry| {
+                let (round_id, value) = entry?;
+                let aad = staged_aad(round_id.value());
+                let record = decrypt(self.master_key.as_ref(), &aad, value.value())?;
+                let record = StagedRefreshRecord::decode(record.as_slice())?;
+                Ok((round_id.value(), record.into()))
+            })
+            .collect()
+    }
+    fn stage(&mut self, round_id: u64, round: StagedRound) -> Result<()> {
+        let record = StagedRefreshRecord::from(round).encode_to_vec();
+        let value = encrypt(self.master_key.as_ref(), &staged_aad(round_id), record)?;
+        let transaction = self.db.begin_write()?;
+        transaction
+            .open_table(STAGED)?
+            .insert(round_id, value.as_slice())?;
+        transaction.commit()?;
+        Ok(())
+    }
+    fn unstage(
+        &mut self,
+        round_id: u64,
+        changes: Vec<(HorcrustStoreKey, Option<StoredShare>)>,
+        committed: Option<CommittedRound>,
+    ) -> Result<()> {
+        let transaction = self.db.begin_write()?;
+        transaction.open_table(STAGED)?.remove(round_id)?;
+        self.write_shares(&transaction, changes)?;
+        if let Some(committed) = committed {
+            let record = CommittedRoundRecord {
+                committed_at: committed.committed_at,
+                skipped_key: committed.skipped,
+            };
+            transaction
+                .open_table(COMMITTED)?
+                .insert(round_id, record.encode_to_vec().as_slice())?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+    fn load_committed(&self) -> Result<Vec<(u64, CommittedRound)>> {
+        let transaction = self.db.begin_read()?;
+        let table = transaction.open_table(COMMITTED)?;
+        table
+            .iter()?
+            .map(|entry| {
+                let (round_id, value) = entry?;
+                let record = CommittedRoundRecord::decode(value.value())?;
+                let committed = CommittedRound {
+                    committed_at: record.committed_at,
+                    skipped: record.skipped_key,
+                };
+                Ok((round_id.value(), committed))
+            })
+            .collect()
+    }
+    fn forget_committed(&mut self, round_ids: &[u64]) -> Result<()> {
+        let transaction = self.db.begin_write()?;
+        {
+            let mut table = transaction.open_table(COMMITTED)?;
+            for round_id in round_ids {
+                table.remove(round_id)?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+impl RedbStore {
+    fn write_shares(
+        &self,
+        transaction: &WriteTransaction,
+        changes: Vec<(HorcrustStoreKey, Option<StoredShare>)>,
+    ) -> Result<()> {
+        let mut table = transaction.open_table(SHARES)?;
+        for (key, share) in changes {
+            match share {
+                Some(share) => {
+                    let record = StoredShareRecord::from(share).encode_to_vec();
+                    let value = encrypt(self.master_key.as_ref(), &share_aad(&key), record)?;
+                    table.insert(key.as_str(), value.as_slice())?;
+                }
+                None => {
+                    table.remove(key.as_str())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The shares are bound to their key, so they can't be swapped on disk.
+fn share_aad(key: &str) -> Vec<u8> {
+    [b"horcrust share ".as_slice(), key.as_bytes()].concat()
+}
+fn staged_aad(round_id: u64) -> Vec<u8> {
+    [
+        b"horcrust staged refresh ".as_slice(),
+        &round_id.to_be_bytes(),
+    ]
+    .concat()
+}
+fn encrypt(master_key: Option<&MasterKey>, aad: &[u8], record: Vec<u8>) -> Result<Vec<u8>> {
+    match master_key {
+        Some(master_key) => master_key.encrypt(&record, aad),
+        None => Ok(record),
+    }
+}
+fn decrypt(master_key: Option<&MasterKey>, aad: &[u8], value: &[u8]) -> Result<Vec<u8>> {
+    match master_key {
+        Some(master_key) => master_key
+            .decrypt(value, aad)
+            .context("Failed to decrypt the stored shares"),
+        None => Ok(value.to_vec()),
+    }
+}
+fn random_salt() -> Vec<u8> {
+    rand::random::<[u8; 16]>().to_vec()
+}
+
+impl From<StoredShare> for StoredShareRecord {
+    fn from(stored: StoredShare) -> Self {
+        Self {
+            share: stored.share,
+            scheme: Some(stored.scheme),
+            commitments: stored.commitments,
+            index: stored.index,
+            epoch: stored.epoch,
+            refresh_interval_secs: refresh_interval_secs(stored.refresh_interval),
+            stored_at: stored.stored_at,
+            refreshed_at: stored.refreshed_at,
+            acl: Some(stored.acl),
+            servers: stored.servers,
+        }
+    }
+}
+impl From<StagedRound> for StagedRefreshRecord {
+    fn from(round: StagedRound) -> Self {
+        let shares = round
+            .shares
+            .into_iter()
+            .map(|staged| StagedShareRecord {
+                key: staged.key,
+                share: staged.share.map(Into::into),
+                base: staged.base.unwrap_or_default(),
+            })
+            .collect();
+        Self {
+            shares,
+            expires_at: round.expires_at,
+            servers: round.servers,
+        }
+    }
+}
+impl From<StagedRefreshRecord> for StagedRound {
+    fn from(record: StagedRefreshRecord) -> Self {
+        let shares = record
+            .shares
+            .into_iter()
+            .map(|staged| StagedShare {
+                key: staged.key,
+                share: staged.share.map(Into::into),
+                // digests are never empty.
+                base: Some(staged.base).filter(|base| !base.is_empty()),
+            })
+            .collect();
+        Self {
+            shares,
+            expires_at: record.expires_at,
+            servers: record.servers,
+        }
+    }
+}
+impl From<StoredShareRecord> for StoredShare {
+    fn from(record: StoredShareRecord) -> Self {
+        Self {
+            share: record.share,
+            scheme: record.scheme.unwrap_or_default(),
+            commitments: record.commitments,
+            index: record.index,
+            epoch: record.epoch,
+            refresh_interval: refresh_interval_from_secs(record.refresh_interval_secs),
+            stored_at: record.stored_at,
+            refreshed_at: record.refreshed_at,
+            acl: record.acl.unwrap_or_default(),
+            servers: record.servers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use horcrust::{KeyAcl, SharingScheme};
+
+    #[test]
+    fn test_redb_store() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("horcrust-store-{}", rand::random::<u64>()));
+        std::fs::create_dir(&dir)?;
+        let path = dir.join("shares.db");
+        let share = |share: u8| StoredShare {
+            share: vec![share],
+            scheme: SharingScheme {
+                threshold: 2,
+                modulus: vec![7],
+                verifiable: false,
+            },
+            commitments: vec![],
+            index: 1,
+            epoch: 0,
+            refresh_interval: None,
+            stored_at: 0,
+            refreshed_at: 0,
+            acl: KeyAcl::default(),
+            servers: vec![],
+        };
+        let mut store = RedbStore::open(&path, None)?;
+        assert!(store.load()?.is_empty());
+        store.write(vec![
+            ("1".into(), Some(share(1))),
+            ("2".into(), Some(share(2))),
+        ])?;
+        store.write(vec![("1".into(), None), ("2".into(), Some(share(3)))])?;
+        drop(store);
+
+        // the shares are still there after reopening the database.
+        let mut store = RedbStore::open(&path, None)?;
+        assert_eq!(store.load()?, vec![("2".into(), share(3))]);
+
+        // encrypting the database needs the new master key to open it.
+        let master_key = MasterKeySource::Passphrase("passphrase".into());
+        store.rotate_master_key(Some(&master_key))?;
+        store.write(vec![("1".into(), Some(share(1)))])?;
+        drop(store);
+        assert!(RedbStore::open(&path, None).is_err());
+        let wrong_key = MasterKeySource::Passphrase("wrong".into());
+        assert!(RedbStore::open(&path, Some(&wrong_key)).is_err());
+        let mut store = RedbStore::open(&path, Some(&master_key))?;
+        assert_eq!(
+            store.load()?,
+            vec![("1".into(), share(1)), ("2".into(), share(3))]
+        );
+
+        // staged rounds are kept until unstaged, removals included.
+        let staged = StagedRound {
+            shares: vec![
+                StagedShare {
+                    key: "1".into(),
+                    share: Some(share(4)),
+                    base: Some(vec![1, 2]),
+                },
+                StagedShare {
+                    key: "2".into(),
+                    share: None,
+                    base: Some(vec![3]),
+                },
+                StagedShare {
+                    key: "3".into(),
+                    share: Some(share(5)),
+                    base: None,
+                },
+            ],
+            expires_at: 10,
+            servers: vec!["a".into(), "b".into()],
+        };
+        store.stage(7, staged.clone())?;
+        drop(store);
+        let mut store = RedbStore::open(&path, Some(&master_key))?;
+        assert_eq!(store.load_staged()?, vec![(7, staged)]);
+        let committed = CommittedRound {
+            committed_at: 5,
+            skipped: vec!["3".into()],
+        };
+        store.unstage(
+            7,
+            vec![("1".into(), Some(share(4)))],
+            Some(committed.clone()),
+        )?;
+        assert!(store.load_staged()?.is_empty());
+        assert_eq!(
+            store.load()?,
+            vec![("1".into(), share(4)), ("2".into(), share(3))]
+        );
+        drop(store);
+
+        // so are committed rounds, until forgotten.
+        let mut store = RedbStore::open(&path, Some(&master_key))?;
+        assert_eq!(store.load_committed()?, vec![(7, committed)]);
+        store.forget_committed(&[7])?;
+        assert!(store.load_committed()?.is_empty());
+        drop(store);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
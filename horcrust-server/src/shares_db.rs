@@ -1,72 +1,700 @@
-use horcrust::{HorcrustShare, HorcrustStoreKey, REFRESH_THRESHOLD};
+use crate::share_store::{CommittedRound, MemoryStore, ShareStore, StagedRound, StagedShare};
+use anyhow::{bail, ensure};
+use horcrust::{
+    key_has_prefix, HorcrustCommitmentsBytes, HorcrustShareBytes, HorcrustStoreKey, KeyAcl,
+    ReshareContributionRequest, SharingScheme,
+};
+use log::warn;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
-use std::time::Instant;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use zeroize::Zeroize;
+
+/// How long committed rounds are remembered, for the servers of the round that didn't get the
+/// commit: a server down for longer rolls the round back while the others committed it.
+const COMMITTED_ROUNDS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A share together with the parameters of the scheme that generated it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredShare {
+    pub share: HorcrustShareBytes,
+    pub scheme: SharingScheme,
+    /// empty if the scheme is not verifiable.
+    pub commitments: HorcrustCommitmentsBytes,
+    /// position of the share in the split, needed to refresh it.
+    pub index: u32,
+    /// number of refreshes committed since the share was stored.
+    pub epoch: u64,
+    /// overrides the refresh interval of the server for this share.
+    pub refresh_interval: Option<Duration>,
+    /// when the share was stored, in seconds since the unix epoch.
+    pub stored_at: u64,
+    /// when the share was last refreshed, in seconds since the unix epoch. 0 if never.
+    pub refreshed_at: u64,
+    /// who can access the share, see `can_access`.
+    pub acl: KeyAcl,
+    /// the servers holding the shares of the key, sorted: the share index of each one is its
+    /// position. They refresh the key together.
+    pub servers: Vec<String>,
+}
+
+/// Identifies a share and its epoch, so a staged round can tell if the share it replaces changed.
+fn share_digest(stored: &StoredShare) -> Vec<u8> {
+    let mut digest = Sha256::new();
+    digest.update(stored.epoch.to_be_bytes());
+    digest.update(&stored.share);
+    digest.finalize().to_vec()
+}
+
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// In messages, 0 means that the share doesn't have a refresh interval of its own.
+pub(crate) fn refresh_interval_from_secs(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+pub(crate) fn refresh_interval_secs(interval: Option<Duration>) -> u64 {
+    interval.map_or(0, |interval| interval.as_secs())
+}
 
-#[derive(Default)]
 pub struct SharesDatabase {
-    shares: HashMap<HorcrustStoreKey, HorcrustShare>,
+    shares: HashMap<HorcrustStoreKey, StoredShare>,
     shares_refresh: HashMap<HorcrustStoreKey, Instant>,
+    /// sub-shares received during a reshare, kept until the reshare is committed.
+    reshares: HashMap<HorcrustStoreKey, Vec<ReshareContributionRequest>>,
+    /// refreshed and reshared shares waiting for the commit, by round.
+    staged: HashMap<u64, StagedRound>,
+    /// rounds committed in the last COMMITTED_ROUNDS_TTL.
+    committed: HashMap<u64, CommittedRound>,
+    /// the refresher allowed to refresh the shares, and when its lease expires.
+    refresh_lease: Option<(u64, Instant)>,
+    /// every change to the shares is written here before being applied.
+    store: Box<dyn ShareStore>,
+}
+
+impl Default for SharesDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Locks the database. A request that panicked while holding the lock doesn't make it unusable:
+/// every change is applied with a single map operation, so the database is still consistent.
+pub fn lock_db(db: &Mutex<SharesDatabase>) -> MutexGuard<'_, SharesDatabase> {
+    db.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
 impl SharesDatabase {
+    /// A database that only lives in memory.
     pub fn new() -> Self {
         Self {
             shares: HashMap::new(),
             shares_refresh: HashMap::new(),
+            reshares: HashMap::new(),
+            staged: HashMap::new(),
+            committed: HashMap::new(),
+            refresh_lease: None,
+            store: Box::new(MemoryStore),
         }
     }
-    pub fn stale_keys(&self) -> Vec<HorcrustStoreKey> {
-        self.shares_refresh
+    /// A database with the shares kept in `store`, starting from the ones already there.
+    pub fn open(store: impl ShareStore + 'static) -> horcrust::Result<Self> {
+        let shares: HashMap<_, _> = store.load()?.into_iter().collect();
+        let now = Instant::now();
+        Ok(Self {
+            shares_refresh: shares.keys().map(|key| (key.clone(), now)).collect(),
+            shares,
+            reshares: HashMap::new(),
+            staged: store.load_staged()?.into_iter().collect(),
+            committed: store.load_committed()?.into_iter().collect(),
+            refresh_lease: None,
+            store: Box::new(store),
+        })
+    }
+    /// Keys whose share is older than its own refresh interval or, if it has none,
+    /// `default_interval`, the oldest first. Keys with a refresh in progress are not stale.
+    pub fn stale_keys(&self, default_interval: Duration) -> Vec<HorcrustStoreKey> {
+        let mut stale: Vec<_> = self
+            .shares_refresh
+            .iter()
+            .filter(|(k, t)| {
+                let interval = self.shares[*k].refresh_interval.unwrap_or(default_interval);
+                t.elapsed() > interval && !self.is_staged(k)
+            })
+            .collect();
+        stale.sort_by_key(|(_, t)| **t);
+        stale.into_iter().map(|(k, _)| k.clone()).collect()
+    }
+    /// True if a refresh or reshare of the key is waiting for the commit.
+    pub fn is_staged(&self, key: &str) -> bool {
+        self.staged
+            .values()
+            .any(|round| round.shares.iter().any(|staged| staged.key == key))
+    }
+    /// Gives the refresh lease to `holder`, unless another refresher holds it. The holder can renew it.
+    pub fn acquire_refresh_lease(&mut self, holder: u64, lease: Duration) -> horcrust::Result<()> {
+        if let Some((current, expiry)) = self.refresh_lease {
+            ensure!(
+                current == holder || expiry <= Instant::now(),
+                "The refresh lease is held by another server."
+            );
+        }
+        self.refresh_lease = Some((holder, Instant::now() + lease));
+        Ok(())
+    }
+    pub fn release_refresh_lease(&mut self, holder: u64) {
+        if self.has_refresh_lease(holder) {
+            self.refresh_lease = None;
+        }
+    }
+    pub fn has_refresh_lease(&self, holder: u64) -> bool {
+        self.refresh_lease
+            .is_some_and(|(current, expiry)| current == holder && expiry > Instant::now())
+    }
+    /// Stores the new shares of a refresh or reshare until `commit_refresh` or `abort_refresh`,
+    /// None for the keys the commit removes. A key can only be in one round at a time. Rounds
+    /// still staged after `expires_at` are resolved with the other `servers`, see
+    /// `expired_rounds`.
+    pub fn stage_refresh(
+        &mut self,
+        round_id: u64,
+        shares: Vec<(HorcrustStoreKey, Option<StoredShare>)>,
+        expires_at: u64,
+        servers: Vec<String>,
+    ) -> horcrust::Result<()> {
+        ensure!(
+            !self.staged.contains_key(&round_id) && !self.committed.contains_key(&round_id),
+            "Refresh {round_id} is already staged or committed."
+        );
+        ensure!(
+            expires_at > unix_time(),
+            "Refresh {round_id} expired before it was staged."
+        );
+        if let Some((key, _)) = shares.iter().find(|(key, _)| self.is_staged(key)) {
+            bail!("Key '{key}' has another refresh in progress.");
+        }
+        let shares = shares
+            .into_iter()
+            .map(|(key, share)| StagedShare {
+                base: self.shares.get(&key).map(share_digest),
+                key,
+                share,
+            })
+            .collect();
+        let round = StagedRound {
+            shares,
+            expires_at,
+            servers,
+        };
+        self.store.stage(round_id, round.clone())?;
+        self.staged.insert(round_id, round);
+        Ok(())
+    }
+    /// Switches to the shares staged by the round, except for the `skipped` keys, and removes
+    /// the keys it removes. Committing an unknown round does nothing, so commits can be retried,
+    /// but expired rounds can't be committed: the servers resolve them, see `resolve_refresh`.
+    pub fn commit_refresh(&mut self, round_id: u64, skipped: &[String]) -> horcrust::Result<()> {
+        let Some(round) = self.staged.get(&round_id) else {
+            return Ok(());
+        };
+        ensure!(
+            unix_time() < round.expires_at,
+            "Refresh {round_id} expired, it is resolved by the servers."
+        );
+        self.apply_refresh(round_id, skipped)
+    }
+    /// Commits an expired round, with the keys skipped by the servers that committed it, or rolls
+    /// it back if `skipped` is None.
+    pub fn resolve_refresh(
+        &mut self,
+        round_id: u64,
+        skipped: Option<&[String]>,
+    ) -> horcrust::Result<()> {
+        match skipped {
+            Some(skipped) => self.apply_refresh(round_id, skipped),
+            None => self.abort_refresh(round_id),
+        }
+    }
+    fn apply_refresh(&mut self, round_id: u64, skipped: &[String]) -> horcrust::Result<()> {
+        let Some(round) = self.staged.get(&round_id) else {
+            return Ok(());
+        };
+        // keys deleted in the meantime were dropped from the staged shares, see `delete`.
+        let refreshed_at = unix_time();
+        let mut changes = vec![];
+        for staged in round.shares.iter().filter(|s| !skipped.contains(&s.key)) {
+            // the base changes if the key was put again since the round was staged.
+            if self.shares.get(&staged.key).map(share_digest) != staged.base {
+                warn!(
+                    "Key '{}' changed since refresh {round_id} was staged, it is not refreshed.",
+                    staged.key
+                );
+                continue;
+            }
+            let share = staged.share.as_ref().map(|share| StoredShare {
+                refreshed_at,
+                ..share.clone()
+            });
+            changes.push((staged.key.clone(), share));
+        }
+        let committed = CommittedRound {
+            committed_at: refreshed_at,
+            skipped: skipped.to_vec(),
+        };
+        self.store
+            .unstage(round_id, changes.clone(), Some(committed.clone()))?;
+        if let Some(mut round) = self.staged.remove(&round_id) {
+            zeroize_round(&mut round);
+        }
+        self.committed.insert(round_id, committed);
+        let now = Instant::now();
+        for (key, share) in changes {
+            match share {
+                Some(share) => {
+                    self.shares_refresh.insert(key.clone(), now);
+                    self.shares.insert(key, share);
+                }
+                None => {
+                    self.shares_refresh.remove(&key);
+                    if let Some(mut removed) = self.shares.remove(&key) {
+                        removed.share.zeroize();
+                    }
+                }
+            }
+        }
+        self.forget_committed()
+    }
+    /// Drops the rounds committed more than COMMITTED_ROUNDS_TTL ago.
+    fn forget_committed(&mut self) -> horcrust::Result<()> {
+        let oldest = unix_time().saturating_sub(COMMITTED_ROUNDS_TTL.as_secs());
+        let old: Vec<u64> = self
+            .committed
+            .iter()
+            .filter(|(_, committed)| committed.committed_at < oldest)
+            .map(|(round_id, _)| *round_id)
+            .collect();
+        if !old.is_empty() {
+            self.store.forget_committed(&old)?;
+            for round_id in old {
+                self.committed.remove(&round_id);
+            }
+        }
+        Ok(())
+    }
+    /// Drops the shares staged by the round.
+    pub fn abort_refresh(&mut self, round_id: u64) -> horcrust::Result<()> {
+        if self.staged.contains_key(&round_id) {
+            self.store.unstage(round_id, vec![], None)?;
+            if let Some(mut round) = self.staged.remove(&round_id) {
+                zeroize_round(&mut round);
+            }
+        }
+        Ok(())
+    }
+    /// The round, if this server committed it in the last COMMITTED_ROUNDS_TTL.
+    pub fn committed_round(&self, round_id: u64) -> Option<&CommittedRound> {
+        self.committed.get(&round_id)
+    }
+    /// The rounds still staged `margin` after they expired, with the servers taking part in
+    /// them: the coordinator stopped before finishing them.
+    pub fn expired_rounds(&self, margin: Duration) -> Vec<(u64, Vec<String>)> {
+        let now = unix_time();
+        self.staged
             .iter()
-            .filter(|(_, t)| t.elapsed() > REFRESH_THRESHOLD)
-            .map(|(k, _)| *k)
+            .filter(|(_, round)| round.expires_at.saturating_add(margin.as_secs()) <= now)
+            .map(|(round_id, round)| (*round_id, round.servers.clone()))
             .collect()
     }
-    pub fn insert<T: Into<HorcrustStoreKey> + Copy, S: Into<HorcrustShare>>(
+    pub fn insert<T: Into<HorcrustStoreKey>>(
         &mut self,
         key: T,
-        share: S,
+        share: StoredShare,
+    ) -> horcrust::Result<()> {
+        self.update(vec![(key.into(), share)])
+    }
+    /// Removes the share of the key and everything about it: its staged refreshes and the
+    /// sub-shares of a reshare. The removed shares are overwritten in memory. Returns false if
+    /// the key was not found.
+    pub fn delete(&mut self, key: &str) -> horcrust::Result<bool> {
+        let Some(mut stored) = self.remove(key)? else {
+            return Ok(false);
+        };
+        stored.share.zeroize();
+        for mut contribution in self.reshares.remove(key).unwrap_or_default() {
+            contribution.share.zeroize();
+        }
+        for (round_id, round) in self.staged.iter_mut() {
+            if !round.shares.iter().any(|staged| staged.key == key) {
+                continue;
+            }
+            for staged in round.shares.iter_mut().filter(|staged| staged.key == key) {
+                if let Some(stored) = &mut staged.share {
+                    stored.share.zeroize();
+                }
+            }
+            round.shares.retain(|staged| staged.key != key);
+            // overwrites the staged round with the one without the key.
+            self.store.stage(*round_id, round.clone())?;
+        }
+        Ok(true)
+    }
+    /// Stores all the shares or, if the store fails, none of them.
+    pub fn update(&mut self, shares: Vec<(HorcrustStoreKey, StoredShare)>) -> horcrust::Result<()> {
+        self.store.write(
+            shares
+                .iter()
+                .map(|(key, share)| (key.clone(), Some(share.clone())))
+                .collect(),
+        )?;
+        let now = Instant::now();
+        for (key, share) in shares {
+            self.shares_refresh.insert(key.clone(), now);
+            self.shares.insert(key, share);
+        }
+        Ok(())
+    }
+    pub fn remove(&mut self, key: &str) -> horcrust::Result<Option<StoredShare>> {
+        if !self.shares.contains_key(key) {
+            return Ok(None);
+        }
+        self.store.write(vec![(key.to_string(), None)])?;
+        self.shares_refresh.remove(key);
+        Ok(self.shares.remove(key))
+    }
+    /// Up to `limit` shares of the keys below `prefix` (see key_has_prefix), in key order,
+    /// starting from the key `start`. Also returns the first key of the next page, if there are
+    /// more shares.
+    pub fn list(
+        &self,
+        prefix: &str,
+        start: &str,
+        limit: usize,
+    ) -> (
+        Vec<(HorcrustStoreKey, StoredShare)>,
+        Option<HorcrustStoreKey>,
     ) {
-        self.shares.insert(key.into(), share.into());
-        self.shares_refresh.insert(key.into(), Instant::now());
+        let mut keys: Vec<_> = self
+            .shares
+            .keys()
+            .filter(|key| key.as_str() >= start && key_has_prefix(key, prefix))
+            .collect();
+        keys.sort();
+        let next = keys.get(limit).map(|key| key.to_string());
+        let page = keys
+            .into_iter()
+            .take(limit)
+            .map(|key| (key.clone(), self.shares[key].clone()))
+            .collect();
+        (page, next)
     }
-    pub fn get<T: Into<HorcrustStoreKey>>(&self, key: T) -> Option<HorcrustShare> {
+    /// The number of keys below `prefix`, see key_has_prefix.
+    pub fn count_keys(&self, prefix: &str) -> usize {
+        self.shares
+            .keys()
+            .filter(|key| key_has_prefix(key, prefix))
+            .count()
+    }
+    pub fn get_stored(&self, key: &str) -> Option<StoredShare> {
+        self.shares.get(key).cloned()
+    }
+    pub fn get(&self, key: &str) -> Option<HorcrustShareBytes> {
         // just to keep things easy, this get returns a copy of the value. Usually it should return a reference to it.
-        self.shares.get(&key.into()).cloned()
+        self.shares.get(key).map(|stored| stored.share.clone())
     }
-    pub fn modify<F, K: Into<HorcrustStoreKey> + Copy>(
-        &mut self,
-        key: K,
-        f: F,
-    ) -> horcrust::Result<()>
+    pub fn scheme(&self, key: &str) -> Option<SharingScheme> {
+        self.shares.get(key).map(|stored| stored.scheme.clone())
+    }
+    pub fn commitments(&self, key: &str) -> Option<HorcrustCommitmentsBytes> {
+        self.shares
+            .get(key)
+            .map(|stored| stored.commitments.clone())
+    }
+    pub fn index(&self, key: &str) -> Option<u32> {
+        self.shares.get(key).map(|stored| stored.index)
+    }
+    pub fn servers(&self, key: &str) -> Option<Vec<String>> {
+        self.shares.get(key).map(|stored| stored.servers.clone())
+    }
+    /// Sub-shares of a different reshare of the same key are dropped.
+    pub fn add_reshare_contribution(&mut self, contribution: ReshareContributionRequest) {
+        let pending = self.reshares.entry(contribution.key.clone()).or_default();
+        let same_reshare = pending.first().is_some_and(|first| {
+            first.round_id == contribution.round_id
+                && first.index == contribution.index
+                && first.contributions == contribution.contributions
+                && first.scheme == contribution.scheme
+        });
+        if !same_reshare {
+            pending.clear();
+        }
+        pending.retain(|c| c.from_index != contribution.from_index);
+        pending.push(contribution);
+    }
+    pub fn take_reshare_contributions(&mut self, key: &str) -> Vec<ReshareContributionRequest> {
+        self.reshares.remove(key).unwrap_or_default()
+    }
+    /// Replaces the stored share with the one returned by `f`, unless `f` fails.
+    pub fn modify<F>(&mut self, key: &str, f: F) -> horcrust::Result<()>
     where
-        F: Fn(HorcrustShare) -> HorcrustShare,
+        F: Fn(&StoredShare) -> horcrust::Result<StoredShare>,
     {
-        if let Some(share) = self.shares.get_mut(&key.into()) {
-            *share = f(*share);
-            // safe unwrap because shares and shares_refresh have the same keys
-            *self.shares_refresh.get_mut(&key.into()).unwrap() = Instant::now();
+        match self.shares.get(key) {
+            Some(stored) => {
+                let modified = f(stored)?;
+                self.update(vec![(key.to_string(), modified)])
+            }
+            None => Ok(()),
         }
-        Ok(())
+    }
+}
+
+fn zeroize_round(round: &mut StagedRound) {
+    for stored in round.shares.iter_mut().filter_map(|s| s.share.as_mut()) {
+        stored.share.zeroize();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use horcrust::AdditiveSecretSharing;
 
     #[test]
     fn test_database() {
-        let secret_sharing = AdditiveSecretSharing::default();
         let mut db = SharesDatabase::new();
-        let key = 0u32;
-        let share = 1;
+        let key = "prod/db/password";
+        let share = vec![1];
         let r = 2;
-        db.insert(key, share);
+        let scheme = SharingScheme {
+            threshold: 2,
+            modulus: vec![1, 1],
+            ..Default::default()
+        };
+        let commitments = vec![3];
+        db.insert(
+            key,
+            StoredShare {
+                share: share.clone(),
+                scheme: scheme.clone(),
+                commitments: commitments.clone(),
+                index: 1,
+                epoch: 0,
+                refresh_interval: None,
+                stored_at: 0,
+                refreshed_at: 0,
+                acl: KeyAcl::default(),
+                servers: vec![],
+            },
+        )
+        .unwrap();
         assert_eq!(db.get(key).unwrap(), share);
+        assert_eq!(db.scheme(key).unwrap(), scheme);
+        assert_eq!(db.commitments(key).unwrap(), commitments);
+        assert_eq!(db.index(key).unwrap(), 1);
 
-        db.modify(key, |share| share + r).unwrap();
-        assert_eq!(db.get(key).unwrap(), share + r);
+        db.modify(key, |stored| {
+            Ok(StoredShare {
+                share: vec![stored.share[0] + r],
+                commitments: vec![stored.commitments[0] + r],
+                ..stored.clone()
+            })
+        })
+        .unwrap();
+        assert_eq!(db.get(key).unwrap(), vec![share[0] + r]);
+        assert_eq!(db.commitments(key).unwrap(), vec![commitments[0] + r]);
+        // a failing modify leaves the share untouched.
+        assert!(db.modify(key, |_| anyhow::bail!("error")).is_err());
+        assert_eq!(db.get(key).unwrap(), vec![share[0] + r]);
+
+        // keys are listed by prefix, a page at a time.
+        let stored = db.get_stored(key).unwrap();
+        for other in ["prod/db/user", "prod/dbx", "staging/db/password"] {
+            db.insert(other, stored.clone()).unwrap();
+        }
+        let (page, next) = db.list("prod/db", "", 1);
+        assert_eq!(page, vec![(key.to_string(), stored.clone())]);
+        assert_eq!(next.as_deref(), Some("prod/db/user"));
+        let (page, next) = db.list("prod/db", "prod/db/user", 1);
+        assert_eq!(page, vec![("prod/db/user".to_string(), stored)]);
+        assert_eq!(next, None);
+        assert_eq!(db.list("", "", 10).0.len(), 4);
+        for other in ["prod/db/user", "prod/dbx", "staging/db/password"] {
+            assert!(db.delete(other).unwrap());
+        }
+        assert!(db.delete(key).unwrap());
+        assert!(!db.delete(key).unwrap());
+        db.insert(
+            key,
+            StoredShare {
+                share: share.clone(),
+                scheme: scheme.clone(),
+                commitments: commitments.clone(),
+                index: 1,
+                epoch: 0,
+                refresh_interval: None,
+                stored_at: 0,
+                refreshed_at: 0,
+                acl: KeyAcl::default(),
+                servers: vec![],
+            },
+        )
+        .unwrap();
+        assert!(db.remove(key).unwrap().is_some());
+        assert!(db.remove(key).unwrap().is_none());
+        assert!(db.get(key).is_none());
+        assert!(db.stale_keys(Duration::from_secs(5)).is_empty());
+    }
+
+    #[test]
+    fn test_staged_refresh() -> horcrust::Result<()> {
+        let mut db = SharesDatabase::new();
+        let share = |share| StoredShare {
+            share: vec![share],
+            scheme: SharingScheme::default(),
+            commitments: vec![],
+            index: 0,
+            epoch: 0,
+            refresh_interval: None,
+            stored_at: 0,
+            refreshed_at: 0,
+            acl: KeyAcl::default(),
+            servers: vec![],
+        };
+        db.insert("1", share(1))?;
+        db.insert("2", share(2))?;
+        let expires_at = unix_time() + 60;
+        let servers = vec!["a".to_string(), "b".to_string()];
+        let stage = |db: &mut SharesDatabase, round_id, shares| {
+            db.stage_refresh(round_id, shares, expires_at, servers.clone())
+        };
+        stage(&mut db, 10, vec![("1".into(), Some(share(3)))])?;
+        // the key is locked by the staged refresh.
+        assert!(db.is_staged("1"));
+        assert!(stage(
+            &mut db,
+            11,
+            vec![("2".into(), Some(share(4))), ("1".into(), Some(share(4)))]
+        )
+        .is_err());
+        assert_eq!(db.get("1").unwrap(), vec![1]);
+        db.commit_refresh(10, &[])?;
+        assert_eq!(db.get("1").unwrap(), vec![3]);
+        assert!(db.committed_round(10).is_some());
+        // commits can be retried, but the round can't be staged again.
+        db.commit_refresh(10, &[])?;
+        assert_eq!(db.get("1").unwrap(), vec![3]);
+        assert!(stage(&mut db, 10, vec![("2".into(), Some(share(4)))]).is_err());
+
+        stage(
+            &mut db,
+            11,
+            vec![("1".into(), Some(share(4))), ("2".into(), Some(share(4)))],
+        )?;
+        db.abort_refresh(11)?;
+        assert_eq!(db.get("1").unwrap(), vec![3]);
+        assert_eq!(db.get("2").unwrap(), vec![2]);
+        assert!(db.committed_round(11).is_none());
+
+        // a deleted key is not brought back by a staged refresh.
+        stage(
+            &mut db,
+            12,
+            vec![("1".into(), Some(share(5))), ("2".into(), Some(share(5)))],
+        )?;
+        assert!(db.delete("1")?);
+        db.commit_refresh(12, &[])?;
+        assert!(db.get("1").is_none());
+        assert_eq!(db.get("2").unwrap(), vec![5]);
+
+        // a reshare adds new keys and removes the old ones on commit.
+        stage(
+            &mut db,
+            13,
+            vec![("2".into(), None), ("3".into(), Some(share(6)))],
+        )?;
+        assert_eq!(db.get("2").unwrap(), vec![5]);
+        db.commit_refresh(13, &[])?;
+        assert!(db.get("2").is_none());
+        assert_eq!(db.get("3").unwrap(), vec![6]);
+
+        // skipped keys, and keys put again since the round was staged, keep their share.
+        db.insert("1", share(1))?;
+        db.insert("2", share(2))?;
+        stage(
+            &mut db,
+            14,
+            vec![
+                ("1".into(), Some(share(7))),
+                ("2".into(), Some(share(7))),
+                ("3".into(), Some(share(7))),
+            ],
+        )?;
+        db.insert("1", share(8))?;
+        db.commit_refresh(14, &["2".to_string()])?;
+        assert_eq!(db.get("1").unwrap(), vec![8]);
+        assert_eq!(db.get("2").unwrap(), vec![2]);
+        assert_eq!(db.get("3").unwrap(), vec![7]);
+        assert_eq!(db.committed_round(14).unwrap().skipped, vec!["2"]);
+
+        // expired rounds can't be committed by the coordinator, the servers resolve them.
+        assert!(stage(&mut db, 15, vec![("1".into(), Some(share(9)))]).is_ok());
+        assert!(db.expired_rounds(Duration::ZERO).is_empty());
+        db.staged.get_mut(&15).unwrap().expires_at = unix_time();
+        assert!(db.commit_refresh(15, &[]).is_err());
+        assert_eq!(
+            db.expired_rounds(Duration::ZERO),
+            vec![(15, servers.clone())]
+        );
+        assert!(db.expired_rounds(Duration::from_secs(60)).is_empty());
+        db.resolve_refresh(15, Some(&[]))?;
+        assert_eq!(db.get("1").unwrap(), vec![9]);
+        assert!(db.expired_rounds(Duration::ZERO).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_refresh_lease() -> horcrust::Result<()> {
+        let mut db = SharesDatabase::new();
+        db.acquire_refresh_lease(1, Duration::from_secs(10))?;
+        assert!(db.has_refresh_lease(1));
+        // the holder renews it, the others have to wait.
+        db.acquire_refresh_lease(1, Duration::from_secs(10))?;
+        assert!(db
+            .acquire_refresh_lease(2, Duration::from_secs(10))
+            .is_err());
+        db.release_refresh_lease(2);
+        assert!(db.has_refresh_lease(1));
+        db.release_refresh_lease(1);
+        db.acquire_refresh_lease(2, Duration::ZERO)?;
+        assert!(!db.has_refresh_lease(2));
+        // an expired lease can be taken.
+        db.acquire_refresh_lease(1, Duration::from_secs(10))?;
+        assert!(db.has_refresh_lease(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reshare_contributions() {
+        let mut db = SharesDatabase::new();
+        let contribution = |index, from_index| ReshareContributionRequest {
+            key: "1".into(),
+            index,
+            from_index,
+            contributions: 2,
+            ..Default::default()
+        };
+        db.add_reshare_contribution(contribution(0, 0));
+        // a sub-share sent twice is kept once.
+        db.add_reshare_contribution(contribution(0, 1));
+        db.add_reshare_contribution(contribution(0, 1));
+        assert_eq!(db.take_reshare_contributions("1").len(), 2);
+        assert!(db.take_reshare_contributions("1").is_empty());
+        // sub-shares of a previous reshare are dropped.
+        db.add_reshare_contribution(contribution(0, 0));
+        db.add_reshare_contribution(contribution(1, 1));
+        assert_eq!(db.take_reshare_contributions("1"), vec![contribution(1, 1)]);
     }
 }
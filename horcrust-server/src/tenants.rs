@@ -0,0 +1,190 @@
+use crate::SharesDatabase;
+use anyhow::{bail, ensure, Context};
+use horcrust::{
+    horcrust_msg_request::Request, peer_id_from_hex, validate_key, HorcrustStoreKey, Result,
+    KEY_SEPARATOR,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// A group of clients with its own keyspace: the keys of its clients are stored below its name,
+/// so `db/password` of tenant `payments` is `payments/db/password` on the server.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Tenant {
+    pub name: String,
+    /// the peer ids of the clients of the tenant: their hex encoded public identities, or the
+    /// fingerprints of their certificates with TLS.
+    pub clients: Vec<String>,
+    /// the most keys the tenant can store, unlimited if missing.
+    pub max_keys: Option<usize>,
+    /// the biggest share the tenant can store, in bytes, unlimited if missing.
+    pub max_share_size: Option<usize>,
+}
+impl Tenant {
+    /// The key on the server of the tenant's `key`.
+    pub fn server_key(&self, key: &str) -> HorcrustStoreKey {
+        format!("{}{KEY_SEPARATOR}{key}", self.name)
+    }
+    /// The tenant's key of a key on the server, if it is in the tenant's keyspace.
+    pub fn client_key<'a>(&self, key: &'a str) -> Option<&'a str> {
+        key.strip_prefix(&self.name)?.strip_prefix(KEY_SEPARATOR)
+    }
+    /// Moves the keys of a request from a client of the tenant to its keyspace. Requests only
+    /// sent by servers are refused.
+    pub fn scope(&self, request: Request) -> Result<Request> {
+        Ok(match request {
+            Request::PutShare(mut put) => {
+                put.key = self.server_key(&put.key);
+                Request::PutShare(put)
+            }
+            Request::GetShare(mut get) => {
+                get.key = self.server_key(&get.key);
+                Request::GetShare(get)
+            }
+            Request::DeleteShare(mut delete) => {
+                delete.key = self.server_key(&delete.key);
+                Request::DeleteShare(delete)
+            }
+            Request::ListKeys(mut list) => {
+                list.prefix = self.server_key(&list.prefix);
+                if !list.start.is_empty() {
+                    list.start = self.server_key(&list.start);
+                }
+                Request::ListKeys(list)
+            }
+            Request::Reshare(mut reshare) => {
+                reshare.key = self.server_key(&reshare.key);
+                Request::Reshare(reshare)
+            }
+            _ => bail!("Only servers can send this request."),
+        })
+    }
+    /// Fails if storing a share of `share_len` bytes with the key exceeds the quotas of the
+    /// tenant. Replacing a share doesn't count as a new key.
+    pub fn check_quotas(&self, db: &SharesDatabase, key: &str, share_len: usize) -> Result<()> {
+        if let Some(max_share_size) = self.max_share_size {
+            ensure!(
+                share_len <= max_share_size,
+                "Quota exceeded: the share is {share_len} bytes, tenant '{}' can store shares of at most {max_share_size} bytes.",
+                self.name
+            );
+        }
+        if let Some(max_keys) = self.max_keys {
+            ensure!(
+                db.get_stored(key).is_some() || db.count_keys(&self.name) < max_keys,
+                "Quota exceeded: tenant '{}' can store at most {max_keys} keys.",
+                self.name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The tenants of the server, by the identities of their clients. If there are any, peers that
+/// are not clients of a tenant can't access any key.
+#[derive(Clone, Debug, Default)]
+pub struct Tenants {
+    tenants: Vec<Tenant>,
+    /// the position of the tenant of every client, by peer id.
+    clients: HashMap<String, usize>,
+}
+impl Tenants {
+    pub fn new(tenants: Vec<Tenant>) -> Result<Self> {
+        let mut clients = HashMap::new();
+        for (i, tenant) in tenants.iter().enumerate() {
+            validate_key(&tenant.name).context("Invalid tenant name")?;
+            ensure!(
+                !tenant.name.contains(KEY_SEPARATOR),
+                "Invalid tenant name '{}': it can't contain '{KEY_SEPARATOR}'.",
+                tenant.name
+            );
+            ensure!(
+                tenants[..i].iter().all(|other| other.name != tenant.name),
+                "Tenant '{}' is defined twice.",
+                tenant.name
+            );
+            for client in tenant.clients.iter() {
+                let peer_id = peer_id_from_hex(client)
+                    .context(format!("Invalid client of tenant '{}'", tenant.name))?;
+                if let Some(other) = clients.insert(peer_id, i) {
+                    bail!(
+                        "Client {client} belongs to both tenant '{}' and '{}'.",
+                        tenants[other].name,
+                        tenant.name
+                    );
+                }
+            }
+        }
+        Ok(Self { tenants, clients })
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tenants.is_empty()
+    }
+    /// The tenant of the peer, None if the peer is not the client of any tenant.
+    pub fn tenant_of(&self, peer: &str) -> Option<&Tenant> {
+        self.clients.get(peer).map(|i| &self.tenants[*i])
+    }
+    /// The tenant whose keyspace holds the key on the server, if any.
+    pub fn tenant_of_key(&self, key: &str) -> Option<&Tenant> {
+        self.tenants
+            .iter()
+            .find(|tenant| tenant.client_key(key).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use horcrust::{
+        msg_refresh_commit_request, msg_retrieve_secret_request, public_identity_to_hex, Identity,
+    };
+
+    fn tenant(name: &str, clients: Vec<String>) -> Tenant {
+        Tenant {
+            name: name.into(),
+            clients,
+            max_keys: None,
+            max_share_size: None,
+        }
+    }
+
+    #[test]
+    fn test_tenants() -> Result<()> {
+        let [client, other] =
+            [(); 2].map(|_| public_identity_to_hex(&Identity::generate().public()));
+        // peer ids are compared in lowercase.
+        let tenants = Tenants::new(vec![tenant("payments", vec![client.to_uppercase()])])?;
+        assert_eq!(tenants.tenant_of(&client).unwrap().name, "payments");
+        assert!(tenants.tenant_of(&other).is_none());
+        assert_eq!(
+            tenants.tenant_of_key("payments/db").unwrap().name,
+            "payments"
+        );
+        assert!(tenants.tenant_of_key("billing/db").is_none());
+
+        let payments = tenant("payments", vec![]);
+        assert_eq!(payments.server_key("db/password"), "payments/db/password");
+        assert_eq!(payments.client_key("payments/db"), Some("db"));
+        assert_eq!(payments.client_key("paymentsx/db"), None);
+        let get = msg_retrieve_secret_request("db".into()).request.unwrap();
+        assert_eq!(
+            payments.scope(get)?,
+            msg_retrieve_secret_request("payments/db".into())
+                .request
+                .unwrap()
+        );
+        let commit = msg_refresh_commit_request(1, true, vec![]).request.unwrap();
+        assert!(payments.scope(commit).is_err());
+
+        assert!(Tenants::new(vec![tenant("a/b", vec![])]).is_err());
+        assert!(Tenants::new(vec![tenant("a", vec![]), tenant("a", vec![])]).is_err());
+        assert!(Tenants::new(vec![tenant("a", vec!["00".into()])]).is_err());
+        assert!(Tenants::new(vec![
+            tenant("a", vec![client.clone()]),
+            tenant("b", vec![client])
+        ])
+        .is_err());
+        Ok(())
+    }
+}
@@ -1,184 +1,560 @@
+use crate::framing::{decode_message, HANDSHAKE_TIMEOUT, IDLE_TIMEOUT};
+use crate::framing::{read_frame, read_frame_async, set_timeouts, write_frame, write_frame_async};
+use crate::identity::{public_identity_to_hex, HandshakeConfig, PublicIdentity};
 use crate::{HorcrustMsgRequest, HorcrustMsgResponse, RawMessage, Result};
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
     Aes256Gcm,
     Key, // Or `Aes128Gcm`
     Nonce,
 };
-use num_bigint::BigUint;
-use num_traits::ToPrimitive;
+use anyhow::{anyhow, ensure};
+use async_trait::async_trait;
+use ed25519_dalek::{Signature, VerifyingKey};
+use hkdf::Hkdf;
 use prost::Message;
-use rand::random;
+use sha2::{Digest, Sha256};
 use std::io::{Read, Write};
-use std::net::Shutdown;
-use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use x25519_dalek::{EphemeralSecret, PublicKey};
 
 pub trait ConnectionHandler<Req, Res> {
     fn send(&mut self, message: Req) -> Result<()>;
     fn receive(&mut self) -> Result<Res>;
+    /// The peer id of the authenticated peer, see peer_id_from_hex.
+    fn peer_id(&self) -> String;
+}
+
+/// Like ConnectionHandler, for connections served by an async runtime.
+#[async_trait]
+pub trait AsyncConnectionHandler<Req, Res> {
+    async fn send(&mut self, message: Req) -> Result<()>;
+    async fn receive(&mut self) -> Result<Res>;
+    /// The peer id of the authenticated peer, see peer_id_from_hex.
+    fn peer_id(&self) -> String;
 }
 
 pub struct TcpConnectionHandler {
     socket: std::net::TcpStream,
-    cipher: Aes256Gcm,
+    session: Session,
+    peer_identity: PublicIdentity,
 }
 impl TcpConnectionHandler {
-    pub fn new(socket: std::net::TcpStream) -> Result<Self> {
-        // TODO super secret key
-        let key: &[u8; 32] = &[42; 32];
-        let key: &Key<Aes256Gcm> = key.into();
-        let cipher = Aes256Gcm::new(key);
-        socket.set_read_timeout(Some(Duration::from_secs(1)))?;
-        socket.set_write_timeout(Some(Duration::from_secs(1)))?;
-        let mut ret = Self { socket, cipher };
-        // comment this to enable replay attacks :D
-        ret.handshake()?;
-        Ok(ret)
-    }
-    pub fn handshake(&mut self) -> Result<()> {
-        let (public_key, private_key) = generate_pk(P, G);
-        self.socket.write_all(&public_key.to_le_bytes())?;
-        let public_key_b = self.handshake_receive_pk()?;
-        let session_key = generate_session_key(private_key, public_key_b, P);
-        let mut key = vec![42; 32];
-        // TODO: should do some key expansion instead.
-        let session_key = session_key.to_le_bytes();
-        for byte in 0..32 {
-            key[byte] = session_key[byte % 8];
+    /// Runs the handshake: fails if the peer is not one of the allowed peers.
+    pub fn new(mut socket: std::net::TcpStream, config: &HandshakeConfig) -> Result<Self> {
+        set_timeouts(&socket, HANDSHAKE_TIMEOUT)?;
+        let handshake = Handshake::new(config);
+        socket.write_all(&handshake.hello())?;
+        let mut peer_hello = [0; 64];
+        socket.read_exact(&mut peer_hello)?;
+        let (handshake, signature) = handshake.receive_hello(&peer_hello)?;
+        socket.write_all(&signature)?;
+        let mut peer_signature = [0; 64];
+        socket.read_exact(&mut peer_signature)?;
+        let (session, peer_identity) = handshake.finish(&peer_signature)?;
+        set_timeouts(&socket, IDLE_TIMEOUT)?;
+        Ok(Self {
+            socket,
+            session,
+            peer_identity,
+        })
+    }
+    /// The identity of the peer, authenticated during the handshake.
+    pub fn peer_identity(&self) -> &PublicIdentity {
+        &self.peer_identity
+    }
+}
+
+/// The async version of TcpConnectionHandler, they can talk to each other.
+pub struct AsyncTcpConnectionHandler {
+    socket: tokio::net::TcpStream,
+    session: Session,
+    peer_identity: PublicIdentity,
+}
+impl AsyncTcpConnectionHandler {
+    /// Runs the handshake: fails if the peer is not one of the allowed peers, or if it takes
+    /// longer than HANDSHAKE_TIMEOUT.
+    pub async fn new(mut socket: tokio::net::TcpStream, config: &HandshakeConfig) -> Result<Self> {
+        let handshake = async {
+            let handshake = Handshake::new(config);
+            socket.write_all(&handshake.hello()).await?;
+            let mut peer_hello = [0; 64];
+            socket.read_exact(&mut peer_hello).await?;
+            let (handshake, signature) = handshake.receive_hello(&peer_hello)?;
+            socket.write_all(&signature).await?;
+            let mut peer_signature = [0; 64];
+            socket.read_exact(&mut peer_signature).await?;
+            handshake.finish(&peer_signature)
+        };
+        let (session, peer_identity) = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+            .await
+            .map_err(|_| anyhow!("The handshake timed out."))??;
+        Ok(Self {
+            socket,
+            session,
+            peer_identity,
+        })
+    }
+}
+
+/// Ephemeral X25519 key exchange, authenticated with the long-term identities: every side sends its
+/// ephemeral public key and its identity, then signs the transcript (both of them) with its
+/// identity. The session key is derived with HKDF-SHA256 from the shared secret, salted with the
+/// transcript, so it is bound to this exchange.
+/// The steps don't do any IO, so they are shared by the sync and async handlers.
+struct Handshake<'a> {
+    config: &'a HandshakeConfig,
+    private_key: EphemeralSecret,
+    public_key: PublicKey,
+}
+/// The handshake after the hellos were exchanged, waiting for the peer's signature.
+struct SignedHandshake {
+    private_key: EphemeralSecret,
+    peer_public_key: PublicKey,
+    peer_identity: PublicIdentity,
+    transcript: Vec<u8>,
+}
+impl<'a> Handshake<'a> {
+    fn new(config: &'a HandshakeConfig) -> Self {
+        let private_key = EphemeralSecret::random_from_rng(OsRng);
+        let public_key = PublicKey::from(&private_key);
+        Self {
+            config,
+            private_key,
+            public_key,
         }
-        let key = Key::<Aes256Gcm>::from_slice(key.as_slice());
-        self.cipher = Aes256Gcm::new(key);
-        Ok(())
     }
-    fn handshake_receive_pk(&mut self) -> Result<u64> {
-        let mut pk = [0; 8];
-        self.socket.read_exact(&mut pk)?;
-        Ok(u64::from_le_bytes(pk))
+    /// The ephemeral public key followed by the identity.
+    fn hello(&self) -> [u8; 64] {
+        let mut hello = [0; 64];
+        hello[..32].copy_from_slice(self.public_key.as_bytes());
+        hello[32..].copy_from_slice(self.config.identity.public().as_bytes());
+        hello
+    }
+    /// Checks the peer's hello, and returns the signature of the transcript to send to the peer.
+    fn receive_hello(self, peer_hello: &[u8; 64]) -> Result<(SignedHandshake, [u8; 64])> {
+        let peer_public_key = PublicKey::from(<[u8; 32]>::try_from(&peer_hello[..32]).unwrap());
+        let peer_identity = VerifyingKey::from_bytes(&peer_hello[32..].try_into().unwrap())?;
+        // a reflected hello would make us talk to ourselves.
+        ensure!(
+            peer_public_key != self.public_key,
+            "Reflected public key received during the handshake."
+        );
+        ensure!(
+            self.config.allowed_peers.contains(&peer_identity),
+            "Unknown peer identity: {}",
+            public_identity_to_hex(&peer_identity)
+        );
+        let identity = self.config.identity.public();
+        let transcript = handshake_transcript(
+            (&self.public_key, &identity),
+            (&peer_public_key, &peer_identity),
+        );
+        let signature = self.config.identity.sign(&transcript).to_bytes();
+        let signed = SignedHandshake {
+            private_key: self.private_key,
+            peer_public_key,
+            peer_identity,
+            transcript,
+        };
+        Ok((signed, signature))
+    }
+}
+impl SignedHandshake {
+    fn finish(self, peer_signature: &[u8; 64]) -> Result<(Session, PublicIdentity)> {
+        self.peer_identity
+            .verify_strict(&self.transcript, &Signature::from_bytes(peer_signature))
+            .map_err(|_| anyhow!("Invalid handshake signature from the peer."))?;
+
+        let shared_secret = self.private_key.diffie_hellman(&self.peer_public_key);
+        // rejects low order points, which would make the shared secret predictable.
+        ensure!(
+            shared_secret.was_contributory(),
+            "Invalid public key received during the handshake."
+        );
+        let hkdf = Hkdf::<Sha256>::new(Some(&self.transcript), shared_secret.as_bytes());
+        let mut key = [0; 32];
+        // safe unwrap: 32 bytes is a valid output length for HKDF-SHA256.
+        hkdf.expand(SESSION_KEY_INFO, &mut key).unwrap();
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        Ok((Session::new(cipher), self.peer_identity))
+    }
+}
+
+const SESSION_KEY_INFO: &[u8] = b"horcrust session key";
+const TRANSCRIPT_LABEL: &[u8] = b"horcrust handshake x25519 ed25519";
+
+/// Both sides send their keys at the same time, so the transcript sorts them by ephemeral public
+/// key to get the same hash on both ends.
+fn handshake_transcript(
+    a: (&PublicKey, &PublicIdentity),
+    b: (&PublicKey, &PublicIdentity),
+) -> Vec<u8> {
+    let (first, second) = if a.0.as_bytes() <= b.0.as_bytes() {
+        (a, b)
+    } else {
+        (b, a)
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(TRANSCRIPT_LABEL);
+    for (public_key, identity) in [first, second] {
+        hasher.update(public_key.as_bytes());
+        hasher.update(identity.as_bytes());
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Requests go from clients to servers, responses the other way around. The direction is
+/// authenticated with every message, so a message can't be sent back to its sender.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Direction {
+    Request,
+    Response,
+}
+impl Direction {
+    fn label(self) -> &'static [u8] {
+        match self {
+            Direction::Request => b"horcrust request",
+            Direction::Response => b"horcrust response",
+        }
     }
 }
 
-// server side - for simplicty I've duplicated the code as generated protobuf doesn't come with
-// a common trait to reuse encode/decode.
+/// The encryption state of a connection after the handshake.
+struct Session {
+    cipher: Aes256Gcm,
+    /// sequence numbers of the next message to send and to receive.
+    sent: u64,
+    received: u64,
+}
+impl Session {
+    fn new(cipher: Aes256Gcm) -> Self {
+        Self {
+            cipher,
+            sent: 0,
+            received: 0,
+        }
+    }
+    /// Encrypts the next message to send, every message is sent in its own frame.
+    fn seal<M: Message>(&mut self, message: M, direction: Direction) -> Result<Vec<u8>> {
+        let payload = encrypt_payload(&self.cipher, direction, self.sent, message.encode_to_vec())?;
+        self.sent += 1;
+        Ok(payload)
+    }
+    /// Decrypts the next received frame.
+    fn open<M: Message + Default>(&mut self, frame: Vec<u8>, direction: Direction) -> Result<M> {
+        let payload = decrypt_payload(&self.cipher, direction, self.received, frame)?;
+        self.received += 1;
+        decode_message(&payload)
+    }
+}
+
+/// client side:
 impl ConnectionHandler<HorcrustMsgRequest, HorcrustMsgResponse> for TcpConnectionHandler {
     fn send(&mut self, message: HorcrustMsgRequest) -> Result<()> {
-        let mut buf = Vec::new();
-        message.encode(&mut buf)?;
-        let nonce = Aes256Gcm::generate_nonce(&mut OsRng); // 96-bits; unique per message
-        let encrypted_payload = self.cipher.encrypt(&nonce, buf.as_ref())?;
-        let nonce = nonce.to_vec();
-        let message = RawMessage {
-            nonce,
-            encrypted_payload,
-        };
-        message.encode(&mut buf)?;
-        self.socket.write_all(&buf)?;
-        self.socket.shutdown(Shutdown::Write)?;
-        Ok(())
+        let frame = self.session.seal(message, Direction::Request)?;
+        write_frame(&mut self.socket, &frame)
     }
-
     fn receive(&mut self) -> Result<HorcrustMsgResponse> {
-        let mut buf = Vec::new();
-        self.socket.read_to_end(&mut buf)?;
-        let buf = decrypt_payload(&self.cipher, buf)?;
-        Ok(HorcrustMsgResponse::decode(buf.as_slice())?)
+        let frame = read_frame(&mut self.socket)?;
+        self.session.open(frame, Direction::Response)
+    }
+    fn peer_id(&self) -> String {
+        public_identity_to_hex(&self.peer_identity)
     }
 }
 
-/// client side:
+/// server side:
 impl ConnectionHandler<HorcrustMsgResponse, HorcrustMsgRequest> for TcpConnectionHandler {
     fn send(&mut self, message: HorcrustMsgResponse) -> Result<()> {
-        let mut buf = Vec::new();
-        message.encode(&mut buf)?;
-        self.socket
-            .write_all(encrypt_payload(&self.cipher, buf)?.as_slice())?;
-        self.socket.shutdown(Shutdown::Write)?;
-        Ok(())
+        let frame = self.session.seal(message, Direction::Response)?;
+        write_frame(&mut self.socket, &frame)
     }
-
     fn receive(&mut self) -> Result<HorcrustMsgRequest> {
-        let mut buf = Vec::new();
-        self.socket.read_to_end(&mut buf)?;
-        let buf = decrypt_payload(&self.cipher, buf)?;
-        Ok(HorcrustMsgRequest::decode(buf.as_slice())?)
+        let frame = read_frame(&mut self.socket)?;
+        self.session.open(frame, Direction::Request)
+    }
+    fn peer_id(&self) -> String {
+        public_identity_to_hex(&self.peer_identity)
+    }
+}
+
+#[async_trait]
+impl AsyncConnectionHandler<HorcrustMsgRequest, HorcrustMsgResponse> for AsyncTcpConnectionHandler {
+    async fn send(&mut self, message: HorcrustMsgRequest) -> Result<()> {
+        let frame = self.session.seal(message, Direction::Request)?;
+        write_frame_async(&mut self.socket, &frame).await
+    }
+    async fn receive(&mut self) -> Result<HorcrustMsgResponse> {
+        let frame = read_frame_async(&mut self.socket).await?;
+        self.session.open(frame, Direction::Response)
+    }
+    fn peer_id(&self) -> String {
+        public_identity_to_hex(&self.peer_identity)
     }
 }
 
-fn decrypt_payload(cipher: &Aes256Gcm, encrypted_payload: Vec<u8>) -> Result<Vec<u8>> {
+#[async_trait]
+impl AsyncConnectionHandler<HorcrustMsgResponse, HorcrustMsgRequest> for AsyncTcpConnectionHandler {
+    async fn send(&mut self, message: HorcrustMsgResponse) -> Result<()> {
+        let frame = self.session.seal(message, Direction::Response)?;
+        write_frame_async(&mut self.socket, &frame).await
+    }
+    async fn receive(&mut self) -> Result<HorcrustMsgRequest> {
+        let frame = read_frame_async(&mut self.socket).await?;
+        self.session.open(frame, Direction::Request)
+    }
+    fn peer_id(&self) -> String {
+        public_identity_to_hex(&self.peer_identity)
+    }
+}
+
+/// The associated data of a message: its direction and its sequence number.
+fn associated_data(direction: Direction, sequence: u64) -> Vec<u8> {
+    let mut aad = direction.label().to_vec();
+    aad.extend_from_slice(&sequence.to_be_bytes());
+    aad
+}
+
+/// Fails unless the message is the one expected next, in the given direction.
+fn decrypt_payload(
+    cipher: &Aes256Gcm,
+    direction: Direction,
+    expected_sequence: u64,
+    encrypted_payload: Vec<u8>,
+) -> Result<Vec<u8>> {
     let message = RawMessage::decode(encrypted_payload.as_slice())?;
+    ensure!(
+        message.sequence >= expected_sequence,
+        "Replayed message: sequence number {}, expected {expected_sequence}.",
+        message.sequence
+    );
+    ensure!(
+        message.sequence == expected_sequence,
+        "Out of order message: sequence number {}, expected {expected_sequence}.",
+        message.sequence
+    );
+    ensure!(message.nonce.len() == 12, "Invalid nonce length.");
     let nonce = Nonce::from_slice(message.nonce.as_slice());
-    Ok(cipher.decrypt(nonce, message.encrypted_payload.as_slice())?)
+    let payload = Payload {
+        msg: message.encrypted_payload.as_slice(),
+        aad: &associated_data(direction, message.sequence),
+    };
+    cipher
+        .decrypt(nonce, payload)
+        .map_err(|_| anyhow!("Failed to decrypt the message: wrong key, direction or sequence."))
 }
-fn encrypt_payload(cipher: &Aes256Gcm, pt_payload: Vec<u8>) -> Result<Vec<u8>> {
+fn encrypt_payload(
+    cipher: &Aes256Gcm,
+    direction: Direction,
+    sequence: u64,
+    pt_payload: Vec<u8>,
+) -> Result<Vec<u8>> {
     let nonce = Aes256Gcm::generate_nonce(&mut OsRng); // 96-bits; unique per message
-    let encrypted_payload = cipher.encrypt(&nonce, pt_payload.as_ref())?;
+    let payload = Payload {
+        msg: pt_payload.as_ref(),
+        aad: &associated_data(direction, sequence),
+    };
+    let encrypted_payload = cipher.encrypt(&nonce, payload)?;
     let nonce = nonce.to_vec();
     let message = RawMessage {
         nonce,
         encrypted_payload,
+        sequence,
     };
     let mut buf = Vec::new();
     message.encode(&mut buf)?;
     Ok(buf)
 }
 
-// TODO: bigger P.
-const P: u64 = 18446744073709551557;
-const G: u64 = 2;
-
-pub fn generate_pk(p: u64, g: u64) -> (u64, u64) {
-    let priv_a: u64 = random::<u64>();
-    let pub_a = modpow(g, priv_a, p);
-    (pub_a, priv_a)
-}
-pub fn generate_session_key(prvkey_a: u64, pk_b: u64, p: u64) -> u64 {
-    // s = (B**a) % p.
-    modpow(pk_b, prvkey_a, p)
-}
-
-pub fn modpow(base: u64, exp: u64, n: u64) -> u64 {
-    let base: BigUint = base.into();
-    let exp = exp.into();
-    let n = n.into();
-    base.modpow(&exp, &n).to_u64().unwrap()
-}
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{msg_store_share_request, msg_success_response};
+    use crate::identity::{AllowedPeers, Identity};
+    use crate::{
+        msg_store_share_request, msg_success_response, AdditiveSecretSharing, ConnectionClosed,
+        MalformedMessage, SecretSharing,
+    };
     use std::sync::mpsc;
     #[test]
     fn test_encrypt_decrypt() -> anyhow::Result<()> {
         let key = Aes256Gcm::generate_key(OsRng);
         let cipher = Aes256Gcm::new(&key);
         let pt_payload = b"Hello World!";
-        let encrypted_payload = encrypt_payload(&cipher, pt_payload.to_vec())?;
-        let decrypted_payload = decrypt_payload(&cipher, encrypted_payload)?;
+        let encrypted_payload =
+            encrypt_payload(&cipher, Direction::Request, 0, pt_payload.to_vec())?;
+        let decrypted_payload =
+            decrypt_payload(&cipher, Direction::Request, 0, encrypted_payload.clone())?;
         assert_eq!(pt_payload, decrypted_payload.as_slice());
+
+        // the same message can't be received twice, or in the other direction.
+        let replayed = decrypt_payload(&cipher, Direction::Request, 1, encrypted_payload.clone());
+        assert!(replayed.unwrap_err().to_string().contains("Replayed"));
+        assert!(decrypt_payload(&cipher, Direction::Response, 0, encrypted_payload).is_err());
+        let skipped = encrypt_payload(&cipher, Direction::Request, 2, pt_payload.to_vec())?;
+        let out_of_order = decrypt_payload(&cipher, Direction::Request, 1, skipped);
+        assert!(out_of_order
+            .unwrap_err()
+            .to_string()
+            .contains("Out of order"));
+
+        // the sequence number is authenticated.
+        let mut tampered = RawMessage::decode(
+            encrypt_payload(&cipher, Direction::Request, 1, vec![1])?.as_slice(),
+        )?;
+        tampered.sequence = 2;
+        assert!(decrypt_payload(&cipher, Direction::Request, 2, tampered.encode_to_vec()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_message() -> anyhow::Result<()> {
+        let cipher = Aes256Gcm::new(&Aes256Gcm::generate_key(OsRng));
+        let mut session = Session::new(cipher.clone());
+        let garbage = encrypt_payload(&cipher, Direction::Request, 0, vec![0xff; 3])?;
+        let result: Result<HorcrustMsgRequest> = session.open(garbage, Direction::Request);
+        assert!(result.unwrap_err().is::<MalformedMessage>());
+        // the session can still receive the next messages.
+        let valid = encrypt_payload(&cipher, Direction::Request, 1, vec![])?;
+        let request: HorcrustMsgRequest = session.open(valid, Direction::Request)?;
+        assert_eq!(request, HorcrustMsgRequest::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_handshake_transcript() {
+        let a = PublicKey::from(&EphemeralSecret::random_from_rng(OsRng));
+        let b = PublicKey::from(&EphemeralSecret::random_from_rng(OsRng));
+        let c = PublicKey::from(&EphemeralSecret::random_from_rng(OsRng));
+        let id_a = Identity::generate().public();
+        let id_b = Identity::generate().public();
+        assert_eq!(
+            handshake_transcript((&a, &id_a), (&b, &id_b)),
+            handshake_transcript((&b, &id_b), (&a, &id_a))
+        );
+        assert_ne!(
+            handshake_transcript((&a, &id_a), (&b, &id_b)),
+            handshake_transcript((&a, &id_a), (&c, &id_b))
+        );
+        assert_ne!(
+            handshake_transcript((&a, &id_a), (&b, &id_b)),
+            handshake_transcript((&a, &id_b), (&b, &id_a))
+        );
+    }
+
+    /// Two configs that allow each other.
+    fn handshake_configs() -> (HandshakeConfig, HandshakeConfig) {
+        let (a, b) = (Identity::generate(), Identity::generate());
+        let peers = AllowedPeers::new([a.public(), b.public()]);
+        (
+            HandshakeConfig::new(a, peers.clone()),
+            HandshakeConfig::new(b, peers),
+        )
+    }
+
+    #[test]
+    fn test_unknown_identity_refused() -> anyhow::Result<()> {
+        let (server_config, _) = handshake_configs();
+        let stranger =
+            HandshakeConfig::new(Identity::generate(), server_config.allowed_peers.clone());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let server_thread = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            TcpConnectionHandler::new(socket, &server_config).err()
+        });
+        let socket = std::net::TcpStream::connect(format!("127.0.0.1:{}", port))?;
+        assert!(TcpConnectionHandler::new(socket, &stranger).is_err());
+        let server_error = server_thread
+            .join()
+            .unwrap()
+            .expect("the stranger is refused");
+        assert!(server_error.to_string().contains("Unknown peer identity"));
         Ok(())
     }
 
     #[test]
     fn test_tcp_encrypted_channel() -> anyhow::Result<()> {
         let (sender, receiver) = mpsc::channel();
-        const REQUEST: HorcrustMsgRequest = msg_store_share_request(1234, 1234);
+        let request = msg_store_share_request(
+            "1234".into(),
+            0,
+            vec![12, 34],
+            AdditiveSecretSharing::default().scheme(),
+            vec![],
+        );
+        let expected_request = request.clone();
         const RESPONSE: HorcrustMsgResponse = msg_success_response();
+        let (server_config, client_config) = handshake_configs();
+        let client_identity = client_config.identity.public();
         let server_thread = std::thread::spawn(move || {
             let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
             let port = listener.local_addr().unwrap().port();
             sender.send(port).expect("Failed to send port");
             let (socket, _) = listener.accept().unwrap();
-            let mut handler = TcpConnectionHandler::new(socket).unwrap();
-            let request = handler.receive().unwrap();
-            assert_eq!(REQUEST, request);
-            handler.send(RESPONSE).unwrap();
+            let mut handler = TcpConnectionHandler::new(socket, &server_config).unwrap();
+            assert_eq!(handler.peer_identity(), &client_identity);
+            // the session carries many requests, until the client closes it.
+            for _ in 0..2 {
+                let request = handler.receive().unwrap();
+                assert_eq!(expected_request, request);
+                handler.send(RESPONSE).unwrap();
+            }
+            let closed: Result<HorcrustMsgRequest> = handler.receive();
+            assert!(closed.unwrap_err().is::<ConnectionClosed>());
         });
         let port = receiver.recv()?;
         let socket = std::net::TcpStream::connect(format!("127.0.0.1:{}", port))?;
-        let mut handler = TcpConnectionHandler::new(socket)?;
-        let request = msg_store_share_request(1234, 1234);
-        handler.send(request)?;
-        assert_eq!(msg_success_response(), handler.receive()?);
+        let mut handler = TcpConnectionHandler::new(socket, &client_config)?;
+        for _ in 0..2 {
+            handler.send(request.clone())?;
+            assert_eq!(msg_success_response(), handler.receive()?);
+        }
+        drop(handler);
         server_thread.join().unwrap();
         Ok(())
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_async_server() -> anyhow::Result<()> {
+        let (server_config, client_config) = handshake_configs();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let server = tokio::spawn(async move {
+            let mut errors = vec![];
+            for _ in 0..2 {
+                let (socket, _) = listener.accept().await.unwrap();
+                let config = server_config.clone();
+                // every connection is served by its own task.
+                errors.push(tokio::spawn(async move {
+                    let mut handler = AsyncTcpConnectionHandler::new(socket, &config).await?;
+                    let _request: HorcrustMsgRequest = handler.receive().await?;
+                    handler.send(msg_success_response()).await
+                }));
+            }
+            errors
+        });
+        // a client that connects and never sends anything doesn't block the others.
+        let silent = std::net::TcpStream::connect(("127.0.0.1", port))?;
+        let client = tokio::task::spawn_blocking(move || -> Result<HorcrustMsgResponse> {
+            let socket = std::net::TcpStream::connect(("127.0.0.1", port))?;
+            let mut handler = TcpConnectionHandler::new(socket, &client_config)?;
+            handler.send(msg_store_share_request(
+                "1".into(),
+                0,
+                vec![1],
+                AdditiveSecretSharing::default().scheme(),
+                vec![],
+            ))?;
+            handler.receive()
+        });
+        assert_eq!(client.await??, msg_success_response());
+        let mut tasks = server.await?;
+        let client_task = tasks.pop().unwrap();
+        client_task.await??;
+        let silent_error = tasks.pop().unwrap().await?.unwrap_err();
+        assert!(silent_error.to_string().contains("timed out"));
+        drop(silent);
+        Ok(())
+    }
 }
@@ -0,0 +1,160 @@
+use crate::Result;
+use anyhow::ensure;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::timeout;
+
+/// Messages bigger than this are refused, so a peer can't make us allocate arbitrary memory.
+pub const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+/// How long the handshake can take.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+/// After the handshake, connections carry many messages: a peer that doesn't send anything for
+/// this long is considered gone.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Returned by `ConnectionHandler::receive` when the peer closed the connection between two
+/// messages, which is how sessions end.
+#[derive(Debug)]
+pub struct ConnectionClosed;
+impl std::fmt::Display for ConnectionClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Connection closed by the peer.")
+    }
+}
+impl std::error::Error for ConnectionClosed {}
+
+/// Returned by `ConnectionHandler::receive` when a message was received, but it is not a valid
+/// message. The connection can still be used, e.g. to reply with an error.
+#[derive(Debug)]
+pub struct MalformedMessage(pub prost::DecodeError);
+impl std::fmt::Display for MalformedMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Malformed message: {}", self.0)
+    }
+}
+impl std::error::Error for MalformedMessage {}
+
+/// Decodes a received message, see MalformedMessage.
+pub(crate) fn decode_message<M: prost::Message + Default>(payload: &[u8]) -> Result<M> {
+    M::decode(payload).map_err(|err| MalformedMessage(err).into())
+}
+
+/// Frames are a big endian u32 length followed by the payload.
+pub(crate) fn write_frame(writer: &mut impl Write, payload: &[u8]) -> Result<()> {
+    ensure!(
+        payload.len() <= MAX_MESSAGE_LEN,
+        "Message of {} bytes is too big.",
+        payload.len()
+    );
+    writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+    writer.write_all(payload)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub(crate) fn read_frame(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let mut len = [0; 4];
+    frame_start(reader.read_exact(&mut len))?;
+    let mut payload = vec![0; frame_len(len)?];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+/// Like write_frame, fails if the peer doesn't read it within IDLE_TIMEOUT.
+pub(crate) async fn write_frame_async(
+    writer: &mut (impl AsyncWrite + Unpin),
+    payload: &[u8],
+) -> Result<()> {
+    ensure!(
+        payload.len() <= MAX_MESSAGE_LEN,
+        "Message of {} bytes is too big.",
+        payload.len()
+    );
+    let write = async {
+        writer
+            .write_all(&(payload.len() as u32).to_be_bytes())
+            .await?;
+        writer.write_all(payload).await?;
+        writer.flush().await
+    };
+    Ok(timeout(IDLE_TIMEOUT, write).await??)
+}
+
+/// Like read_frame, fails if the peer doesn't send a whole frame within IDLE_TIMEOUT.
+pub(crate) async fn read_frame_async(reader: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>> {
+    let read = async {
+        let mut len = [0; 4];
+        frame_start(reader.read_exact(&mut len).await.map(|_| ()))?;
+        let mut payload = vec![0; frame_len(len)?];
+        reader.read_exact(&mut payload).await?;
+        Ok(payload)
+    };
+    timeout(IDLE_TIMEOUT, read).await?
+}
+
+/// A clean end of the stream is only expected before a new frame.
+fn frame_start(read: std::io::Result<()>) -> Result<()> {
+    read.map_err(|err| match err.kind() {
+        ErrorKind::UnexpectedEof => ConnectionClosed.into(),
+        _ => err.into(),
+    })
+}
+
+fn frame_len(len: [u8; 4]) -> Result<usize> {
+    let len = u32::from_be_bytes(len) as usize;
+    ensure!(len <= MAX_MESSAGE_LEN, "Message of {len} bytes is too big.");
+    Ok(len)
+}
+
+pub(crate) fn set_timeouts(socket: &TcpStream, timeout: Duration) -> Result<()> {
+    socket.set_read_timeout(Some(timeout))?;
+    socket.set_write_timeout(Some(timeout))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames() -> Result<()> {
+        let mut stream = vec![];
+        write_frame(&mut stream, b"first")?;
+        write_frame(&mut stream, b"")?;
+        write_frame(&mut stream, b"second")?;
+        let mut reader = stream.as_slice();
+        assert_eq!(read_frame(&mut reader)?, b"first");
+        assert_eq!(read_frame(&mut reader)?, b"");
+        assert_eq!(read_frame(&mut reader)?, b"second");
+        assert!(read_frame(&mut reader)
+            .unwrap_err()
+            .is::<ConnectionClosed>());
+
+        // a truncated frame is an error, not the end of the session.
+        let mut reader = &stream[..7];
+        assert!(!read_frame(&mut reader)
+            .unwrap_err()
+            .is::<ConnectionClosed>());
+        let too_big = (MAX_MESSAGE_LEN as u32 + 1).to_be_bytes();
+        assert!(read_frame(&mut too_big.as_slice()).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_frames() -> Result<()> {
+        let mut stream = vec![];
+        write_frame_async(&mut stream, b"first").await?;
+        // both versions use the same format.
+        write_frame(&mut stream, b"second")?;
+        let mut reader = stream.as_slice();
+        assert_eq!(read_frame_async(&mut reader).await?, b"first");
+        assert_eq!(read_frame_async(&mut reader).await?, b"second");
+        assert!(read_frame_async(&mut reader)
+            .await
+            .unwrap_err()
+            .is::<ConnectionClosed>());
+        Ok(())
+    }
+}
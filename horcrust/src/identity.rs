@@ -0,0 +1,147 @@
+use crate::Result;
+use aes_gcm::aead::OsRng;
+use anyhow::{anyhow, Context};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The public part of an Identity, used by peers to recognize it.
+pub type PublicIdentity = VerifyingKey;
+
+/// Long-term Ed25519 identity key of a server or client, used to sign the handshake.
+pub struct Identity {
+    signing_key: SigningKey,
+}
+impl Identity {
+    pub fn generate() -> Self {
+        Self {
+            signing_key: SigningKey::generate(&mut OsRng),
+        }
+    }
+    /// Loads the hex encoded secret key stored in `path`. If the file doesn't exist, a new
+    /// identity is generated and stored there.
+    pub fn load_or_generate(path: &Path) -> Result<Self> {
+        if path.exists() {
+            let content = std::fs::read_to_string(path)
+                .context(format!("failed to read the identity from {path:?}"))?;
+            let bytes: [u8; 32] = hex::decode(content.trim())?
+                .try_into()
+                .map_err(|_| anyhow!("Invalid identity in {path:?}"))?;
+            return Ok(Self {
+                signing_key: SigningKey::from_bytes(&bytes),
+            });
+        }
+        let identity = Self::generate();
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(path)
+            .context(format!("failed to store the identity in {path:?}"))?;
+        writeln!(file, "{}", hex::encode(identity.signing_key.to_bytes()))?;
+        Ok(identity)
+    }
+    pub fn public(&self) -> PublicIdentity {
+        self.signing_key.verifying_key()
+    }
+    pub(crate) fn sign(&self, message: &[u8]) -> Signature {
+        self.signing_key.sign(message)
+    }
+}
+
+/// Hex encoding of a public identity, as used in the allowed peers file.
+pub fn public_identity_to_hex(identity: &PublicIdentity) -> String {
+    hex::encode(identity.as_bytes())
+}
+pub fn public_identity_from_hex(hex_identity: &str) -> Result<PublicIdentity> {
+    let bytes: [u8; 32] = hex::decode(hex_identity)?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid public identity: {hex_identity}"))?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+/// Checks a peer id, and encodes it like the ones of the connections so they can be compared. A
+/// peer id is the hex encoded public identity of a handshake peer, or the SHA-256 fingerprint of
+/// the certificate of a TLS peer: 32 bytes either way.
+pub fn peer_id_from_hex(peer_id: &str) -> Result<String> {
+    let bytes: [u8; 32] = hex::decode(peer_id)?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid peer id: {peer_id}"))?;
+    Ok(hex::encode(bytes))
+}
+
+/// The identities a server or client accepts to talk to.
+#[derive(Clone, Default, Debug)]
+pub struct AllowedPeers {
+    peers: HashSet<[u8; 32]>,
+}
+impl AllowedPeers {
+    pub fn new(peers: impl IntoIterator<Item = PublicIdentity>) -> Self {
+        Self {
+            peers: peers.into_iter().map(|p| p.to_bytes()).collect(),
+        }
+    }
+    /// One hex encoded public identity per line. Empty lines and lines starting with # are skipped.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .context(format!("failed to read the allowed peers from {path:?}"))?;
+        let peers = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(public_identity_from_hex)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(peers))
+    }
+    pub fn contains(&self, peer: &PublicIdentity) -> bool {
+        self.peers.contains(peer.as_bytes())
+    }
+}
+
+/// What a connection needs to authenticate itself and its peer during the handshake.
+#[derive(Clone)]
+pub struct HandshakeConfig {
+    pub identity: Arc<Identity>,
+    pub allowed_peers: AllowedPeers,
+}
+impl HandshakeConfig {
+    pub fn new(identity: Identity, allowed_peers: AllowedPeers) -> Self {
+        Self {
+            identity: Arc::new(identity),
+            allowed_peers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_files() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("horcrust-identity-{}", rand::random::<u64>()));
+        std::fs::create_dir(&dir)?;
+        let path = dir.join("identity");
+        let identity = Identity::load_or_generate(&path)?;
+        let loaded = Identity::load_or_generate(&path)?;
+        assert_eq!(identity.public(), loaded.public());
+
+        let other = Identity::generate();
+        let peers_path = dir.join("peers");
+        std::fs::write(
+            &peers_path,
+            format!(
+                "# the first server\n{}\n\n",
+                public_identity_to_hex(&identity.public())
+            ),
+        )?;
+        let peers = AllowedPeers::load(&peers_path)?;
+        assert!(peers.contains(&identity.public()));
+        assert!(!peers.contains(&other.public()));
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
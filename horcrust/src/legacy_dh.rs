@@ -0,0 +1,42 @@
+//! A legacy helper, only compiled for tests: the Diffie-Hellman key exchange over a 64 bits prime
+//! used by the first versions of the handshake. It gives no real security, and no connection uses
+//! it anymore.
+use num_bigint::BigUint;
+use num_traits::ToPrimitive;
+use rand::random;
+
+pub const P: u64 = 18446744073709551557;
+pub const G: u64 = 2;
+
+pub fn generate_pk(p: u64, g: u64) -> (u64, u64) {
+    let priv_a: u64 = random::<u64>();
+    let pub_a = modpow(g, priv_a, p);
+    (pub_a, priv_a)
+}
+pub fn generate_session_key(prvkey_a: u64, pk_b: u64, p: u64) -> u64 {
+    // s = (B**a) % p.
+    modpow(pk_b, prvkey_a, p)
+}
+
+pub fn modpow(base: u64, exp: u64, n: u64) -> u64 {
+    let base: BigUint = base.into();
+    let exp = exp.into();
+    let n = n.into();
+    base.modpow(&exp, &n).to_u64().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_key_exchange() {
+        let (pk_a, prv_a) = generate_pk(P, G);
+        let (pk_b, prv_b) = generate_pk(P, G);
+        assert_eq!(
+            generate_session_key(prv_a, pk_b, P),
+            generate_session_key(prv_b, pk_a, P)
+        );
+        assert_eq!(modpow(3, 4, 100), 81);
+    }
+}
@@ -1,26 +1,59 @@
 mod secret_sharing;
-
-use std::time::Duration;
+mod verifiable_secret_sharing;
 
 mod connection;
+mod framing;
+mod identity;
+#[cfg(test)]
+mod legacy_dh;
 mod messages;
 mod messages_utils;
+mod store_key;
+mod tls;
+mod transport;
 
 pub use crate::secret_sharing::AdditiveSecretSharing;
-pub use crate::secret_sharing::SecretSharing;
-pub use connection::{ConnectionHandler, TcpConnectionHandler};
+pub use crate::secret_sharing::{
+    default_modulus, secret_sharing_from_scheme, BytesSecretSharing, SecretSharing,
+    SecretSharingError, ShamirSecretSharing,
+};
+pub use crate::verifiable_secret_sharing::{PedersenSecretSharing, VerifiableSecretSharing};
+pub use connection::{
+    AsyncConnectionHandler, AsyncTcpConnectionHandler, ConnectionHandler, TcpConnectionHandler,
+};
+pub use framing::{ConnectionClosed, MalformedMessage, IDLE_TIMEOUT, MAX_MESSAGE_LEN};
+pub use identity::{
+    peer_id_from_hex, public_identity_from_hex, public_identity_to_hex, AllowedPeers,
+    HandshakeConfig, Identity, PublicIdentity,
+};
 pub use messages::*;
 pub use messages_utils::*;
+pub use num_bigint::BigUint;
+pub use store_key::{key_has_prefix, validate_key, KEY_SEPARATOR, MAX_KEY_LEN};
+pub use tls::{
+    tls_certificate_id, tls_client_config, tls_server_config, AsyncTlsConnectionHandler,
+    TlsConnectionHandler,
+};
+pub use transport::{
+    AsyncIncomingConnection, ConnectionPool, IncomingConnection, OutgoingConnection, TlsConfig,
+    Transport,
+};
 
-/// type alias for the secret. It's just a single number which should be less than Q (defined in secret_sharing).
-pub type HorcrustSecret = u64;
-/// type alias for the Store Key.
-pub type HorcrustStoreKey = u32;
+/// type alias for the secret. It's just a single number which should be less than the modulus of the scheme.
+pub type HorcrustSecret = BigUint;
+/// type alias for the Store Key: a UTF-8 name, see validate_key.
+pub type HorcrustStoreKey = String;
 /// A type alias for the Share type.
-pub type HorcrustShare = u64;
+pub type HorcrustShare = BigUint;
+/// A secret of arbitrary length, split in chunks by BytesSecretSharing.
+pub type HorcrustSecretBytes = Vec<u8>;
+/// A share of a HorcrustSecretBytes: the shares of every chunk, encoded one after the other.
+pub type HorcrustShareBytes = Vec<u8>;
+/// A public commitment to a share, used to verify it. See VerifiableSecretSharing.
+pub type HorcrustCommitment = BigUint;
+/// The commitments of every chunk of a HorcrustSecretBytes, encoded one after the other.
+pub type HorcrustCommitmentsBytes = Vec<u8>;
+/// Maximum length in bytes of a HorcrustSecretBytes.
+pub const MAX_SECRET_LEN: usize = 64 * 1024;
 /// our own result type, TODO: implement using thiserror.
 pub type Result<T> = anyhow::Result<T>;
-
-/// Amount of time after which a key is considered stale and a candidate for refreshing
-/// used by shares_db.
-pub const REFRESH_THRESHOLD: Duration = Duration::from_millis(5000);
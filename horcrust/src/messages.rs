@@ -18,7 +18,10 @@ pub mod horcrust_msg_message {
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct HorcrustMsgRequest {
-    #[prost(oneof = "horcrust_msg_request::Request", tags = "1, 2, 3")]
+    #[prost(
+        oneof = "horcrust_msg_request::Request",
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12"
+    )]
     pub request: ::core::option::Option<horcrust_msg_request::Request>,
 }
 /// Nested message and enum types in `HorcrustMsgRequest`.
@@ -32,12 +35,30 @@ pub mod horcrust_msg_request {
         GetShare(super::GetShareRequest),
         #[prost(message, tag = "3")]
         Refresh(super::RefreshShareRequest),
+        #[prost(message, tag = "4")]
+        Reshare(super::ReshareRequest),
+        #[prost(message, tag = "5")]
+        ReshareSplit(super::ReshareSplitRequest),
+        #[prost(message, tag = "6")]
+        ReshareContribution(super::ReshareContributionRequest),
+        #[prost(message, tag = "7")]
+        ReshareStage(super::ReshareStageRequest),
+        #[prost(message, tag = "8")]
+        RefreshCommit(super::RefreshCommitRequest),
+        #[prost(message, tag = "9")]
+        RefreshLock(super::RefreshLockRequest),
+        #[prost(message, tag = "10")]
+        DeleteShare(super::DeleteShareRequest),
+        #[prost(message, tag = "11")]
+        ListKeys(super::ListKeysRequest),
+        #[prost(message, tag = "12")]
+        RefreshStatus(super::RefreshStatusRequest),
     }
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct HorcrustMsgResponse {
-    #[prost(oneof = "horcrust_msg_response::Response", tags = "1, 2")]
+    #[prost(oneof = "horcrust_msg_response::Response", tags = "1, 2, 3, 4, 5")]
     pub response: ::core::option::Option<horcrust_msg_response::Response>,
 }
 /// Nested message and enum types in `HorcrustMsgResponse`.
@@ -49,6 +70,12 @@ pub mod horcrust_msg_response {
         Error(super::HorcrustMsgError),
         #[prost(message, tag = "2")]
         ShareResponse(super::ShareResponse),
+        #[prost(message, tag = "3")]
+        ListKeys(super::ListKeysResponse),
+        #[prost(message, tag = "4")]
+        RefreshStaged(super::RefreshStagedResponse),
+        #[prost(message, tag = "5")]
+        RefreshStatus(super::RefreshStatusResponse),
     }
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -58,34 +85,386 @@ pub struct HorcrustMsgError {
     pub error: bool,
     #[prost(string, tag = "2")]
     pub error_string: ::prost::alloc::string::String,
+    /// why the request failed, GENERIC unless the client can handle it differently.
+    #[prost(enumeration = "ErrorCode", tag = "3")]
+    pub code: i32,
 }
+/// Who can access a key, by hex encoded public identity. Keys without an owner can be accessed by any peer.
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct PutShareRequest {
+pub struct KeyAcl {
+    /// the client that stored the key: it can read and change it, and it is the only one that can change the KeyAcl or
+    /// reshare the key.
+    #[prost(string, tag = "1")]
+    pub owner: ::prost::alloc::string::String,
+    /// can retrieve the share.
+    #[prost(string, repeated, tag = "2")]
+    pub readers: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// can replace and delete the key.
+    #[prost(string, repeated, tag = "3")]
+    pub writers: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// Parameters of the secret sharing scheme used to create a share.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SharingScheme {
+    /// minimum amount of shares needed to recover the secret. 0 means additive (n-of-n) sharing.
     #[prost(uint32, tag = "1")]
-    pub key: u32,
-    #[prost(uint64, tag = "2")]
-    pub share: u64,
+    pub threshold: u32,
+    /// the modulus of the field, as big endian bytes.
+    #[prost(bytes = "vec", tag = "2")]
+    pub modulus: ::prost::alloc::vec::Vec<u8>,
+    /// Pedersen verifiable sharing: the modulus is the order of a fixed group, commitments are published.
+    #[prost(bool, tag = "3")]
+    pub verifiable: bool,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PutShareRequest {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub share: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "3")]
+    pub scheme: ::core::option::Option<SharingScheme>,
+    /// public commitments to the shares, empty if the scheme is not verifiable.
+    #[prost(bytes = "vec", tag = "4")]
+    pub commitments: ::prost::alloc::vec::Vec<u8>,
+    /// position of the share in the split, its x-coordinate is index + 1.
+    #[prost(uint32, tag = "5")]
+    pub index: u32,
+    /// refresh the share once it is this old, instead of using the server's refresh interval. 0 to use the server's one.
+    #[prost(uint64, tag = "6")]
+    pub refresh_interval_secs: u64,
+    /// the readers and writers of the key, the owner is the client storing it. Ignored unless sent by the owner, or
+    /// the key is new.
+    #[prost(message, optional, tag = "7")]
+    pub acl: ::core::option::Option<KeyAcl>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct GetShareRequest {
-    #[prost(uint32, tag = "1")]
-    pub key: u32,
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+}
+/// Removes the share of the key, and stops refreshing it.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeleteShareRequest {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+}
+/// Lists the keys stored by the server, in order, a page at a time. The shares are never returned.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListKeysRequest {
+    /// the first key of the page.
+    #[prost(string, tag = "1")]
+    pub start: ::prost::alloc::string::String,
+    /// the maximum number of keys in the page, 0 for the server's default.
+    #[prost(uint32, tag = "2")]
+    pub limit: u32,
+    /// only list the keys below this one, e.g. `prod/db` lists `prod/db/password`. Empty to list all of them.
+    #[prost(string, tag = "3")]
+    pub prefix: ::prost::alloc::string::String,
 }
+/// Refreshers are different for every server: each one gets the values generated for its own share index.
+/// Refreshes are two-phase: servers only stage the refreshed shares, and switch to them on a RefreshCommitRequest.
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct RefreshShareRequest {
-    #[prost(uint32, repeated, tag = "1")]
-    pub key: ::prost::alloc::vec::Vec<u32>,
+    #[prost(string, repeated, tag = "1")]
+    pub key: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// one refresher for each key, in the same order.
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub random: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+    /// updates to the commitments of each key, empty for keys whose scheme is not verifiable.
+    #[prost(bytes = "vec", repeated, tag = "3")]
+    pub commitments: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+    /// the share index the refreshers were generated for.
+    #[prost(uint32, tag = "4")]
+    pub index: u32,
+    /// identifies the refresh round, chosen at random by the server coordinating it.
+    #[prost(uint64, tag = "5")]
+    pub round_id: u64,
+    /// the coordinator, it must hold the refresh lease of the server.
+    #[prost(uint64, tag = "6")]
+    pub holder: u64,
+    /// seconds since the unix epoch. The coordinator doesn't commit the round after this, and servers don't accept the
+    /// commit: they resolve the round among themselves, see RefreshStatusRequest.
+    #[prost(uint64, tag = "7")]
+    pub expires_at: u64,
+    /// every server taking part in the round.
+    #[prost(string, repeated, tag = "8")]
+    pub round_servers: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// Keys are staged one by one: the ones that can't be refreshed are reported, and the coordinator leaves them out of
+/// the commit on every server.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RefreshStagedResponse {
+    #[prost(string, repeated, tag = "1")]
+    pub failed_key: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// why each key failed, in the same order.
+    #[prost(string, repeated, tag = "2")]
+    pub error: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// Sent once every server staged the refresh, or the reshare, to switch to the new shares, or to drop them if any server
+/// failed.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RefreshCommitRequest {
+    /// the round to commit or roll back, see RefreshShareRequest.round_id.
+    #[prost(uint64, tag = "1")]
+    pub round_id: u64,
+    #[prost(bool, tag = "2")]
+    pub commit: bool,
+    /// keys some server failed to stage: no server switches to their new shares.
+    #[prost(string, repeated, tag = "3")]
+    pub skipped_key: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// Rounds the coordinator didn't finish before they expired are resolved by the servers: each one asks the others of
+/// the round whether they committed it. It is committed if any of them did, and rolled back once all of them answered
+/// that they didn't.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RefreshStatusRequest {
+    #[prost(uint64, tag = "1")]
+    pub round_id: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RefreshStatusResponse {
+    #[prost(bool, tag = "1")]
+    pub committed: bool,
+    /// the skipped keys of the commit, see RefreshCommitRequest.
+    #[prost(string, repeated, tag = "2")]
+    pub skipped_key: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// Coordinating refreshes, and reshares, needs the refresh lease of every server: servers only stage refreshes and
+/// reshares from the holder of their lease, so a single round is in flight at a time. The coordinator takes it for a
+/// round and releases it once the round is over, or if it can't get it from all the servers.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RefreshLockRequest {
+    /// random id of the coordinator.
+    #[prost(uint64, tag = "1")]
+    pub holder: u64,
+    /// how long the lease lasts, if the coordinator stops before releasing it.
     #[prost(uint64, tag = "2")]
-    pub random: u64,
+    pub lease_ms: u64,
+    #[prost(bool, tag = "3")]
+    pub release: bool,
+}
+/// Moves a key to a new set of servers, and threshold, without rebuilding the secret. Sent by the client to one
+/// of the servers holding the key, which coordinates the reshare: it holds the refresh lease of the old and new servers
+/// until the new shares are committed, like a refresh.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ReshareRequest {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    /// the new servers, the share index of each one is its position.
+    #[prost(string, repeated, tag = "2")]
+    pub servers: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// the new threshold, 0 means additive (n-of-n) sharing.
+    #[prost(uint32, tag = "3")]
+    pub threshold: u32,
+}
+/// Sent by the coordinator to the old servers taking part in the reshare: each of them splits its own share
+/// and sends the sub-shares to the new servers.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ReshareSplitRequest {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub servers: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, optional, tag = "3")]
+    pub scheme: ::core::option::Option<SharingScheme>,
+    /// the share indexes of the old servers taking part in the reshare.
+    #[prost(uint32, repeated, tag = "4")]
+    pub indices: ::prost::alloc::vec::Vec<u32>,
+    /// the share index of the server receiving this request.
+    #[prost(uint32, tag = "5")]
+    pub index: u32,
+    /// the epoch of the share seen by the coordinator: the reshare fails if the share was refreshed in the meantime.
+    #[prost(uint64, tag = "6")]
+    pub epoch: u64,
+    /// the coordinator, it must hold the refresh lease of the server.
+    #[prost(uint64, tag = "7")]
+    pub holder: u64,
+    /// identifies the reshare round, like the round_id of a RefreshShareRequest.
+    #[prost(uint64, tag = "8")]
+    pub round_id: u64,
+}
+/// A sub-share sent by an old server to a new one.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ReshareContributionRequest {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    /// the share index of the new server.
+    #[prost(uint32, tag = "2")]
+    pub index: u32,
+    /// the share index of the old server.
+    #[prost(uint32, tag = "3")]
+    pub from_index: u32,
+    /// how many sub-shares the new server should receive.
+    #[prost(uint32, tag = "4")]
+    pub contributions: u32,
+    #[prost(bytes = "vec", tag = "5")]
+    pub share: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "6")]
+    pub commitments: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "7")]
+    pub scheme: ::core::option::Option<SharingScheme>,
+    /// like in PutShareRequest.
+    #[prost(uint64, tag = "8")]
+    pub refresh_interval_secs: u64,
+    #[prost(message, optional, tag = "9")]
+    pub acl: ::core::option::Option<KeyAcl>,
+    /// the epoch of the reshared share, the new shares get the next one.
+    #[prost(uint64, tag = "10")]
+    pub epoch: u64,
+    #[prost(uint64, tag = "11")]
+    pub round_id: u64,
+    /// the coordinator, it must hold the refresh lease of the new server.
+    #[prost(uint64, tag = "12")]
+    pub holder: u64,
+}
+/// Sent by the coordinator when all the sub-shares were delivered: new servers stage the sum of the sub-shares, old
+/// servers not in the new set stage the removal of theirs. They switch on the RefreshCommitRequest of the round.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ReshareStageRequest {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(bool, tag = "2")]
+    pub remove: bool,
+    #[prost(uint64, tag = "3")]
+    pub round_id: u64,
+    /// the coordinator, it must hold the refresh lease of the server.
+    #[prost(uint64, tag = "4")]
+    pub holder: u64,
+    /// the new servers, they hold the new shares.
+    #[prost(string, repeated, tag = "5")]
+    pub servers: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// see RefreshShareRequest.
+    #[prost(uint64, tag = "6")]
+    pub expires_at: u64,
+    #[prost(string, repeated, tag = "7")]
+    pub round_servers: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// What a server knows about a key, without the share.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KeyInfo {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub scheme: ::core::option::Option<SharingScheme>,
+    #[prost(uint32, tag = "3")]
+    pub index: u32,
+    #[prost(uint64, tag = "4")]
+    pub epoch: u64,
+    /// seconds since the unix epoch.
+    #[prost(uint64, tag = "5")]
+    pub stored_at: u64,
+    /// seconds since the unix epoch, 0 if the share was never refreshed.
+    #[prost(uint64, tag = "6")]
+    pub refreshed_at: u64,
+    #[prost(uint64, tag = "7")]
+    pub refresh_interval_secs: u64,
+    #[prost(message, optional, tag = "8")]
+    pub acl: ::core::option::Option<KeyAcl>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListKeysResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub keys: ::prost::alloc::vec::Vec<KeyInfo>,
+    /// if there are more keys, the start of the next page.
+    #[prost(bool, tag = "2")]
+    pub more: bool,
+    #[prost(string, tag = "3")]
+    pub next: ::prost::alloc::string::String,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ShareResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    pub share: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "2")]
+    pub scheme: ::core::option::Option<SharingScheme>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub commitments: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint32, tag = "4")]
+    pub index: u32,
+    /// incremented on every committed refresh: only shares of the same epoch can be combined.
+    #[prost(uint64, tag = "5")]
+    pub epoch: u64,
+}
+/// A share as servers store it on disk.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StoredShareRecord {
+    #[prost(bytes = "vec", tag = "1")]
+    pub share: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, optional, tag = "2")]
+    pub scheme: ::core::option::Option<SharingScheme>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub commitments: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint32, tag = "4")]
+    pub index: u32,
+    #[prost(uint64, tag = "5")]
+    pub epoch: u64,
+    #[prost(uint64, tag = "6")]
+    pub refresh_interval_secs: u64,
+    #[prost(uint64, tag = "7")]
+    pub stored_at: u64,
+    #[prost(uint64, tag = "8")]
+    pub refreshed_at: u64,
+    #[prost(message, optional, tag = "9")]
+    pub acl: ::core::option::Option<KeyAcl>,
+    /// the servers holding the shares of the key, the share index of each one is its position.
+    #[prost(string, repeated, tag = "10")]
+    pub servers: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// A key of a staged round.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StagedShareRecord {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    /// the new share, missing if the commit removes the key, e.g. on a reshare to other servers.
+    #[prost(message, optional, tag = "2")]
+    pub share: ::core::option::Option<StoredShareRecord>,
+    /// digest of the share it replaces, empty if the key was not stored: the key is not committed if it changed since.
+    #[prost(bytes = "vec", tag = "3")]
+    pub base: ::prost::alloc::vec::Vec<u8>,
+}
+/// A refresh, or reshare, round staged on disk, waiting for the commit.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StagedRefreshRecord {
+    #[prost(message, repeated, tag = "1")]
+    pub shares: ::prost::alloc::vec::Vec<StagedShareRecord>,
+    /// see RefreshShareRequest.
+    #[prost(uint64, tag = "2")]
+    pub expires_at: u64,
+    #[prost(string, repeated, tag = "3")]
+    pub servers: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// A round this server committed, kept for a while to answer RefreshStatusRequest.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CommittedRoundRecord {
+    /// seconds since the unix epoch.
     #[prost(uint64, tag = "1")]
-    pub share: u64,
+    pub committed_at: u64,
+    #[prost(string, repeated, tag = "2")]
+    pub skipped_key: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -94,4 +473,35 @@ pub struct RawMessage {
     pub nonce: ::prost::alloc::vec::Vec<u8>,
     #[prost(bytes = "vec", tag = "2")]
     pub encrypted_payload: ::prost::alloc::vec::Vec<u8>,
+    /// position of the message in its direction of the session, starting from 0. It is authenticated
+    /// together with the direction, so messages can't be replayed, reordered or reflected.
+    #[prost(uint64, tag = "3")]
+    pub sequence: u64,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum ErrorCode {
+    Generic = 0,
+    /// the client is not allowed to do this with the key, see KeyAcl.
+    Unauthorized = 1,
+}
+impl ErrorCode {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            ErrorCode::Generic => "ERROR_CODE_GENERIC",
+            ErrorCode::Unauthorized => "ERROR_CODE_UNAUTHORIZED",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "ERROR_CODE_GENERIC" => Some(Self::Generic),
+            "ERROR_CODE_UNAUTHORIZED" => Some(Self::Unauthorized),
+            _ => None,
+        }
+    }
 }
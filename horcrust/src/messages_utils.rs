@@ -1,7 +1,10 @@
 use crate::{
-    horcrust_msg_request, horcrust_msg_response, GetShareRequest, HorcrustMsgError,
-    HorcrustMsgRequest, HorcrustMsgResponse, HorcrustShare, HorcrustStoreKey, PutShareRequest,
-    RefreshShareRequest, ShareResponse,
+    horcrust_msg_request, horcrust_msg_response, DeleteShareRequest, ErrorCode, GetShareRequest,
+    HorcrustCommitmentsBytes, HorcrustMsgError, HorcrustMsgRequest, HorcrustMsgResponse,
+    HorcrustShareBytes, HorcrustStoreKey, KeyAcl, KeyInfo, ListKeysRequest, ListKeysResponse,
+    PutShareRequest, RefreshCommitRequest, RefreshLockRequest, RefreshShareRequest,
+    RefreshStagedResponse, RefreshStatusRequest, RefreshStatusResponse, ReshareContributionRequest,
+    ReshareRequest, ReshareSplitRequest, ReshareStageRequest, ShareResponse, SharingScheme,
 };
 
 pub const fn msg_success_response() -> HorcrustMsgResponse {
@@ -9,25 +12,46 @@ pub const fn msg_success_response() -> HorcrustMsgResponse {
         response: Some(horcrust_msg_response::Response::Error(HorcrustMsgError {
             error: false,
             error_string: String::new(),
+            code: ErrorCode::Generic as i32,
         })),
     }
 }
-pub const fn msg_share_response(share: HorcrustShare) -> HorcrustMsgResponse {
+pub const fn msg_share_response(
+    share: HorcrustShareBytes,
+    scheme: SharingScheme,
+    commitments: HorcrustCommitmentsBytes,
+    index: u32,
+    epoch: u64,
+) -> HorcrustMsgResponse {
     HorcrustMsgResponse {
         response: Some(horcrust_msg_response::Response::ShareResponse(
-            ShareResponse { share },
+            ShareResponse {
+                share,
+                scheme: Some(scheme),
+                commitments,
+                index,
+                epoch,
+            },
         )),
     }
 }
 
 pub const fn msg_store_share_request(
     key: HorcrustStoreKey,
-    share: HorcrustShare,
+    index: u32,
+    share: HorcrustShareBytes,
+    scheme: SharingScheme,
+    commitments: HorcrustCommitmentsBytes,
 ) -> HorcrustMsgRequest {
     HorcrustMsgRequest {
         request: Some(horcrust_msg_request::Request::PutShare(PutShareRequest {
             key,
             share,
+            scheme: Some(scheme),
+            commitments,
+            index,
+            refresh_interval_secs: 0,
+            acl: None,
         })),
     }
 }
@@ -39,34 +63,189 @@ pub const fn msg_retrieve_secret_request(key: HorcrustStoreKey) -> HorcrustMsgRe
     }
 }
 
+pub const fn msg_list_keys_request(
+    prefix: String,
+    start: HorcrustStoreKey,
+    limit: u32,
+) -> HorcrustMsgRequest {
+    HorcrustMsgRequest {
+        request: Some(horcrust_msg_request::Request::ListKeys(ListKeysRequest {
+            start,
+            limit,
+            prefix,
+        })),
+    }
+}
+pub fn msg_list_keys_response(
+    keys: Vec<KeyInfo>,
+    next: Option<HorcrustStoreKey>,
+) -> HorcrustMsgResponse {
+    let (more, next) = match next {
+        Some(next) => (true, next),
+        None => (false, String::new()),
+    };
+    HorcrustMsgResponse {
+        response: Some(horcrust_msg_response::Response::ListKeys(
+            ListKeysResponse { keys, more, next },
+        )),
+    }
+}
+
+pub const fn msg_delete_share_request(key: HorcrustStoreKey) -> HorcrustMsgRequest {
+    HorcrustMsgRequest {
+        request: Some(horcrust_msg_request::Request::DeleteShare(
+            DeleteShareRequest { key },
+        )),
+    }
+}
+
 pub const fn msg_put_share_request(
     key: HorcrustStoreKey,
-    share: HorcrustShare,
+    index: u32,
+    share: HorcrustShareBytes,
+    scheme: SharingScheme,
+    commitments: HorcrustCommitmentsBytes,
+    refresh_interval_secs: u64,
+    acl: KeyAcl,
 ) -> HorcrustMsgRequest {
     HorcrustMsgRequest {
         request: Some(horcrust_msg_request::Request::PutShare(PutShareRequest {
             key,
             share,
+            scheme: Some(scheme),
+            commitments,
+            index,
+            refresh_interval_secs,
+            acl: Some(acl),
         })),
     }
 }
 
-pub const fn msg_refresh_share_request(
-    key: Vec<HorcrustStoreKey>,
-    random: HorcrustShare,
+pub const fn msg_refresh_share_request(refresh: RefreshShareRequest) -> HorcrustMsgRequest {
+    HorcrustMsgRequest {
+        request: Some(horcrust_msg_request::Request::Refresh(refresh)),
+    }
+}
+pub const fn msg_refresh_staged_response(
+    failed_key: Vec<HorcrustStoreKey>,
+    error: Vec<String>,
+) -> HorcrustMsgResponse {
+    HorcrustMsgResponse {
+        response: Some(horcrust_msg_response::Response::RefreshStaged(
+            RefreshStagedResponse { failed_key, error },
+        )),
+    }
+}
+pub const fn msg_refresh_commit_request(
+    round_id: u64,
+    commit: bool,
+    skipped_key: Vec<HorcrustStoreKey>,
 ) -> HorcrustMsgRequest {
     HorcrustMsgRequest {
-        request: Some(horcrust_msg_request::Request::Refresh(
-            RefreshShareRequest { key, random },
+        request: Some(horcrust_msg_request::Request::RefreshCommit(
+            RefreshCommitRequest {
+                round_id,
+                commit,
+                skipped_key,
+            },
+        )),
+    }
+}
+pub const fn msg_refresh_status_request(round_id: u64) -> HorcrustMsgRequest {
+    HorcrustMsgRequest {
+        request: Some(horcrust_msg_request::Request::RefreshStatus(
+            RefreshStatusRequest { round_id },
+        )),
+    }
+}
+pub const fn msg_refresh_status_response(
+    committed: bool,
+    skipped_key: Vec<HorcrustStoreKey>,
+) -> HorcrustMsgResponse {
+    HorcrustMsgResponse {
+        response: Some(horcrust_msg_response::Response::RefreshStatus(
+            RefreshStatusResponse {
+                committed,
+                skipped_key,
+            },
+        )),
+    }
+}
+pub const fn msg_refresh_lock_request(holder: u64, lease_ms: u64) -> HorcrustMsgRequest {
+    HorcrustMsgRequest {
+        request: Some(horcrust_msg_request::Request::RefreshLock(
+            RefreshLockRequest {
+                holder,
+                lease_ms,
+                release: false,
+            },
+        )),
+    }
+}
+pub const fn msg_refresh_unlock_request(holder: u64) -> HorcrustMsgRequest {
+    HorcrustMsgRequest {
+        request: Some(horcrust_msg_request::Request::RefreshLock(
+            RefreshLockRequest {
+                holder,
+                lease_ms: 0,
+                release: true,
+            },
         )),
     }
 }
 
+pub const fn msg_reshare_request(
+    key: HorcrustStoreKey,
+    servers: Vec<String>,
+    threshold: u32,
+) -> HorcrustMsgRequest {
+    HorcrustMsgRequest {
+        request: Some(horcrust_msg_request::Request::Reshare(ReshareRequest {
+            key,
+            servers,
+            threshold,
+        })),
+    }
+}
+
+pub const fn msg_reshare_split_request(split: ReshareSplitRequest) -> HorcrustMsgRequest {
+    HorcrustMsgRequest {
+        request: Some(horcrust_msg_request::Request::ReshareSplit(split)),
+    }
+}
+
+pub const fn msg_reshare_contribution_request(
+    contribution: ReshareContributionRequest,
+) -> HorcrustMsgRequest {
+    HorcrustMsgRequest {
+        request: Some(horcrust_msg_request::Request::ReshareContribution(
+            contribution,
+        )),
+    }
+}
+
+pub const fn msg_reshare_stage_request(stage: ReshareStageRequest) -> HorcrustMsgRequest {
+    HorcrustMsgRequest {
+        request: Some(horcrust_msg_request::Request::ReshareStage(stage)),
+    }
+}
+
 pub fn msg_error_response(msg: &str) -> HorcrustMsgResponse {
     HorcrustMsgResponse {
         response: Some(horcrust_msg_response::Response::Error(HorcrustMsgError {
             error: true,
             error_string: msg.to_string(),
+            code: ErrorCode::Generic as i32,
+        })),
+    }
+}
+/// An error response telling the client it is not allowed to do what it asked.
+pub fn msg_unauthorized_response(msg: &str) -> HorcrustMsgResponse {
+    HorcrustMsgResponse {
+        response: Some(horcrust_msg_response::Response::Error(HorcrustMsgError {
+            error: true,
+            error_string: msg.to_string(),
+            code: ErrorCode::Unauthorized as i32,
         })),
     }
 }
@@ -1,96 +1,785 @@
-use crate::{HorcrustSecret, HorcrustShare};
-use rand::Rng;
+use crate::verifiable_secret_sharing::{PedersenSecretSharing, VerifiableSecretSharing};
+use crate::{
+    HorcrustCommitment, HorcrustCommitmentsBytes, HorcrustSecret, HorcrustSecretBytes,
+    HorcrustShare, HorcrustShareBytes, Result, SharingScheme, MAX_SECRET_LEN,
+};
+use anyhow::{bail, ensure};
+use num_bigint::{BigUint, RandBigInt};
+use num_traits::{One, Zero};
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum SecretSharingError {
+    #[error("The secret is out of range, it should be less than {limit}.")]
+    SecretOutOfRange { limit: BigUint },
+    #[error("The secret is {len} bytes long, the maximum is {max} bytes.")]
+    SecretTooLong { len: usize, max: usize },
+    #[error("The modulus {modulus} is not prime, threshold schemes need a prime modulus.")]
+    ModulusNotPrime { modulus: BigUint },
+    #[error("Can't generate {shares} shares, at least {min} are needed.")]
+    NotEnoughShares { shares: usize, min: usize },
+    #[error("Share index {index} is given more than once.")]
+    DuplicateShareIndex { index: usize },
+}
 
 pub trait SecretSharing {
-    fn split(&self, shares: usize, secret: HorcrustSecret) -> Vec<HorcrustShare>;
-    fn combine(&self, shares: Vec<HorcrustShare>) -> HorcrustSecret;
-    // Used by the server side to refresh the secret.
+    /// Fails if the secret is not less than `limit()`.
+    fn split(
+        &self,
+        shares: usize,
+        secret: HorcrustSecret,
+    ) -> std::result::Result<Vec<HorcrustShare>, SecretSharingError>;
+    /// `shares` is a list of (index, share), where index is the position of the share in the
+    /// vector returned by `split`. Fails if an index is given more than once.
+    fn combine(
+        &self,
+        shares: Vec<(usize, HorcrustShare)>,
+    ) -> std::result::Result<HorcrustSecret, SecretSharingError>;
+    /// Used by the server side to refresh a share with the refresher generated for its index.
     fn refresh_share(&self, r: HorcrustShare, share: HorcrustShare) -> HorcrustShare;
-    fn generate_refreshers(&self, shares: usize) -> Vec<HorcrustShare>;
-    fn limit(&self) -> Option<u64>;
+    /// Returns a refresher for every share: the refresher at position i is meant for the share at
+    /// index i. Refreshed shares combine to the same secret.
+    /// Fails if there are too few shares to refresh them.
+    fn generate_refreshers(
+        &self,
+        shares: usize,
+    ) -> std::result::Result<Vec<HorcrustShare>, SecretSharingError>;
+    /// The term of the share at `index` when combining the shares at `indices`: the secret is
+    /// the sum of the terms. Used to reshare without combining.
+    fn lagrange_term(
+        &self,
+        index: usize,
+        indices: &[usize],
+        share: &HorcrustShare,
+    ) -> std::result::Result<HorcrustSecret, SecretSharingError>;
+    fn limit(&self) -> Option<BigUint>;
+    /// Shares are numbers less than this limit.
+    fn share_limit(&self) -> Option<BigUint> {
+        self.limit()
+    }
+    /// Minimum amount of shares (out of `shares`) needed by `combine` to recover the secret.
+    fn threshold(&self, shares: usize) -> usize;
+    /// The scheme parameters, sent to the servers together with the shares.
+    fn scheme(&self) -> SharingScheme;
+    /// Returns Some if the shares can be verified against public commitments.
+    fn as_verifiable(&self) -> Option<&dyn VerifiableSecretSharing> {
+        None
+    }
+}
+
+/// Builds the secret sharing implementation described by `scheme`.
+pub fn secret_sharing_from_scheme(scheme: &SharingScheme) -> Result<Box<dyn SecretSharing>> {
+    let modulus = BigUint::from_bytes_be(&scheme.modulus);
+    ensure!(modulus > BigUint::one(), "Invalid modulus: {}", modulus);
+    if scheme.verifiable {
+        ensure!(
+            scheme.threshold > 0,
+            "Verifiable sharing needs a threshold."
+        );
+        ensure!(
+            modulus == PedersenSecretSharing::group_order(),
+            "Verifiable sharing uses a fixed modulus."
+        );
+        return Ok(Box::new(PedersenSecretSharing::new(
+            scheme.threshold as usize,
+        )));
+    }
+    Ok(match scheme.threshold {
+        0 => Box::new(AdditiveSecretSharing::new(modulus)),
+        threshold => Box::new(ShamirSecretSharing::try_new(threshold as usize, modulus)?),
+    })
+}
+
+/// The default modulus: the 255 bits prime 2^255 - 19.
+pub fn default_modulus() -> BigUint {
+    (BigUint::one() << 255u32) - 19u32
+}
+
+/// Rounds of the Miller-Rabin test: a composite passes all of them with probability at most
+/// 4^-rounds.
+const MILLER_RABIN_ROUNDS: usize = 40;
+
+/// Miller-Rabin primality test with random bases, after trial division by the small primes.
+pub fn is_probable_prime(n: &BigUint) -> bool {
+    let two = BigUint::from(2u32);
+    if n < &two {
+        return false;
+    }
+    for p in [2u32, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37] {
+        if n == &BigUint::from(p) {
+            return true;
+        }
+        if (n % p).is_zero() {
+            return false;
+        }
+    }
+    // n - 1 = d * 2^s, with d odd.
+    let n_minus_one = n - 1u32;
+    // safe unwrap: n - 1 is not zero.
+    let s = n_minus_one.trailing_zeros().unwrap();
+    let d = &n_minus_one >> s;
+    let mut rng = rand::thread_rng();
+    'rounds: for _ in 0..MILLER_RABIN_ROUNDS {
+        let a = rng.gen_biguint_range(&two, &n_minus_one);
+        let mut x = a.modpow(&d, n);
+        if x.is_one() || x == n_minus_one {
+            continue;
+        }
+        for _ in 1..s {
+            x = &x * &x % n;
+            if x == n_minus_one {
+                continue 'rounds;
+            }
+        }
+        // a is a witness that n is composite.
+        return false;
+    }
+    true
 }
-/// This simple implementation assumes that the original secret is always less than q.
-const Q: u64 = 431;
 
+/// Additive n-of-n secret sharing modulo q: the shares are random numbers that sum up to the secret.
+/// This simple implementation assumes that the original secret is always less than q.
 pub struct AdditiveSecretSharing {
-    q: u64,
+    q: BigUint,
+}
+impl AdditiveSecretSharing {
+    pub fn new(q: BigUint) -> Self {
+        assert!(q > BigUint::one());
+        Self { q }
+    }
+    /// Random numbers modulo q, summing up to `sum`. Fails if there are no shares.
+    fn random_summing_to(
+        &self,
+        shares: usize,
+        sum: &BigUint,
+    ) -> std::result::Result<Vec<HorcrustShare>, SecretSharingError> {
+        if shares == 0 {
+            return Err(SecretSharingError::NotEnoughShares { shares, min: 1 });
+        }
+        let mut rng = rand::thread_rng();
+        let mut ret = vec![];
+        let mut total = BigUint::zero();
+        for _ in 0..shares - 1 {
+            let share = rng.gen_biguint_below(&self.q);
+            total = (total + &share) % &self.q;
+            ret.push(share);
+        }
+        // the last one makes the sum equal to `sum`.
+        ret.push((sum % &self.q + &self.q - total) % &self.q);
+        Ok(ret)
+    }
 }
 impl Default for AdditiveSecretSharing {
     fn default() -> Self {
-        Self { q: Q }
+        Self::new(default_modulus())
     }
 }
 impl SecretSharing for AdditiveSecretSharing {
-    fn split(&self, shares: usize, secret: HorcrustSecret) -> Vec<HorcrustShare> {
-        let mut rng = rand::thread_rng();
-        let mut ret = vec![];
+    fn split(
+        &self,
+        shares: usize,
+        secret: HorcrustSecret,
+    ) -> std::result::Result<Vec<HorcrustShare>, SecretSharingError> {
+        if secret >= self.q {
+            return Err(SecretSharingError::SecretOutOfRange {
+                limit: self.q.clone(),
+            });
+        }
+        self.random_summing_to(shares, &secret)
+    }
 
-        // Generate random shares
-        for _ in 0..shares - 1 {
-            let share = rng.gen_range(0..self.q);
-            ret.push(share as HorcrustShare);
+    fn combine(
+        &self,
+        shares: Vec<(usize, HorcrustShare)>,
+    ) -> std::result::Result<HorcrustSecret, SecretSharingError> {
+        // returns sum(shares) % q
+        Ok(shares
+            .into_iter()
+            .fold(BigUint::zero(), |acc, (_, share)| (acc + share) % &self.q))
+    }
+
+    fn refresh_share(&self, r: HorcrustShare, share: HorcrustShare) -> HorcrustShare {
+        (r + share) % &self.q
+    }
+    /// A single share can't change without changing the secret.
+    fn generate_refreshers(
+        &self,
+        shares: usize,
+    ) -> std::result::Result<Vec<HorcrustShare>, SecretSharingError> {
+        if shares < 2 {
+            return Err(SecretSharingError::NotEnoughShares { shares, min: 2 });
         }
+        self.random_summing_to(shares, &BigUint::zero())
+    }
 
-        // Calculate the last share to make the sum equal to the secret
-        let last = secret as i64 - ret.iter().sum::<u64>() as i64;
-        ret.push(last.rem_euclid(self.q as i64) as HorcrustShare);
-        ret
+    fn lagrange_term(
+        &self,
+        _index: usize,
+        _indices: &[usize],
+        share: &HorcrustShare,
+    ) -> std::result::Result<HorcrustSecret, SecretSharingError> {
+        Ok(share.clone())
     }
 
-    fn combine(&self, shares: Vec<HorcrustShare>) -> HorcrustSecret {
-        // returns sum(shares) % Q
+    fn limit(&self) -> Option<BigUint> {
+        Some(self.q.clone())
+    }
+
+    fn threshold(&self, shares: usize) -> usize {
         shares
+    }
+
+    fn scheme(&self) -> SharingScheme {
+        SharingScheme {
+            threshold: 0,
+            modulus: self.q.to_bytes_be(),
+            verifiable: false,
+        }
+    }
+}
+
+/// Shamir's k-of-n threshold secret sharing over GF(p), p must be prime: see `try_new`.
+/// The share at index i is the evaluation of a random polynomial of degree threshold - 1 in x = i + 1,
+/// and the secret is the polynomial's value in 0.
+pub struct ShamirSecretSharing {
+    threshold: usize,
+    p: BigUint,
+}
+impl ShamirSecretSharing {
+    pub fn new(threshold: usize, p: BigUint) -> Self {
+        assert!(threshold > 0);
+        assert!(p > BigUint::one());
+        Self { threshold, p }
+    }
+    /// Like `new`, fails if p is not prime: the shares would combine to a wrong secret.
+    pub fn try_new(threshold: usize, p: BigUint) -> std::result::Result<Self, SecretSharingError> {
+        // the default modulus is known to be prime, and it's checked on every refresh.
+        if p != default_modulus() && !is_probable_prime(&p) {
+            return Err(SecretSharingError::ModulusNotPrime { modulus: p });
+        }
+        Ok(Self::new(threshold, p))
+    }
+    /// Multiplicative inverse through Fermat's little theorem, as p is prime.
+    fn inverse(&self, a: &BigUint) -> BigUint {
+        a.modpow(&(&self.p - 2u32), &self.p)
+    }
+    /// The coefficients of a random polynomial of degree threshold - 1 with constant term `secret`.
+    pub(crate) fn random_polynomial(&self, secret: BigUint) -> Vec<BigUint> {
+        let mut rng = rand::thread_rng();
+        let mut coefficients = vec![secret % &self.p];
+        for _ in 1..self.threshold {
+            coefficients.push(rng.gen_biguint_below(&self.p));
+        }
+        coefficients
+    }
+    /// Evaluates the polynomial in x = 1..=shares. Fails if there are less shares than the
+    /// threshold, as the secret couldn't be recovered.
+    pub(crate) fn evaluate(
+        &self,
+        coefficients: &[BigUint],
+        shares: usize,
+    ) -> std::result::Result<Vec<HorcrustShare>, SecretSharingError> {
+        if shares < self.threshold {
+            return Err(SecretSharingError::NotEnoughShares {
+                shares,
+                min: self.threshold,
+            });
+        }
+        Ok((1..=shares as u64)
+            .map(|x| {
+                // Horner's method
+                coefficients
+                    .iter()
+                    .rev()
+                    .fold(BigUint::zero(), |acc, c| (acc * x + c) % &self.p)
+            })
+            .collect())
+    }
+    /// The coefficient of the share at `index` when combining the shares at `indices`:
+    /// prod (x_m / (x_m - x_i)) over the other indices m. Fails if an index is repeated: x_m - x_i
+    /// would be zero.
+    fn lagrange_coefficient(
+        &self,
+        index: usize,
+        indices: &[usize],
+    ) -> std::result::Result<HorcrustShare, SecretSharingError> {
+        let mut sorted = indices.to_vec();
+        sorted.sort_unstable();
+        if let Some(pair) = sorted.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(SecretSharingError::DuplicateShareIndex { index: pair[0] });
+        }
+        let xi = BigUint::from(index + 1);
+        let (num, den) = indices
             .iter()
-            .fold(0, |acc, &share| (acc + share).rem_euclid(self.q))
+            .filter(|&&m| m != index)
+            .map(|&m| BigUint::from(m + 1))
+            .fold((BigUint::one(), BigUint::one()), |(num, den), xm| {
+                let diff = (&xm + &self.p - &xi % &self.p) % &self.p;
+                (num * xm % &self.p, den * diff % &self.p)
+            });
+        Ok(num * self.inverse(&den) % &self.p)
+    }
+    fn evaluate_random_polynomial(
+        &self,
+        shares: usize,
+        secret: BigUint,
+    ) -> std::result::Result<Vec<HorcrustShare>, SecretSharingError> {
+        self.evaluate(&self.random_polynomial(secret), shares)
+    }
+}
+impl SecretSharing for ShamirSecretSharing {
+    fn split(
+        &self,
+        shares: usize,
+        secret: HorcrustSecret,
+    ) -> std::result::Result<Vec<HorcrustShare>, SecretSharingError> {
+        if secret >= self.p {
+            return Err(SecretSharingError::SecretOutOfRange {
+                limit: self.p.clone(),
+            });
+        }
+        self.evaluate_random_polynomial(shares, secret)
+    }
+
+    fn combine(
+        &self,
+        shares: Vec<(usize, HorcrustShare)>,
+    ) -> std::result::Result<HorcrustSecret, SecretSharingError> {
+        // Lagrange interpolation in x = 0.
+        let indices: Vec<usize> = shares.iter().map(|(i, _)| *i).collect();
+        shares.into_iter().try_fold(BigUint::zero(), |acc, (i, y)| {
+            Ok((acc + y * self.lagrange_coefficient(i, &indices)?) % &self.p)
+        })
     }
 
+    fn lagrange_term(
+        &self,
+        index: usize,
+        indices: &[usize],
+        share: &HorcrustShare,
+    ) -> std::result::Result<HorcrustSecret, SecretSharingError> {
+        Ok(share * self.lagrange_coefficient(index, indices)? % &self.p)
+    }
+
+    /// The refresher is the evaluation of the random polynomial at the x-coordinate of the share.
     fn refresh_share(&self, r: HorcrustShare, share: HorcrustShare) -> HorcrustShare {
-        (r + share).rem_euclid(self.q)
+        (r + share) % &self.p
     }
-    fn generate_refreshers(&self, shares: usize) -> Vec<HorcrustShare> {
-        assert!(shares > 1);
-        let mut rng = rand::thread_rng();
+
+    /// Evaluations of a random polynomial with constant term 0: adding them to the shares changes
+    /// the polynomial but not the secret.
+    fn generate_refreshers(
+        &self,
+        shares: usize,
+    ) -> std::result::Result<Vec<HorcrustShare>, SecretSharingError> {
+        self.evaluate_random_polynomial(shares, BigUint::zero())
+    }
+
+    fn limit(&self) -> Option<BigUint> {
+        Some(self.p.clone())
+    }
+
+    fn threshold(&self, _shares: usize) -> usize {
+        self.threshold
+    }
+
+    fn scheme(&self) -> SharingScheme {
+        SharingScheme {
+            threshold: self.threshold as u32,
+            modulus: self.p.to_bytes_be(),
+            verifiable: false,
+        }
+    }
+}
+
+/// Shares secrets of arbitrary length. The secret, prefixed by its length, is split in chunks
+/// that fit in the field of the inner scheme, and every chunk is shared on its own.
+/// A share is the concatenation of the shares of the chunks, each encoded as a big endian
+/// number on `share_width` bytes.
+pub struct BytesSecretSharing {
+    inner: Box<dyn SecretSharing>,
+    limit: BigUint,
+    share_limit: BigUint,
+    chunk_len: usize,
+    share_width: usize,
+}
+impl BytesSecretSharing {
+    pub fn new(inner: Box<dyn SecretSharing>) -> Result<Self> {
+        let Some(limit) = inner.limit() else {
+            bail!("Byte secrets need a scheme with a limit.");
+        };
+        // the biggest chunk that is always less than limit.
+        let chunk_len = ((limit.bits() - 1) / 8) as usize;
+        ensure!(chunk_len > 0, "The modulus is too small to store bytes.");
+        let share_limit = inner.share_limit().unwrap_or_else(|| limit.clone());
+        let share_width = (&share_limit - 1u32).bits().div_ceil(8) as usize;
+        Ok(Self {
+            inner,
+            limit,
+            share_limit,
+            chunk_len,
+            share_width,
+        })
+    }
+    pub fn from_scheme(scheme: &SharingScheme) -> Result<Self> {
+        Self::new(secret_sharing_from_scheme(scheme)?)
+    }
+    pub fn inner(&self) -> &dyn SecretSharing {
+        self.inner.as_ref()
+    }
+
+    /// Fails if the secret is longer than MAX_SECRET_LEN.
+    pub fn split(
+        &self,
+        shares: usize,
+        secret: &[u8],
+    ) -> std::result::Result<Vec<HorcrustShareBytes>, SecretSharingError> {
+        Ok(self.split_with_commitments(shares, secret)?.0)
+    }
+    /// Like `split`, also returns the commitments to the shares of every chunk if the scheme is
+    /// verifiable. Otherwise, the commitments are empty.
+    pub fn split_with_commitments(
+        &self,
+        shares: usize,
+        secret: &[u8],
+    ) -> std::result::Result<(Vec<HorcrustShareBytes>, HorcrustCommitmentsBytes), SecretSharingError>
+    {
+        if secret.len() > MAX_SECRET_LEN {
+            return Err(SecretSharingError::SecretTooLong {
+                len: secret.len(),
+                max: MAX_SECRET_LEN,
+            });
+        }
+        let mut plaintext = (secret.len() as u32).to_be_bytes().to_vec();
+        plaintext.extend_from_slice(secret);
+        // the last chunk is padded with zeros, combine drops them using the length.
+        plaintext.resize(plaintext.len().div_ceil(self.chunk_len) * self.chunk_len, 0);
+        let mut ret = vec![HorcrustShareBytes::new(); shares];
+        let mut commitments = HorcrustCommitmentsBytes::new();
+        for chunk in plaintext.chunks(self.chunk_len) {
+            let value = BigUint::from_bytes_be(chunk);
+            self.split_chunk(&mut ret, &mut commitments, value)?;
+        }
+        Ok((ret, commitments))
+    }
+    /// Splits a single chunk, appending its shares to `out` and its commitments to `commitments`.
+    fn split_chunk(
+        &self,
+        out: &mut [HorcrustShareBytes],
+        commitments: &mut HorcrustCommitmentsBytes,
+        value: BigUint,
+    ) -> std::result::Result<(), SecretSharingError> {
+        let chunk_shares = match self.inner.as_verifiable() {
+            Some(verifiable) => {
+                let (chunk_shares, chunk_commitments) =
+                    verifiable.split_verifiable(out.len(), value)?;
+                self.encode_commitments(commitments, &chunk_commitments);
+                chunk_shares
+            }
+            None => self.inner.split(out.len(), value)?,
+        };
+        for (share, chunk_share) in out.iter_mut().zip(chunk_shares) {
+            encode(share, &chunk_share, self.share_width);
+        }
+        Ok(())
+    }
+
+    /// `shares` is a list of (index, share), see SecretSharing::combine.
+    pub fn combine(&self, shares: Vec<(usize, HorcrustShareBytes)>) -> Result<HorcrustSecretBytes> {
+        let decoded = shares
+            .into_iter()
+            .map(|(index, share)| Ok((index, self.decode(&share)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let chunks = decoded.first().map(|(_, s)| s.len()).unwrap_or(0);
+        ensure!(
+            decoded.iter().all(|(_, s)| s.len() == chunks),
+            "Shares have different lengths."
+        );
+        let mut plaintext = vec![];
+        for chunk in 0..chunks {
+            let chunk_shares = decoded
+                .iter()
+                .map(|(i, s)| (*i, s[chunk].clone()))
+                .collect();
+            let value = self.inner.combine(chunk_shares)?;
+            ensure!(
+                value.bits() <= 8 * self.chunk_len as u64,
+                "Recovered secret is malformed."
+            );
+            encode(&mut plaintext, &value, self.chunk_len);
+        }
+        ensure!(plaintext.len() >= 4, "Shares are too short.");
+        let len = u32::from_be_bytes(plaintext[..4].try_into().unwrap()) as usize;
+        ensure!(plaintext.len() >= 4 + len, "Recovered secret is malformed.");
+        Ok(plaintext[4..4 + len].to_vec())
+    }
+
+    /// Checks every chunk of the share at `index` against its commitments.
+    /// Fails if the scheme is not verifiable.
+    pub fn verify(&self, index: usize, share: &[u8], commitments: &[u8]) -> Result<bool> {
+        let Some(verifiable) = self.inner.as_verifiable() else {
+            bail!("The sharing scheme is not verifiable.");
+        };
+        let share = self.decode(share)?;
+        let commitments = self.decode_commitments(commitments)?;
+        if share.len() != commitments.len() {
+            return Ok(false);
+        }
+        Ok(share
+            .iter()
+            .zip(commitments)
+            .all(|(chunk_share, chunk_commitments)| {
+                verifiable.verify(index, chunk_share, &chunk_commitments)
+            }))
+    }
+
+    /// Used by the server side: fails unless `share` is a share of this scheme, with its
+    /// `commitments` if the scheme is verifiable. Malformed shares couldn't be refreshed.
+    pub fn validate_share(&self, share: &[u8], commitments: &[u8]) -> Result<()> {
+        let chunks = self.decode(share)?.len();
+        // the length prefix of the secret takes at least a chunk.
+        let max_chunks = (4 + MAX_SECRET_LEN).div_ceil(self.chunk_len);
+        ensure!(
+            (1..=max_chunks).contains(&chunks),
+            "Share length {} is not valid.",
+            share.len()
+        );
+        match self.inner.as_verifiable() {
+            Some(_) => ensure!(
+                self.decode_commitments(commitments)?.len() == chunks,
+                "The commitments don't match the share."
+            ),
+            None => ensure!(
+                commitments.is_empty(),
+                "The sharing scheme is not verifiable, but commitments were sent."
+            ),
+        }
+        Ok(())
+    }
+
+    // Used by the server side to refresh a share.
+    pub fn refresh_share(&self, r: &[u8], share: &[u8]) -> Result<HorcrustShareBytes> {
+        let r = self.decode(r)?;
+        let share = self.decode(share)?;
+        ensure!(
+            r.len() == share.len(),
+            "Refresher and share have different lengths."
+        );
         let mut ret = vec![];
-        for _ in 0..shares - 1 {
-            let share = rng.gen_range(0..self.q);
-            ret.push(share as HorcrustShare);
+        for (r, share) in r.into_iter().zip(share) {
+            let refreshed = self.inner.refresh_share(r, share);
+            encode(&mut ret, &refreshed, self.share_width);
+        }
+        Ok(ret)
+    }
+    /// Used by the server side to keep the commitments in line with the refreshed share.
+    pub fn refresh_commitments(
+        &self,
+        commitments: &[u8],
+        updates: &[u8],
+    ) -> Result<HorcrustCommitmentsBytes> {
+        let Some(verifiable) = self.inner.as_verifiable() else {
+            bail!("The sharing scheme is not verifiable.");
+        };
+        let commitments = self.decode_commitments(commitments)?;
+        let updates = self.decode_commitments(updates)?;
+        ensure!(
+            commitments.len() == updates.len(),
+            "Commitments and updates have different lengths."
+        );
+        let mut ret = vec![];
+        for (commitments, updates) in commitments.iter().zip(updates) {
+            let refreshed = verifiable.refresh_commitments(commitments, &updates);
+            self.encode_commitments(&mut ret, &refreshed);
+        }
+        Ok(ret)
+    }
+    /// Generates the refreshers for shares that are `share_len` bytes long.
+    pub fn generate_refreshers(
+        &self,
+        shares: usize,
+        share_len: usize,
+    ) -> Result<Vec<HorcrustShareBytes>> {
+        Ok(self
+            .generate_refreshers_with_commitments(shares, share_len)?
+            .0)
+    }
+    /// Like `generate_refreshers`, also returns the updates to the commitments if the scheme
+    /// is verifiable. Otherwise, the updates are empty.
+    pub fn generate_refreshers_with_commitments(
+        &self,
+        shares: usize,
+        share_len: usize,
+    ) -> Result<(Vec<HorcrustShareBytes>, HorcrustCommitmentsBytes)> {
+        let mut ret = vec![HorcrustShareBytes::new(); shares];
+        let mut updates = HorcrustCommitmentsBytes::new();
+        for _ in 0..share_len / self.share_width {
+            let refreshers = match self.inner.as_verifiable() {
+                Some(verifiable) => {
+                    let (refreshers, chunk_updates) =
+                        verifiable.generate_verifiable_refreshers(shares)?;
+                    self.encode_commitments(&mut updates, &chunk_updates);
+                    refreshers
+                }
+                None => self.inner.generate_refreshers(shares)?,
+            };
+            for (refresher, r) in ret.iter_mut().zip(refreshers) {
+                encode(refresher, &r, self.share_width);
+            }
+        }
+        Ok((ret, updates))
+    }
+
+    /// Used by the holder of the share at `index`, one of the shares at `indices` taking part in
+    /// a reshare: splits its lagrange term with the `new` scheme.
+    /// The sub-share at position j goes to the new share holder j, and the sum of the sub-shares
+    /// it receives (see `combine_reshares`) is its share of the same secret.
+    pub fn reshare(
+        &self,
+        index: usize,
+        indices: &[usize],
+        share: &[u8],
+        new: &BytesSecretSharing,
+        shares: usize,
+    ) -> Result<(Vec<HorcrustShareBytes>, HorcrustCommitmentsBytes)> {
+        ensure!(
+            self.limit == new.limit,
+            "Resharing can't change the modulus."
+        );
+        ensure!(
+            indices.contains(&index),
+            "Share index {index} is not taking part in the reshare."
+        );
+        let mut ret = vec![HorcrustShareBytes::new(); shares];
+        let mut commitments = HorcrustCommitmentsBytes::new();
+        for chunk in self.decode(share)? {
+            let value = self.inner.lagrange_term(index, indices, &chunk)?;
+            new.split_chunk(&mut ret, &mut commitments, value)?;
         }
+        Ok((ret, commitments))
+    }
+    /// Used by the new share holders: sums up the sub-shares, and their commitments, received
+    /// from the old share holders.
+    pub fn combine_reshares(
+        &self,
+        sub_shares: Vec<(HorcrustShareBytes, HorcrustCommitmentsBytes)>,
+    ) -> Result<(HorcrustShareBytes, HorcrustCommitmentsBytes)> {
+        let mut sub_shares = sub_shares.into_iter();
+        let Some(first) = sub_shares.next() else {
+            bail!("No sub-shares to combine.");
+        };
+        sub_shares.try_fold(
+            first,
+            |(share, commitments), (sub_share, sub_commitments)| {
+                let share = self.refresh_share(&sub_share, &share)?;
+                let commitments = match self.inner.as_verifiable() {
+                    Some(_) => self.refresh_commitments(&commitments, &sub_commitments)?,
+                    None => commitments,
+                };
+                Ok((share, commitments))
+            },
+        )
+    }
 
-        let summed = -(ret.iter().sum::<u64>() as i64);
-        ret.push(summed.rem_euclid(self.q as i64) as u64);
-        ret
+    fn decode(&self, share: &[u8]) -> Result<Vec<HorcrustShare>> {
+        decode(share, self.share_width, &self.share_limit)
+    }
+    /// Commitments are encoded like shares, with the width of the commitments limit.
+    fn encode_commitments(&self, out: &mut HorcrustCommitmentsBytes, commitments: &[BigUint]) {
+        // only called for verifiable schemes.
+        let limit = self.inner.as_verifiable().unwrap().commitments_limit();
+        let width = (limit - 1u32).bits().div_ceil(8) as usize;
+        for commitment in commitments {
+            encode(out, commitment, width);
+        }
+    }
+    /// Returns the commitments of every chunk.
+    fn decode_commitments(&self, commitments: &[u8]) -> Result<Vec<Vec<HorcrustCommitment>>> {
+        let Some(verifiable) = self.inner.as_verifiable() else {
+            bail!("The sharing scheme is not verifiable.");
+        };
+        let limit = verifiable.commitments_limit();
+        let width = (&limit - 1u32).bits().div_ceil(8) as usize;
+        let decoded = decode(commitments, width, &limit)?;
+        let per_chunk = verifiable.commitments_len();
+        ensure!(
+            decoded.len().is_multiple_of(per_chunk),
+            "Commitments length {} is not valid.",
+            commitments.len()
+        );
+        Ok(decoded.chunks(per_chunk).map(|c| c.to_vec()).collect())
     }
+}
 
-    fn limit(&self) -> Option<u64> {
-        Some(self.q)
+/// Decodes a list of big endian numbers, each on `width` bytes and less than `limit`.
+fn decode(bytes: &[u8], width: usize, limit: &BigUint) -> Result<Vec<BigUint>> {
+    if !bytes.len().is_multiple_of(width) {
+        bail!("Share length {} is not valid.", bytes.len());
     }
+    bytes
+        .chunks(width)
+        .map(|chunk| {
+            let value = BigUint::from_bytes_be(chunk);
+            ensure!(&value < limit, "Share is out of the field.");
+            Ok(value)
+        })
+        .collect()
+}
+
+/// Appends `value` to `out` as a big endian number on `width` bytes.
+fn encode(out: &mut Vec<u8>, value: &BigUint, width: usize) {
+    // zero is encoded as [0] by to_bytes_be.
+    let bytes = if value.is_zero() {
+        vec![]
+    } else {
+        value.to_bytes_be()
+    };
+    out.resize(out.len() + width - bytes.len(), 0);
+    out.extend_from_slice(&bytes);
 }
 
 #[cfg(test)]
 mod test {
 
     use super::*;
+
+    fn indexed(shares: Vec<HorcrustShare>) -> Vec<(usize, HorcrustShare)> {
+        shares.into_iter().enumerate().collect()
+    }
+
     #[test]
     fn test_additive_secret_sharing() {
-        let secret_sharing = AdditiveSecretSharing::default();
-        let shares = secret_sharing.split(3, 10);
-        let combined_secret = secret_sharing.combine(shares);
-        assert_eq!(combined_secret, 10);
-        let shares = secret_sharing.split(3, 0);
-        let combined_secret = secret_sharing.combine(shares);
-        assert_eq!(combined_secret, 0);
-        let shares = secret_sharing.split(3, Q + 1);
-        let combined_secret = secret_sharing.combine(shares);
-        assert_eq!(combined_secret, 1);
-        let shares = secret_sharing.split(3, Q - 1);
-        let combined_secret = secret_sharing.combine(shares);
-        assert_eq!(combined_secret, Q - 1);
+        let q = BigUint::from(431u32);
+        let secret_sharing = AdditiveSecretSharing::new(q.clone());
+        let ten = BigUint::from(10u32);
+        let shares = secret_sharing.split(3, ten.clone()).unwrap();
+        let combined_secret = secret_sharing.combine(indexed(shares)).unwrap();
+        assert_eq!(combined_secret, ten);
+        let shares = secret_sharing.split(3, BigUint::zero()).unwrap();
+        let combined_secret = secret_sharing.combine(indexed(shares)).unwrap();
+        assert_eq!(combined_secret, BigUint::zero());
+        let shares = secret_sharing.split(3, &q - 1u32).unwrap();
+        let combined_secret = secret_sharing.combine(indexed(shares)).unwrap();
+        assert_eq!(combined_secret, &q - 1u32);
+        // secrets out of range are rejected.
+        let out_of_range = Err(SecretSharingError::SecretOutOfRange { limit: q.clone() });
+        assert_eq!(secret_sharing.split(3, q.clone()), out_of_range);
+        assert_eq!(secret_sharing.split(3, &q + 69u32), out_of_range);
+        // there must be at least a share.
+        assert_eq!(
+            secret_sharing.split(0, ten.clone()),
+            Err(SecretSharingError::NotEnoughShares { shares: 0, min: 1 })
+        );
 
         let shares_count = 3;
-        let mut shares: Vec<u64> = secret_sharing.split(shares_count, 10);
+        let shares: Vec<HorcrustShare> = secret_sharing.split(shares_count, ten.clone()).unwrap();
 
         // generate random refreshers:
-        let refreshes = secret_sharing.generate_refreshers(shares_count);
+        let refreshes = secret_sharing.generate_refreshers(shares_count).unwrap();
+        // a single share can't be refreshed.
+        assert_eq!(
+            secret_sharing.generate_refreshers(1),
+            Err(SecretSharingError::NotEnoughShares { shares: 1, min: 2 })
+        );
         // apply them to the shares
         let shares: Vec<HorcrustShare> = shares
             .into_iter()
@@ -98,7 +787,288 @@ mod test {
             .map(|(share, r)| secret_sharing.refresh_share(r, share))
             .collect();
         // try to recombine
-        let combined_secret = secret_sharing.combine(shares);
-        assert_eq!(combined_secret, 10);
+        let combined_secret = secret_sharing.combine(indexed(shares)).unwrap();
+        assert_eq!(combined_secret, ten);
+    }
+
+    #[test]
+    fn test_additive_secret_sharing_big_modulus() {
+        // 2^521 - 1
+        let q = (BigUint::one() << 521u32) - 1u32;
+        let secret_sharing = AdditiveSecretSharing::new(q.clone());
+        for secret in [BigUint::zero(), &q - 1u32, &q >> 1u32] {
+            let shares = secret_sharing.split(5, secret.clone()).unwrap();
+            assert!(shares.iter().all(|share| share < &q));
+            let refreshers = secret_sharing.generate_refreshers(5).unwrap();
+            let shares: Vec<HorcrustShare> = shares
+                .into_iter()
+                .zip(refreshers)
+                .map(|(share, r)| secret_sharing.refresh_share(r, share))
+                .collect();
+            assert_eq!(secret_sharing.combine(indexed(shares)).unwrap(), secret);
+        }
+    }
+
+    #[test]
+    fn test_shamir_secret_sharing() {
+        let p = default_modulus();
+        let secret_sharing = ShamirSecretSharing::new(3, p.clone());
+        let secret = BigUint::from(123456789u32);
+        let shares = indexed(secret_sharing.split(5, secret.clone()).unwrap());
+        assert_eq!(secret_sharing.combine(shares.clone()).unwrap(), secret);
+        // any 3 shares are enough
+        let subset = vec![shares[4].clone(), shares[0].clone(), shares[2].clone()];
+        assert_eq!(secret_sharing.combine(subset).unwrap(), secret);
+        let subset = vec![shares[1].clone(), shares[3].clone(), shares[4].clone()];
+        assert_eq!(secret_sharing.combine(subset).unwrap(), secret);
+        // 2 shares are not
+        let subset = vec![shares[1].clone(), shares[3].clone()];
+        assert_ne!(secret_sharing.combine(subset).unwrap(), secret);
+        // the same share twice is refused, the interpolation would divide by zero.
+        let duplicate = Err(SecretSharingError::DuplicateShareIndex { index: 1 });
+        let subset = vec![shares[1].clone(), shares[3].clone(), shares[1].clone()];
+        assert_eq!(secret_sharing.combine(subset), duplicate);
+        assert_eq!(
+            secret_sharing.lagrange_term(1, &[1, 3, 1], &shares[1].1),
+            duplicate
+        );
+
+        let shares = secret_sharing.split(5, &p - 1u32).unwrap();
+        assert_eq!(secret_sharing.combine(indexed(shares)).unwrap(), &p - 1u32);
+        let out_of_range = Err(SecretSharingError::SecretOutOfRange { limit: p.clone() });
+        assert_eq!(secret_sharing.split(5, p.clone()), out_of_range);
+        // composite moduli are refused, they would break the interpolation.
+        for composite in [BigUint::from(561u32), &p * 3u32, &p * &p] {
+            assert_eq!(
+                ShamirSecretSharing::try_new(3, composite.clone()).err(),
+                Some(SecretSharingError::ModulusNotPrime { modulus: composite })
+            );
+        }
+
+        // less shares than the threshold can't be generated.
+        let not_enough = Err(SecretSharingError::NotEnoughShares { shares: 2, min: 3 });
+        assert_eq!(secret_sharing.split(2, secret.clone()), not_enough);
+        assert_eq!(secret_sharing.generate_refreshers(2), not_enough);
+
+        // refreshed shares still combine to the same secret.
+        let shares = secret_sharing.split(5, secret.clone()).unwrap();
+        let refreshers = secret_sharing.generate_refreshers(5).unwrap();
+        let refreshed: Vec<HorcrustShare> = shares
+            .iter()
+            .zip(refreshers)
+            .map(|(share, r)| secret_sharing.refresh_share(r, share.clone()))
+            .collect();
+        assert_ne!(shares, refreshed);
+        let refreshed = indexed(refreshed);
+        let subset = vec![
+            refreshed[0].clone(),
+            refreshed[2].clone(),
+            refreshed[3].clone(),
+        ];
+        assert_eq!(secret_sharing.combine(subset).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_is_probable_prime() {
+        let primes = [2u32, 3, 37, 41, 257, 65537, 2147483647];
+        for p in primes {
+            assert!(is_probable_prime(&BigUint::from(p)), "{p} is prime");
+        }
+        // 561 and 41041 are Carmichael numbers, they fool the Fermat test.
+        for n in [0u32, 1, 4, 561, 41041, 65535, 2147483649] {
+            assert!(!is_probable_prime(&BigUint::from(n)), "{n} is not prime");
+        }
+        assert!(is_probable_prime(&default_modulus()));
+        assert!(is_probable_prime(&((BigUint::one() << 521u32) - 1u32)));
+        assert!(!is_probable_prime(&((BigUint::one() << 255u32) - 21u32)));
+    }
+
+    #[test]
+    fn test_bytes_secret_sharing() -> anyhow::Result<()> {
+        let modulus = default_modulus().to_bytes_be();
+        let schemes = [
+            SharingScheme {
+                threshold: 0,
+                modulus: modulus.clone(),
+                ..Default::default()
+            },
+            SharingScheme {
+                threshold: 2,
+                modulus: modulus.clone(),
+                ..Default::default()
+            },
+            // the smallest modulus that can hold a byte.
+            SharingScheme {
+                threshold: 0,
+                modulus: vec![1, 1],
+                ..Default::default()
+            },
+        ];
+        for scheme in schemes {
+            let secret_sharing = BytesSecretSharing::from_scheme(&scheme)?;
+            for secret in [&b""[..], b"a", b"hunter2", &[255; 4096]] {
+                let shares = secret_sharing.split(3, secret)?;
+                assert_eq!(
+                    secret_sharing.combine(indexed_bytes(shares.clone()))?,
+                    secret
+                );
+
+                let refreshers = secret_sharing.generate_refreshers(3, shares[0].len())?;
+                let shares = shares
+                    .iter()
+                    .zip(refreshers)
+                    .map(|(share, r)| secret_sharing.refresh_share(&r, share))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                assert_eq!(secret_sharing.combine(indexed_bytes(shares))?, secret);
+            }
+        }
+        let scheme = SharingScheme {
+            threshold: 2,
+            modulus,
+            ..Default::default()
+        };
+        let secret_sharing = BytesSecretSharing::from_scheme(&scheme)?;
+        let shares = indexed_bytes(secret_sharing.split(3, b"hunter2")?);
+        let subset = vec![shares[2].clone(), shares[0].clone()];
+        assert_eq!(secret_sharing.combine(subset)?, b"hunter2");
+        // malformed shares are rejected.
+        let mut subset = vec![shares[2].clone(), shares[0].clone()];
+        subset[0].1.pop();
+        assert!(secret_sharing.combine(subset).is_err());
+        let mut subset = vec![shares[2].clone(), shares[0].clone()];
+        subset[0].1.iter_mut().for_each(|b| *b = 255);
+        assert!(secret_sharing.combine(subset).is_err());
+        secret_sharing.validate_share(&shares[0].1, &[])?;
+        assert!(secret_sharing
+            .validate_share(&shares[0].1[1..], &[])
+            .is_err());
+        assert!(secret_sharing.validate_share(&[], &[]).is_err());
+        assert!(secret_sharing.validate_share(&[255; 32], &[]).is_err());
+        assert!(secret_sharing.validate_share(&shares[0].1, &[1]).is_err());
+        // threshold schemes need a prime modulus, additive ones don't.
+        let scheme = SharingScheme {
+            threshold: 2,
+            modulus: vec![1, 0],
+            ..Default::default()
+        };
+        assert!(BytesSecretSharing::from_scheme(&scheme).is_err());
+        let scheme = SharingScheme {
+            threshold: 0,
+            ..scheme
+        };
+        assert!(BytesSecretSharing::from_scheme(&scheme).is_ok());
+        // moduli too small to hold a byte are rejected.
+        let scheme = SharingScheme {
+            threshold: 0,
+            modulus: vec![255],
+            ..Default::default()
+        };
+        assert!(BytesSecretSharing::from_scheme(&scheme).is_err());
+        // secrets too long are rejected.
+        let secret = vec![0; MAX_SECRET_LEN + 1];
+        assert_eq!(
+            secret_sharing.split(3, &secret),
+            Err(SecretSharingError::SecretTooLong {
+                len: MAX_SECRET_LEN + 1,
+                max: MAX_SECRET_LEN
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_verifiable_secret_sharing() -> anyhow::Result<()> {
+        let secret_sharing = BytesSecretSharing::new(Box::new(PedersenSecretSharing::new(2)))?;
+        let scheme = secret_sharing.inner().scheme();
+        assert!(BytesSecretSharing::from_scheme(&scheme).is_ok());
+        let (shares, commitments) = secret_sharing.split_with_commitments(3, b"hunter2")?;
+        for (index, share) in shares.iter().enumerate() {
+            assert!(secret_sharing.verify(index, share, &commitments)?);
+        }
+        assert!(!secret_sharing.verify(1, &shares[0], &commitments)?);
+        secret_sharing.validate_share(&shares[0], &commitments)?;
+        assert!(secret_sharing.validate_share(&shares[0], &[]).is_err());
+
+        let (refreshers, updates) =
+            secret_sharing.generate_refreshers_with_commitments(3, shares[0].len())?;
+        let commitments = secret_sharing.refresh_commitments(&commitments, &updates)?;
+        let shares = shares
+            .iter()
+            .zip(refreshers)
+            .map(|(share, r)| secret_sharing.refresh_share(&r, share))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        for (index, share) in shares.iter().enumerate() {
+            assert!(secret_sharing.verify(index, share, &commitments)?);
+        }
+        let subset = vec![(2, shares[2].clone()), (1, shares[1].clone())];
+        assert_eq!(secret_sharing.combine(subset)?, b"hunter2");
+
+        // verifiable schemes must use the group order as modulus.
+        let scheme = SharingScheme {
+            modulus: default_modulus().to_bytes_be(),
+            ..scheme
+        };
+        assert!(BytesSecretSharing::from_scheme(&scheme).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_reshare() -> anyhow::Result<()> {
+        let modulus = default_modulus();
+        let reshares: [(Box<dyn SecretSharing>, Box<dyn SecretSharing>); 3] = [
+            (
+                Box::new(ShamirSecretSharing::new(2, modulus.clone())),
+                Box::new(ShamirSecretSharing::new(3, modulus.clone())),
+            ),
+            (
+                Box::new(AdditiveSecretSharing::new(modulus.clone())),
+                Box::new(ShamirSecretSharing::new(2, modulus.clone())),
+            ),
+            (
+                Box::new(PedersenSecretSharing::new(2)),
+                Box::new(PedersenSecretSharing::new(2)),
+            ),
+        ];
+        for (old, new) in reshares {
+            let old = BytesSecretSharing::new(old)?;
+            let new = BytesSecretSharing::new(new)?;
+            let shares = old.split(3, b"hunter2")?;
+            // only a qualified subset of the old shares takes part.
+            let indices: Vec<usize> = (3 - old.inner().threshold(3)..3).collect();
+            let mut received = vec![vec![]; 4];
+            for &index in indices.iter() {
+                let (sub_shares, commitments) =
+                    old.reshare(index, &indices, &shares[index], &new, 4)?;
+                for (j, sub_share) in sub_shares.into_iter().enumerate() {
+                    received[j].push((sub_share, commitments.clone()));
+                }
+            }
+            let mut new_shares = vec![];
+            for (j, sub_shares) in received.into_iter().enumerate() {
+                let (share, commitments) = new.combine_reshares(sub_shares)?;
+                if new.inner().as_verifiable().is_some() {
+                    assert!(new.verify(j, &share, &commitments)?);
+                }
+                new_shares.push((j, share));
+            }
+            assert_eq!(new.combine(new_shares.clone())?, b"hunter2");
+            let threshold = new.inner().threshold(4);
+            assert_eq!(
+                new.combine(new_shares[4 - threshold..].to_vec())?,
+                b"hunter2"
+            );
+        }
+        // the modulus can't change.
+        let old = BytesSecretSharing::new(Box::new(AdditiveSecretSharing::new(modulus)))?;
+        let new = BytesSecretSharing::new(Box::new(AdditiveSecretSharing::new(BigUint::from(
+            65537u32,
+        ))))?;
+        let shares = old.split(2, b"hunter2")?;
+        assert!(old.reshare(0, &[0, 1], &shares[0], &new, 2).is_err());
+        Ok(())
+    }
+
+    fn indexed_bytes(shares: Vec<HorcrustShareBytes>) -> Vec<(usize, HorcrustShareBytes)> {
+        shares.into_iter().enumerate().collect()
     }
 }
@@ -0,0 +1,73 @@
+use crate::Result;
+use anyhow::ensure;
+
+/// Maximum length in bytes of a HorcrustStoreKey.
+pub const MAX_KEY_LEN: usize = 256;
+/// Separates the levels of hierarchical keys, e.g. `prod/db/password`.
+pub const KEY_SEPARATOR: char = '/';
+
+/// Keys are non empty UTF-8 names of up to MAX_KEY_LEN bytes, without control characters. Every
+/// level of a hierarchical key must be non empty, so `prod//db` and `/prod` are refused.
+pub fn validate_key(key: &str) -> Result<()> {
+    ensure!(!key.is_empty(), "The key can't be empty.");
+    ensure!(
+        key.len() <= MAX_KEY_LEN,
+        "The key is {} bytes long, the maximum is {MAX_KEY_LEN}.",
+        key.len()
+    );
+    ensure!(
+        !key.chars().any(char::is_control),
+        "The key can't contain control characters."
+    );
+    ensure!(
+        key.split(KEY_SEPARATOR).all(|level| !level.is_empty()),
+        "The levels of the key '{key}' can't be empty."
+    );
+    Ok(())
+}
+
+/// True if the key is `prefix` or below it: `prod/db` matches `prod/db` and `prod/db/password`,
+/// but not `prod/dbx`. A trailing separator in the prefix is ignored, the empty prefix matches
+/// every key.
+pub fn key_has_prefix(key: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches(KEY_SEPARATOR);
+    if prefix.is_empty() {
+        return true;
+    }
+    match key.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with(KEY_SEPARATOR),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_key() {
+        assert!(validate_key("123").is_ok());
+        assert!(validate_key("prod/db/password").is_ok());
+        assert!(validate_key("clé secrète").is_ok());
+        assert!(validate_key(&"a".repeat(MAX_KEY_LEN)).is_ok());
+        assert!(validate_key(&"a".repeat(MAX_KEY_LEN + 1)).is_err());
+        // the limit is in bytes, not characters.
+        assert!(validate_key(&"é".repeat(MAX_KEY_LEN / 2 + 1)).is_err());
+        assert!(validate_key("").is_err());
+        assert!(validate_key("prod\ndb").is_err());
+        assert!(validate_key("/prod").is_err());
+        assert!(validate_key("prod//db").is_err());
+        assert!(validate_key("prod/").is_err());
+    }
+
+    #[test]
+    fn test_key_has_prefix() {
+        assert!(key_has_prefix("prod/db/password", "prod"));
+        assert!(key_has_prefix("prod/db/password", "prod/db/"));
+        assert!(key_has_prefix("prod/db", "prod/db"));
+        assert!(key_has_prefix("prod/db", ""));
+        assert!(!key_has_prefix("prod/dbx", "prod/db"));
+        assert!(!key_has_prefix("prod", "prod/db"));
+        assert!(!key_has_prefix("staging/db", "prod"));
+    }
+}
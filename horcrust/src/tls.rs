@@ -0,0 +1,334 @@
+use crate::framing::{decode_message, HANDSHAKE_TIMEOUT, IDLE_TIMEOUT};
+use crate::framing::{read_frame, read_frame_async, set_timeouts, write_frame, write_frame_async};
+use crate::{
+    AsyncConnectionHandler, ConnectionHandler, HorcrustMsgRequest, HorcrustMsgResponse, Result,
+};
+use anyhow::{anyhow, ensure, Context};
+use async_trait::async_trait;
+use prost::Message;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection};
+use rustls::{ConnectionCommon, StreamOwned};
+use sha2::{Digest, Sha256};
+use std::io::{BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+trait TlsStream: Read + Write + Send {}
+impl<C, S> TlsStream for StreamOwned<C, TcpStream>
+where
+    C: std::ops::DerefMut<Target = ConnectionCommon<S>> + Send,
+    S: rustls::SideData,
+{
+}
+
+/// A ConnectionHandler over TLS, for operators who prefer standard PKI to the custom handshake.
+/// Both sides present a certificate signed by the CA. Every message is sent in its own frame, so
+/// a connection can carry many of them.
+pub struct TlsConnectionHandler {
+    stream: Box<dyn TlsStream>,
+    peer_id: String,
+}
+impl TlsConnectionHandler {
+    /// Client side: fails if the server certificate is not valid for `server_name`.
+    pub fn connect(
+        socket: TcpStream,
+        config: Arc<ClientConfig>,
+        server_name: &str,
+    ) -> Result<Self> {
+        set_timeouts(&socket, HANDSHAKE_TIMEOUT)?;
+        let server_name = ServerName::try_from(server_name.to_string())?;
+        let mut stream = StreamOwned::new(ClientConnection::new(config, server_name)?, socket);
+        // completes the handshake now, so certificate errors are reported here.
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        set_timeouts(&stream.sock, IDLE_TIMEOUT)?;
+        let peer_id = peer_certificate_id(stream.conn.peer_certificates())?;
+        Ok(Self {
+            stream: Box::new(stream),
+            peer_id,
+        })
+    }
+    /// Server side: fails if the client has no certificate signed by the CA.
+    pub fn accept(socket: TcpStream, config: Arc<ServerConfig>) -> Result<Self> {
+        set_timeouts(&socket, HANDSHAKE_TIMEOUT)?;
+        let mut stream = StreamOwned::new(ServerConnection::new(config)?, socket);
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        set_timeouts(&stream.sock, IDLE_TIMEOUT)?;
+        let peer_id = peer_certificate_id(stream.conn.peer_certificates())?;
+        Ok(Self {
+            stream: Box::new(stream),
+            peer_id,
+        })
+    }
+    fn send_message<M: Message>(&mut self, message: M) -> Result<()> {
+        write_frame(&mut self.stream, &message.encode_to_vec())
+    }
+    fn receive_message<M: Message + Default>(&mut self) -> Result<M> {
+        decode_message(&read_frame(&mut self.stream)?)
+    }
+}
+
+impl ConnectionHandler<HorcrustMsgRequest, HorcrustMsgResponse> for TlsConnectionHandler {
+    fn send(&mut self, message: HorcrustMsgRequest) -> Result<()> {
+        self.send_message(message)
+    }
+    fn receive(&mut self) -> Result<HorcrustMsgResponse> {
+        self.receive_message()
+    }
+    fn peer_id(&self) -> String {
+        self.peer_id.clone()
+    }
+}
+impl ConnectionHandler<HorcrustMsgResponse, HorcrustMsgRequest> for TlsConnectionHandler {
+    fn send(&mut self, message: HorcrustMsgResponse) -> Result<()> {
+        self.send_message(message)
+    }
+    fn receive(&mut self) -> Result<HorcrustMsgRequest> {
+        self.receive_message()
+    }
+    fn peer_id(&self) -> String {
+        self.peer_id.clone()
+    }
+}
+
+/// The async version of the server side of TlsConnectionHandler.
+pub struct AsyncTlsConnectionHandler {
+    stream: tokio_rustls::server::TlsStream<tokio::net::TcpStream>,
+    peer_id: String,
+}
+impl AsyncTlsConnectionHandler {
+    pub async fn accept(socket: tokio::net::TcpStream, config: Arc<ServerConfig>) -> Result<Self> {
+        let accept = tokio_rustls::TlsAcceptor::from(config).accept(socket);
+        let stream = tokio::time::timeout(HANDSHAKE_TIMEOUT, accept)
+            .await
+            .map_err(|_| anyhow!("The TLS handshake timed out."))??;
+        // the verifier already refused the clients without a valid certificate.
+        let peer_id = peer_certificate_id(stream.get_ref().1.peer_certificates())?;
+        Ok(Self { stream, peer_id })
+    }
+}
+
+/// The peer id of the peer, from the certificate it presented during the handshake.
+fn peer_certificate_id(certificates: Option<&[CertificateDer<'_>]>) -> Result<String> {
+    certificates
+        .and_then(|certs| certs.first())
+        .map(certificate_id)
+        .ok_or_else(|| anyhow!("The peer didn't present a certificate."))
+}
+
+#[async_trait]
+impl AsyncConnectionHandler<HorcrustMsgResponse, HorcrustMsgRequest> for AsyncTlsConnectionHandler {
+    async fn send(&mut self, message: HorcrustMsgResponse) -> Result<()> {
+        write_frame_async(&mut self.stream, &message.encode_to_vec()).await
+    }
+    async fn receive(&mut self) -> Result<HorcrustMsgRequest> {
+        decode_message(&read_frame_async(&mut self.stream).await?)
+    }
+    fn peer_id(&self) -> String {
+        self.peer_id.clone()
+    }
+}
+
+/// Server side configuration, from PEM files with the certificate chain and its private key. Only
+/// the clients with a certificate signed by the CA certificates at `ca_path` are accepted.
+pub fn tls_server_config(
+    cert_path: &Path,
+    key_path: &Path,
+    ca_path: &Path,
+) -> Result<Arc<ServerConfig>> {
+    let provider = provider();
+    let verifier = WebPkiClientVerifier::builder_with_provider(
+        Arc::new(load_roots(ca_path)?),
+        provider.clone(),
+    )
+    .build()?;
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(load_certs(cert_path)?, load_key(key_path)?)?;
+    Ok(Arc::new(config))
+}
+
+/// Client side configuration, trusting the CA certificates in the PEM bundle at `ca_path`, and
+/// authenticating with the certificate chain at `cert_path` and its private key.
+pub fn tls_client_config(
+    ca_path: &Path,
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<Arc<ClientConfig>> {
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(load_roots(ca_path)?)
+        .with_client_auth_cert(load_certs(cert_path)?, load_key(key_path)?)?;
+    Ok(Arc::new(config))
+}
+
+/// The peer id of the certificate chain at `cert_path`, the one the servers see when it connects.
+pub fn tls_certificate_id(cert_path: &Path) -> Result<String> {
+    // safe index: load_certs fails without certificates.
+    Ok(certificate_id(&load_certs(cert_path)?[0]))
+}
+
+/// The hex encoded SHA-256 fingerprint of the certificate.
+fn certificate_id(cert: &CertificateDer) -> String {
+    hex::encode(Sha256::digest(cert.as_ref()))
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn load_roots(ca_path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca_path)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
+
+fn load_key(key_path: &Path) -> Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut BufReader::new(
+        std::fs::File::open(key_path).context(format!("failed to open {key_path:?}"))?,
+    ))?
+    .ok_or_else(|| anyhow!("No private key found in {key_path:?}"))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let file = std::fs::File::open(path).context(format!("failed to open {path:?}"))?;
+    let certs =
+        rustls_pemfile::certs(&mut BufReader::new(file)).collect::<std::io::Result<Vec<_>>>()?;
+    ensure!(!certs.is_empty(), "No certificates found in {path:?}");
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{msg_retrieve_secret_request, msg_success_response};
+
+    /// Writes a self-signed certificate for localhost and 127.0.0.1, returns the paths of the
+    /// certificate and of its key. The certificate is its own CA, and both the server and the
+    /// client use it.
+    fn self_signed_cert(dir: &Path) -> (std::path::PathBuf, std::path::PathBuf) {
+        let names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+        let certified = rcgen::generate_simple_self_signed(names).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+        (cert_path, key_path)
+    }
+
+    #[test]
+    fn test_tls_channel() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("horcrust-tls-{}", rand::random::<u64>()));
+        std::fs::create_dir(&dir)?;
+        let (cert_path, key_path) = self_signed_cert(&dir);
+        let server_config = tls_server_config(&cert_path, &key_path, &cert_path)?;
+        let client_config = tls_client_config(&cert_path, &cert_path, &key_path)?;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let server_thread = std::thread::spawn(move || -> Result<Vec<HorcrustMsgRequest>> {
+            let mut requests = vec![];
+            for _ in 0..2 {
+                let (socket, _) = listener.accept()?;
+                let mut handler = TlsConnectionHandler::accept(socket, server_config.clone())?;
+                requests.push(handler.receive()?);
+                handler.send(msg_success_response())?;
+            }
+            Ok(requests)
+        });
+        // the certificate is valid both for the name and for the address.
+        for name in ["localhost", "127.0.0.1"] {
+            let socket = TcpStream::connect(("127.0.0.1", port))?;
+            let mut handler = TlsConnectionHandler::connect(socket, client_config.clone(), name)?;
+            handler.send(msg_retrieve_secret_request("1234".into()))?;
+            let response: HorcrustMsgResponse = handler.receive()?;
+            assert_eq!(response, msg_success_response());
+        }
+        assert_eq!(
+            server_thread.join().unwrap()?,
+            vec![msg_retrieve_secret_request("1234".into()); 2]
+        );
+
+        // a server whose certificate is not signed by a trusted CA is refused.
+        let other_dir = dir.join("other");
+        std::fs::create_dir(&other_dir)?;
+        let (other_cert, other_key) = self_signed_cert(&other_dir);
+        let untrusting_config = tls_client_config(&other_cert, &other_cert, &other_key)?;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let server_config = tls_server_config(&cert_path, &key_path, &cert_path)?;
+        let server_thread = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            TlsConnectionHandler::accept(socket, server_config).is_err()
+        });
+        let socket = TcpStream::connect(("127.0.0.1", port))?;
+        assert!(TlsConnectionHandler::connect(socket, untrusting_config, "localhost").is_err());
+        assert!(server_thread.join().unwrap());
+
+        // and so is a client without a certificate.
+        let no_cert_config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(load_roots(&cert_path)?)
+            .with_no_client_auth();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let server_config = tls_server_config(&cert_path, &key_path, &cert_path)?;
+        let server_thread = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            TlsConnectionHandler::accept(socket, server_config).is_err()
+        });
+        let socket = TcpStream::connect(("127.0.0.1", port))?;
+        // with TLS 1.3 the client finishes its handshake first, and learns of the refusal later.
+        let refused = TlsConnectionHandler::connect(socket, Arc::new(no_cert_config), "localhost")
+            .and_then(|mut handler| {
+                handler.send(msg_retrieve_secret_request("1234".into()))?;
+                let response: HorcrustMsgResponse = handler.receive()?;
+                Ok(response)
+            });
+        assert!(refused.is_err());
+        assert!(server_thread.join().unwrap());
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_async_tls_server() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("horcrust-tls-{}", rand::random::<u64>()));
+        std::fs::create_dir(&dir)?;
+        let (cert_path, key_path) = self_signed_cert(&dir);
+        let server_config = tls_server_config(&cert_path, &key_path, &cert_path)?;
+        let client_config = tls_client_config(&cert_path, &cert_path, &key_path)?;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await?;
+            let mut handler = AsyncTlsConnectionHandler::accept(socket, server_config).await?;
+            let request = handler.receive().await?;
+            handler.send(msg_success_response()).await?;
+            anyhow::Ok((request, handler.peer_id()))
+        });
+        let client = tokio::task::spawn_blocking(move || -> Result<HorcrustMsgResponse> {
+            let socket = TcpStream::connect(("127.0.0.1", port))?;
+            let mut handler = TlsConnectionHandler::connect(socket, client_config, "localhost")?;
+            handler.send(msg_retrieve_secret_request("1234".into()))?;
+            handler.receive()
+        });
+        assert_eq!(client.await??, msg_success_response());
+        // the server identifies the client by the fingerprint of its certificate.
+        let (request, peer_id) = server.await??;
+        assert_eq!(request, msg_retrieve_secret_request("1234".into()));
+        assert_eq!(peer_id, tls_certificate_id(&cert_path)?);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
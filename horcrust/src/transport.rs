@@ -0,0 +1,153 @@
+use crate::tls::{AsyncTlsConnectionHandler, TlsConnectionHandler};
+use crate::{
+    AsyncConnectionHandler, AsyncTcpConnectionHandler, ConnectionHandler, HandshakeConfig,
+    HorcrustMsgRequest, HorcrustMsgResponse, Result, TcpConnectionHandler, IDLE_TIMEOUT,
+};
+use anyhow::anyhow;
+use rustls::{ClientConfig, ServerConfig};
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// A connection to a server: sends requests, receives responses.
+pub type OutgoingConnection =
+    Box<dyn ConnectionHandler<HorcrustMsgRequest, HorcrustMsgResponse> + Send>;
+/// A connection from a client or another server: receives requests, sends responses.
+pub type IncomingConnection =
+    Box<dyn ConnectionHandler<HorcrustMsgResponse, HorcrustMsgRequest> + Send>;
+
+/// IncomingConnection for async servers.
+pub type AsyncIncomingConnection =
+    Box<dyn AsyncConnectionHandler<HorcrustMsgResponse, HorcrustMsgRequest> + Send>;
+
+/// How connections are secured: with the handshake authenticated by the peers' identities, or
+/// with TLS.
+#[derive(Clone)]
+pub enum Transport {
+    Handshake(HandshakeConfig),
+    Tls(TlsConfig),
+}
+
+/// The configurations for the client and server side of TLS connections. Clients only need the
+/// client side, servers need both as they also connect to each other.
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    pub client: Option<Arc<ClientConfig>>,
+    pub server: Option<Arc<ServerConfig>>,
+}
+
+impl Transport {
+    /// Connects to `server`, an address in the host:port form.
+    pub fn connect(&self, server: &str) -> Result<OutgoingConnection> {
+        let socket = TcpStream::connect(server)?;
+        Ok(match self {
+            Transport::Handshake(config) => Box::new(TcpConnectionHandler::new(socket, config)?),
+            Transport::Tls(config) => {
+                let client = config
+                    .client
+                    .clone()
+                    .ok_or_else(|| anyhow!("Missing the TLS CA bundle."))?;
+                Box::new(TlsConnectionHandler::connect(
+                    socket,
+                    client,
+                    server_host(server),
+                )?)
+            }
+        })
+    }
+    pub fn accept(&self, socket: TcpStream) -> Result<IncomingConnection> {
+        Ok(match self {
+            Transport::Handshake(config) => Box::new(TcpConnectionHandler::new(socket, config)?),
+            Transport::Tls(config) => {
+                let server = config
+                    .server
+                    .clone()
+                    .ok_or_else(|| anyhow!("Missing the TLS certificate."))?;
+                Box::new(TlsConnectionHandler::accept(socket, server)?)
+            }
+        })
+    }
+    pub async fn accept_async(
+        &self,
+        socket: tokio::net::TcpStream,
+    ) -> Result<AsyncIncomingConnection> {
+        Ok(match self {
+            Transport::Handshake(config) => {
+                Box::new(AsyncTcpConnectionHandler::new(socket, config).await?)
+            }
+            Transport::Tls(config) => {
+                let server = config
+                    .server
+                    .clone()
+                    .ok_or_else(|| anyhow!("Missing the TLS certificate."))?;
+                Box::new(AsyncTlsConnectionHandler::accept(socket, server).await?)
+            }
+        })
+    }
+}
+
+/// Keeps one connection per server, so many requests share the same session.
+pub struct ConnectionPool {
+    transport: Transport,
+    connections: HashMap<String, (OutgoingConnection, Instant)>,
+}
+impl ConnectionPool {
+    pub fn new(transport: Transport) -> Self {
+        Self {
+            transport,
+            connections: HashMap::new(),
+        }
+    }
+    /// Sends the request to `server` and waits for its response. Connections that failed, or
+    /// that were idle long enough for the server to close them, are replaced by new ones.
+    pub fn request(
+        &mut self,
+        server: &str,
+        request: HorcrustMsgRequest,
+    ) -> Result<HorcrustMsgResponse> {
+        let (mut connection, _) = self.connection(server)?;
+        connection.send(request)?;
+        let response = connection.receive()?;
+        self.connections
+            .insert(server.to_string(), (connection, Instant::now()));
+        Ok(response)
+    }
+    /// The peer id `server` authenticated with, connecting to it if needed.
+    pub fn peer_id(&mut self, server: &str) -> Result<String> {
+        let (connection, last_used) = self.connection(server)?;
+        let peer_id = connection.peer_id();
+        self.connections
+            .insert(server.to_string(), (connection, last_used));
+        Ok(peer_id)
+    }
+    /// The connection to `server`, and when it was last used. Connections idle long enough for
+    /// the server to close them are replaced by new ones.
+    fn connection(&mut self, server: &str) -> Result<(OutgoingConnection, Instant)> {
+        Ok(match self.connections.remove(server) {
+            Some((connection, last_used)) if last_used.elapsed() < IDLE_TIMEOUT / 2 => {
+                (connection, last_used)
+            }
+            _ => (self.transport.connect(server)?, Instant::now()),
+        })
+    }
+}
+
+/// The host part of a host:port address, the name the certificate is checked against.
+fn server_host(server: &str) -> &str {
+    let host = server.rsplit_once(':').map_or(server, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_host() {
+        assert_eq!(server_host("server1:8080"), "server1");
+        assert_eq!(server_host("127.0.0.1:9091"), "127.0.0.1");
+        assert_eq!(server_host("[::1]:9091"), "::1");
+        assert_eq!(server_host("localhost"), "localhost");
+    }
+}
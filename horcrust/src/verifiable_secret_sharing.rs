@@ -0,0 +1,313 @@
+use crate::secret_sharing::{SecretSharing, SecretSharingError, ShamirSecretSharing};
+use crate::{HorcrustCommitment, HorcrustSecret, HorcrustShare, SharingScheme};
+use num_bigint::{BigUint, RandBigInt};
+use num_traits::One;
+use sha2::{Digest, Sha256};
+
+/// The 2048-bit MODP group prime from RFC 3526. It's a safe prime: p = 2q + 1 with q prime.
+const MODP_2048_PRIME: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E3404DD\
+    EF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF0598DA48361C55D39A69163FA8FD24CF5F\
+    83655D23DCA3AD961C62F356208552BB9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF6955817183995497CEA956AE515D2261898FA0510\
+    15728E5A8AACAA68FFFFFFFFFFFFFFFF";
+/// 2 is a quadratic residue modulo the MODP primes, so it generates the subgroup of order q.
+const GENERATOR: u32 = 2;
+
+/// A secret sharing scheme whose shares can be checked against public commitments, so that
+/// a corrupted or malicious server can't make the client recover a wrong secret.
+pub trait VerifiableSecretSharing: SecretSharing {
+    /// Like `split`, also returns the commitments to publish together with the shares.
+    fn split_verifiable(
+        &self,
+        shares: usize,
+        secret: HorcrustSecret,
+    ) -> std::result::Result<(Vec<HorcrustShare>, Vec<HorcrustCommitment>), SecretSharingError>;
+    /// Checks the share at `index` (see SecretSharing::combine) against the commitments.
+    fn verify(
+        &self,
+        index: usize,
+        share: &HorcrustShare,
+        commitments: &[HorcrustCommitment],
+    ) -> bool;
+    /// Like `generate_refreshers`, also returns the updates to apply to the commitments.
+    fn generate_verifiable_refreshers(
+        &self,
+        shares: usize,
+    ) -> std::result::Result<(Vec<HorcrustShare>, Vec<HorcrustCommitment>), SecretSharingError>;
+    /// Applies the updates returned by `generate_verifiable_refreshers` to the commitments.
+    fn refresh_commitments(
+        &self,
+        commitments: &[HorcrustCommitment],
+        updates: &[HorcrustCommitment],
+    ) -> Vec<HorcrustCommitment>;
+    /// Amount of commitments published for every secret.
+    fn commitments_len(&self) -> usize;
+    /// Commitments are numbers less than this limit.
+    fn commitments_limit(&self) -> BigUint;
+}
+
+/// Hashed to get the second generator h, so that nobody knows its discrete logarithm in base g.
+const SECOND_GENERATOR_LABEL: &[u8] = b"horcrust pedersen second generator";
+
+/// Pedersen's verifiable secret sharing: Shamir's secret sharing over Z_q of the secret with a
+/// polynomial f, and of a random blinding value with a polynomial r. The dealer publishes
+/// C_j = g^a_j * h^b_j mod p for every coefficient a_j of f and b_j of r. The share (y, t) =
+/// (f(x), r(x)) is valid iff g^y * h^t = prod_j C_j^(x^j) mod p.
+/// Unlike Feldman's g^a_j, the commitments don't reveal anything about the secret: C_0 doesn't
+/// allow to brute-force low-entropy secrets.
+/// It uses the RFC 3526 2048-bit group, so the modulus of the shares is fixed to its order q.
+/// A share is encoded as the single number y + t * q, less than q^2.
+pub struct PedersenSecretSharing {
+    shamir: ShamirSecretSharing,
+    p: BigUint,
+    q: BigUint,
+    g: BigUint,
+    h: BigUint,
+}
+impl PedersenSecretSharing {
+    pub fn new(threshold: usize) -> Self {
+        let q = Self::group_order();
+        let p = (&q << 1u32) + 1u32;
+        Self {
+            shamir: ShamirSecretSharing::new(threshold, q.clone()),
+            h: second_generator(&p),
+            p,
+            q,
+            g: BigUint::from(GENERATOR),
+        }
+    }
+    /// The order q of the group, used as modulus for the shares.
+    pub fn group_order() -> BigUint {
+        // safe unwrap: the constant is a valid hex number.
+        let p = BigUint::parse_bytes(MODP_2048_PRIME.as_bytes(), 16).unwrap();
+        p >> 1u32
+    }
+    /// g^a_j * h^b_j for every pair of coefficients.
+    fn commit(&self, coefficients: &[BigUint], blinding: &[BigUint]) -> Vec<HorcrustCommitment> {
+        coefficients
+            .iter()
+            .zip(blinding)
+            .map(|(a, b)| self.g.modpow(a, &self.p) * self.h.modpow(b, &self.p) % &self.p)
+            .collect()
+    }
+    /// Evaluates the polynomials of the secret and of the blinding values, and encodes the shares.
+    fn evaluate(
+        &self,
+        coefficients: &[BigUint],
+        blinding: &[BigUint],
+        shares: usize,
+    ) -> std::result::Result<Vec<HorcrustShare>, SecretSharingError> {
+        let values = self.shamir.evaluate(coefficients, shares)?;
+        let blinding = self.shamir.evaluate(blinding, shares)?;
+        Ok(values
+            .into_iter()
+            .zip(blinding)
+            .map(|(y, t)| self.encode(&y, &t))
+            .collect())
+    }
+    fn encode(&self, y: &BigUint, t: &BigUint) -> HorcrustShare {
+        y + t * &self.q
+    }
+    /// The share of the secret and of the blinding value.
+    fn decode(&self, share: &HorcrustShare) -> (BigUint, BigUint) {
+        (share % &self.q, share / &self.q % &self.q)
+    }
+    fn random_blinding(&self) -> Vec<BigUint> {
+        let blinding = rand::thread_rng().gen_biguint_below(&self.q);
+        self.shamir.random_polynomial(blinding)
+    }
+}
+
+/// A generator of the subgroup of order q whose discrete logarithm in base g is unknown: the
+/// label is expanded with SHA-256 to more bits than p, reduced modulo p and squared.
+fn second_generator(p: &BigUint) -> BigUint {
+    let bytes: Vec<u8> = (0u8..9)
+        .flat_map(|counter| {
+            Sha256::new()
+                .chain_update(SECOND_GENERATOR_LABEL)
+                .chain_update([counter])
+                .finalize()
+        })
+        .collect();
+    (BigUint::from_bytes_be(&bytes) % p).modpow(&BigUint::from(2u32), p)
+}
+
+impl SecretSharing for PedersenSecretSharing {
+    fn split(
+        &self,
+        shares: usize,
+        secret: HorcrustSecret,
+    ) -> std::result::Result<Vec<HorcrustShare>, SecretSharingError> {
+        Ok(self.split_verifiable(shares, secret)?.0)
+    }
+    fn combine(
+        &self,
+        shares: Vec<(usize, HorcrustShare)>,
+    ) -> std::result::Result<HorcrustSecret, SecretSharingError> {
+        let shares = shares
+            .into_iter()
+            .map(|(index, share)| (index, self.decode(&share).0))
+            .collect();
+        self.shamir.combine(shares)
+    }
+    /// Both the share of the secret and the one of the blinding value are refreshed.
+    fn refresh_share(&self, r: HorcrustShare, share: HorcrustShare) -> HorcrustShare {
+        let (r_y, r_t) = self.decode(&r);
+        let (y, t) = self.decode(&share);
+        self.encode(
+            &self.shamir.refresh_share(r_y, y),
+            &self.shamir.refresh_share(r_t, t),
+        )
+    }
+    fn generate_refreshers(
+        &self,
+        shares: usize,
+    ) -> std::result::Result<Vec<HorcrustShare>, SecretSharingError> {
+        Ok(self.generate_verifiable_refreshers(shares)?.0)
+    }
+    fn lagrange_term(
+        &self,
+        index: usize,
+        indices: &[usize],
+        share: &HorcrustShare,
+    ) -> std::result::Result<HorcrustSecret, SecretSharingError> {
+        self.shamir
+            .lagrange_term(index, indices, &self.decode(share).0)
+    }
+    fn limit(&self) -> Option<BigUint> {
+        self.shamir.limit()
+    }
+    fn share_limit(&self) -> Option<BigUint> {
+        Some(&self.q * &self.q)
+    }
+    fn threshold(&self, shares: usize) -> usize {
+        self.shamir.threshold(shares)
+    }
+    fn scheme(&self) -> SharingScheme {
+        SharingScheme {
+            verifiable: true,
+            ..self.shamir.scheme()
+        }
+    }
+    fn as_verifiable(&self) -> Option<&dyn VerifiableSecretSharing> {
+        Some(self)
+    }
+}
+impl VerifiableSecretSharing for PedersenSecretSharing {
+    fn split_verifiable(
+        &self,
+        shares: usize,
+        secret: HorcrustSecret,
+    ) -> std::result::Result<(Vec<HorcrustShare>, Vec<HorcrustCommitment>), SecretSharingError>
+    {
+        if secret >= self.q {
+            return Err(SecretSharingError::SecretOutOfRange {
+                limit: self.q.clone(),
+            });
+        }
+        let coefficients = self.shamir.random_polynomial(secret);
+        let blinding = self.random_blinding();
+        Ok((
+            self.evaluate(&coefficients, &blinding, shares)?,
+            self.commit(&coefficients, &blinding),
+        ))
+    }
+
+    fn verify(
+        &self,
+        index: usize,
+        share: &HorcrustShare,
+        commitments: &[HorcrustCommitment],
+    ) -> bool {
+        if commitments.len() != self.commitments_len() || share >= &(&self.q * &self.q) {
+            return false;
+        }
+        let (y, t) = self.decode(share);
+        let x = BigUint::from(index + 1);
+        let mut x_j = BigUint::one();
+        let mut expected = BigUint::one();
+        for commitment in commitments {
+            expected = expected * commitment.modpow(&x_j, &self.p) % &self.p;
+            x_j = x_j * &x % &self.q;
+        }
+        self.g.modpow(&y, &self.p) * self.h.modpow(&t, &self.p) % &self.p == expected
+    }
+
+    /// The refreshers are the evaluations of a random polynomial d with d(0) = 0, and of a random
+    /// polynomial e for the blinding values. The updates are the commitments to their
+    /// coefficients: C_j * g^d_j * h^e_j = g^(a_j + d_j) * h^(b_j + e_j).
+    fn generate_verifiable_refreshers(
+        &self,
+        shares: usize,
+    ) -> std::result::Result<(Vec<HorcrustShare>, Vec<HorcrustCommitment>), SecretSharingError>
+    {
+        let coefficients = self.shamir.random_polynomial(BigUint::from(0u32));
+        let blinding = self.random_blinding();
+        Ok((
+            self.evaluate(&coefficients, &blinding, shares)?,
+            self.commit(&coefficients, &blinding),
+        ))
+    }
+
+    fn refresh_commitments(
+        &self,
+        commitments: &[HorcrustCommitment],
+        updates: &[HorcrustCommitment],
+    ) -> Vec<HorcrustCommitment> {
+        commitments
+            .iter()
+            .zip(updates)
+            .map(|(c, u)| c * u % &self.p)
+            .collect()
+    }
+
+    fn commitments_len(&self) -> usize {
+        self.shamir.threshold(0)
+    }
+
+    fn commitments_limit(&self) -> BigUint {
+        self.p.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pedersen_secret_sharing() {
+        let secret_sharing = PedersenSecretSharing::new(2);
+        let secret = BigUint::from(1234u32);
+        let (shares, commitments) = secret_sharing.split_verifiable(3, secret.clone()).unwrap();
+        assert_eq!(commitments.len(), 2);
+        for (index, share) in shares.iter().enumerate() {
+            assert!(secret_sharing.verify(index, share, &commitments));
+        }
+        // a tampered share, or a share sent for the wrong index, is detected.
+        let bad_share = &shares[1] + 1u32;
+        assert!(!secret_sharing.verify(1, &bad_share, &commitments));
+        assert!(!secret_sharing.verify(0, &shares[1], &commitments));
+        // the commitments are hiding: sharing the same secret again publishes different ones.
+        let (_, other_commitments) = secret_sharing.split_verifiable(3, secret.clone()).unwrap();
+        assert_ne!(commitments[0], other_commitments[0]);
+        assert_ne!(
+            commitments[0],
+            BigUint::from(GENERATOR).modpow(&secret, &secret_sharing.p)
+        );
+
+        let (refreshers, updates) = secret_sharing.generate_verifiable_refreshers(3).unwrap();
+        let commitments = secret_sharing.refresh_commitments(&commitments, &updates);
+        let shares: Vec<HorcrustShare> = shares
+            .into_iter()
+            .zip(refreshers)
+            .map(|(share, r)| secret_sharing.refresh_share(r, share))
+            .collect();
+        for (index, share) in shares.iter().enumerate() {
+            assert!(secret_sharing.verify(index, share, &commitments));
+        }
+        let subset = vec![(2, shares[2].clone()), (0, shares[0].clone())];
+        assert_eq!(secret_sharing.combine(subset).unwrap(), secret);
+    }
+}
@@ -2,7 +2,7 @@
 fn test_client_cli() {
     // TODO:
     // spawn up server, then:
-    // should fail if client tries to store a secret bigger than Q.
+    // should fail if client tries to store a secret bigger than MAX_SECRET_LEN.
     // should fail if client tries to retrieve a key that is was not stored yet.
     // eventually shares should change as they are refreshed.
     // should fail if server list is less than 2.